@@ -0,0 +1,74 @@
+//! Price oracle with manually set prices, implements `get_price_data`
+//! of the NEAR price oracle for tests and testnet deployments.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, PanicOnDefault};
+use std::collections::HashMap;
+
+pub type AssetId = String;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Price {
+    pub multiplier: U128,
+    pub decimals: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AssetOptionalPrice {
+    pub asset_id: AssetId,
+    pub price: Option<Price>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceData {
+    pub timestamp: U64,
+    pub recency_duration_sec: u32,
+    pub prices: Vec<AssetOptionalPrice>,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct MockOracle {
+    pub owner_id: AccountId,
+    pub prices: HashMap<AssetId, Price>,
+}
+
+#[near_bindgen]
+impl MockOracle {
+    #[init]
+    pub fn new(owner_id: AccountId) -> Self {
+        Self {
+            owner_id,
+            prices: HashMap::new(),
+        }
+    }
+
+    pub fn set_price(&mut self, asset_id: AssetId, price: Price) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only the owner can set prices"
+        );
+        self.prices.insert(asset_id, price);
+    }
+
+    pub fn get_price_data(&self, asset_ids: Option<Vec<AssetId>>) -> PriceData {
+        let asset_ids = asset_ids.unwrap_or_else(|| self.prices.keys().cloned().collect());
+        PriceData {
+            timestamp: U64(env::block_timestamp()),
+            recency_duration_sec: 90,
+            prices: asset_ids
+                .into_iter()
+                .map(|asset_id| AssetOptionalPrice {
+                    price: self.prices.get(&asset_id).copied(),
+                    asset_id,
+                })
+                .collect(),
+        }
+    }
+}
@@ -1,9 +1,14 @@
 use near_sdk::{
-    json_types::U128,
+    json_types::{U128, U64},
     serde::{Deserialize, Serialize},
     AccountId,
 };
 
+use crate::borrow::{BorrowId, LiquidationResult, LiquidationTarget};
+use crate::futures::{FuturesId, FuturesSide, FuturesType};
+use crate::options::OptionSeriesId;
+use crate::perpetual::PerpetualId;
+
 /// Single swap action.
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
@@ -12,6 +17,8 @@ pub struct SwapAction {
     pub token_in: AccountId,
     pub amount_in: U128,
     pub token_out: AccountId,
+    /// Fails if the swap gives less, required for operators
+    pub min_amount_out: Option<U128>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -20,6 +27,8 @@ pub struct MultihopeSwapAction {
     pub token_in: AccountId,
     pub amount_in: U128,
     pub token_out: AccountId,
+    /// Fails if the swaps give less, required for operators
+    pub min_amount_out: Option<U128>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -31,6 +40,8 @@ pub struct OpenPositionAction {
     pub token1_liquidity: Option<U128>,
     pub lower_bound_price: f64,
     pub upper_bound_price: f64,
+    /// Expiration of the request made by the first leg, an hour from now if not set
+    pub expiration_ts: Option<U64>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -42,6 +53,95 @@ pub struct AddLiquidityAction {
     pub token1_liquidity: Option<U128>,
 }
 
+/// Opens a position with the largest liquidity that fits in the max amounts
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OpenPositionWithAmountsAction {
+    pub pool_id: usize,
+    pub max_amount0: U128,
+    pub max_amount1: U128,
+    pub min_amount0: U128,
+    pub min_amount1: U128,
+    pub lower_bound_price: f64,
+    pub upper_bound_price: f64,
+}
+
+/// Opens a position from a single token, swapping a part of it to the other pool token
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ZapOpenPositionAction {
+    pub pool_id: usize,
+    pub token_in: AccountId,
+    pub amount_in: U128,
+    pub lower_bound_price: f64,
+    pub upper_bound_price: f64,
+    /// In basis points of the TWAP prices of the pools
+    pub max_slippage: u16,
+}
+
+/// Closes the position and swaps both tokens into `token_out`
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ZapClosePositionAction {
+    pub pool_id: usize,
+    pub position_id: u128,
+    pub token_out: AccountId,
+    pub min_amount_out: U128,
+}
+
+/// Removes basis points or liquidity units of the position and swaps both tokens
+/// into `token_out`
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ZapRemoveLiquidityShareAction {
+    pub pool_id: usize,
+    pub position_id: u128,
+    pub basis_points: Option<u16>,
+    pub liquidity: Option<f64>,
+    pub token_out: AccountId,
+    pub min_amount_out: U128,
+}
+
+/// Adds the largest liquidity that fits in the max amounts to the position
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AddLiquidityWithAmountsAction {
+    pub pool_id: usize,
+    pub position_id: u128,
+    pub max_amount0: U128,
+    pub max_amount1: U128,
+    pub min_amount0: U128,
+    pub min_amount1: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RemoveLiquidityAction {
+    pub pool_id: usize,
+    pub position_id: u128,
+    pub token0_liquidity: Option<U128>,
+    pub token1_liquidity: Option<U128>,
+}
+
+/// Removes basis points or liquidity units of the position, all of it closes the position
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RemoveLiquidityShareAction {
+    pub pool_id: usize,
+    pub position_id: u128,
+    pub basis_points: Option<u16>,
+    pub liquidity: Option<f64>,
+    pub min_amount0: U128,
+    pub min_amount1: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClosePositionAction {
+    pub pool_id: usize,
+    pub position_id: u128,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct CreateDepositAction {
@@ -49,6 +149,51 @@ pub struct CreateDepositAction {
     pub amount: U128,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CloseDepositAction {
+    pub asset: AccountId,
+    pub amount: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SupplyCollateralAndBorrowAction {
+    pub pool_id: usize,
+    pub position_id: u128,
+    pub leverage: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RepayPartialAction {
+    pub borrow_id: BorrowId,
+    pub amount0: U128,
+    pub amount1: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReduceLeverageAction {
+    pub borrow_id: BorrowId,
+    pub target_leverage: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AddCollateralAction {
+    pub borrow_id: BorrowId,
+    pub token0_liquidity: Option<U128>,
+    pub token1_liquidity: Option<U128>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct IncreaseLeverageAction {
+    pub borrow_id: BorrowId,
+    pub new_leverage: f64,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct ReturnCollateralAndRepayAction {
@@ -58,7 +203,7 @@ pub struct ReturnCollateralAndRepayAction {
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct LiquidateAction {
-    pub borrow_id: u128,
+    pub target: LiquidationTarget,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -66,6 +211,85 @@ pub struct LiquidateAction {
 pub struct WithdrawAction {
     pub token: AccountId,
     pub amount: U128,
+    pub receiver_id: Option<AccountId>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferAction {
+    pub receiver_id: AccountId,
+    pub token: AccountId,
+    pub amount: U128,
+    pub memo: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OpenFuturesAction {
+    pub pool_id: usize,
+    pub futures_type: FuturesType,
+    pub side: FuturesSide,
+    pub price: f64,
+    pub size: U128,
+    pub collateral: U128,
+    pub expiration_ts: U64,
+    /// Fails if `price` is above it for a long or below it for a short, required for operators
+    pub limit_price: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TakeFuturesAction {
+    pub futures_id: FuturesId,
+    pub collateral: U128,
+    /// Fails if the price of the futures is above it for a long or below it for a short,
+    /// required for operators
+    pub limit_price: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FuturesAction {
+    pub futures_id: FuturesId,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OpenPerpetualAction {
+    pub pool_id: usize,
+    pub side: FuturesSide,
+    pub size: U128,
+    pub margin: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PerpetualAction {
+    pub perpetual_id: PerpetualId,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WriteOptionsAction {
+    pub series_id: OptionSeriesId,
+    pub amount: U128,
+    pub premium: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BuyOptionsAction {
+    pub series_id: OptionSeriesId,
+    pub writer_id: AccountId,
+    pub amount: U128,
+    /// Fails if the premium per option of the offer is above it, required for operators
+    pub max_premium: Option<U128>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OptionSeriesAction {
+    pub series_id: OptionSeriesId,
 }
 
 /// Single action. Allows to execute sequence of various actions initiated by an account.
@@ -80,4 +304,48 @@ pub enum Action {
     CreateDeposit(CreateDepositAction),
     ReturnCollateralAndRepay(ReturnCollateralAndRepayAction),
     Liquidate(LiquidateAction),
+    OpenFutures(OpenFuturesAction),
+    TakeFutures(TakeFuturesAction),
+    CancelFutures(FuturesAction),
+    SettleFutures(FuturesAction),
+    LiquidateFutures(FuturesAction),
+    OpenPerpetual(OpenPerpetualAction),
+    ClosePerpetual(PerpetualAction),
+    WriteOptions(WriteOptionsAction),
+    BuyOptions(BuyOptionsAction),
+    ExerciseOptions(OptionSeriesAction),
+    ClaimOptionCollateral(OptionSeriesAction),
+    Transfer(TransferAction),
+    RemoveLiquidity(RemoveLiquidityAction),
+    ClosePosition(ClosePositionAction),
+    CloseDeposit(CloseDepositAction),
+    SupplyCollateralAndBorrow(SupplyCollateralAndBorrowAction),
+    RepayPartial(RepayPartialAction),
+    ReduceLeverage(ReduceLeverageAction),
+    AddCollateral(AddCollateralAction),
+    IncreaseLeverage(IncreaseLeverageAction),
+    OpenPositionWithAmounts(OpenPositionWithAmountsAction),
+    AddLiquidityWithAmounts(AddLiquidityWithAmountsAction),
+    RemoveLiquidityShare(RemoveLiquidityShareAction),
+    ZapOpenPosition(ZapOpenPositionAction),
+    ZapClosePosition(ZapClosePositionAction),
+    ZapRemoveLiquidityShare(ZapRemoveLiquidityShareAction),
+}
+
+/// Result of a single action, in the order of the executed actions
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ActionResult {
+    /// The action returns nothing, withdrawals are resolved after the call
+    None,
+    /// Amount received, e.g. from a swap or a closed perpetual
+    Amount(U128),
+    /// Amounts of token0 and token1 given back from or added to a position
+    Amounts(U128, U128),
+    /// Position opened, none while an open position request waits for the other token
+    PositionId(Option<u128>),
+    BorrowId(BorrowId),
+    FuturesId(FuturesId),
+    PerpetualId(PerpetualId),
+    Liquidation(LiquidationResult),
 }
@@ -1,17 +1,62 @@
 use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
 use near_sdk::json_types::U128;
-use near_sdk::{collections::UnorderedMap, AccountId};
+use near_sdk::{collections::UnorderedMap, ext_contract, AccountId, PromiseResult};
 use std::collections::HashMap;
 
+use crate::events::{TransferLog, WithdrawLog};
 use crate::pool::CollectedFee;
 
 pub const GAS_FOR_FT_TRANSFER: u64 = 20_000_000_000_000;
+pub const GAS_FOR_RESOLVE_WITHDRAW: u64 = 10_000_000_000_000;
 
 pub type BalancesMap = UnorderedMap<AccountId, Balance>;
 type Balance = UnorderedMap<AccountId, u128>;
 
 pub use crate::*;
 
+#[ext_contract(ext_self)]
+pub trait WithdrawCallbacks {
+    fn on_withdraw_resolve(
+        &mut self,
+        account_id: AccountId,
+        receiver_id: AccountId,
+        token: AccountId,
+        amount: U128,
+    ) -> U128;
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Gives the amount back to the balance of the account if the transfer failed.
+    /// Returns the amount withdrawn.
+    #[private]
+    pub fn on_withdraw_resolve(
+        &mut self,
+        account_id: AccountId,
+        receiver_id: AccountId,
+        token: AccountId,
+        amount: U128,
+    ) -> U128 {
+        let success = matches!(env::promise_result(0), PromiseResult::Successful(_));
+        if !success {
+            self.increase_balance(&account_id, &token, amount.0);
+        }
+        let log = EventLog::new(EventLogVariant::Withdraw(vec![WithdrawLog {
+            account_id,
+            receiver_id,
+            token,
+            amount,
+            success,
+        }]));
+        env::log(log.to_string().as_bytes());
+        if success {
+            amount
+        } else {
+            U128(0)
+        }
+    }
+}
+
 impl Contract {
     pub fn deposit_ft(&mut self, account_id: &AccountId, token_in: &AccountId, amount: u128) {
         if let Some(mut balance) = self.balances_map.get(account_id) {
@@ -26,7 +71,14 @@ impl Contract {
         }
     }
 
-    pub fn balance_withdraw(&mut self, account_id: &AccountId, token: &AccountId, amount: u128) {
+    /// Transfers the tokens to the receiver, the balance is restored if the transfer fails
+    pub fn balance_withdraw(
+        &mut self,
+        account_id: &AccountId,
+        token: &AccountId,
+        amount: u128,
+        receiver_id: &AccountId,
+    ) -> Promise {
         let mut balance = self.balances_map.get(account_id).expect(BAL0);
         let current_amount = balance.get(token).expect(BAL0);
         assert!(
@@ -37,13 +89,57 @@ impl Contract {
         balance.insert(token, &(current_amount - amount));
         self.balances_map.insert(account_id, &balance);
         ext_fungible_token::ft_transfer(
-            account_id.to_string(),
+            receiver_id.to_string(),
             U128(amount),
             None,
             &token,
             1,
             GAS_FOR_FT_TRANSFER,
-        );
+        )
+        .then(ext_self::on_withdraw_resolve(
+            account_id.clone(),
+            receiver_id.clone(),
+            token.clone(),
+            U128(amount),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_WITHDRAW,
+        ))
+    }
+
+    /// Moves the amount to the balance of the receiver, the sender pays the storage
+    /// of a new token balance of the receiver
+    pub fn balance_transfer(
+        &mut self,
+        account_id: &AccountId,
+        receiver_id: &AccountId,
+        token: &AccountId,
+        amount: u128,
+        memo: Option<String>,
+    ) {
+        assert_ne!(account_id, receiver_id, "{}", BAL2);
+        assert!(amount > 0, "{}", BAL3);
+        assert!(self.storage_accounts.get(receiver_id).is_some(), "{}", STR0);
+        let initial_storage = self.internal_storage_usage();
+        self.decrease_balance(account_id, token, amount);
+        self.increase_balance(receiver_id, token, amount);
+        self.internal_charge_storage(account_id, initial_storage);
+        let log = EventLog::new(EventLogVariant::Transfer(vec![TransferLog {
+            sender_id: account_id.clone(),
+            receiver_id: receiver_id.clone(),
+            token: token.clone(),
+            amount: U128(amount),
+            memo,
+        }]));
+        env::log(log.to_string().as_bytes());
+    }
+
+    /// Balance of the token, 0 for unknown accounts
+    pub(crate) fn internal_get_balance(&self, account_id: &AccountId, token: &AccountId) -> u128 {
+        self.balances_map
+            .get(account_id)
+            .and_then(|balance| balance.get(token))
+            .unwrap_or(0)
     }
 
     pub fn decrease_balance(&mut self, account_id: &AccountId, token: &AccountId, amount: u128) {
@@ -1,8 +1,10 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::serde::Serialize;
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::AccountId;
 
 use crate::deposit::{BASIS_POINT_BASE, MS_IN_YEAR};
+use crate::perpetual::PerpetualId;
 
 pub type BorrowId = u128;
 #[derive(BorshDeserialize, BorshSerialize, Serialize)]
@@ -46,3 +48,36 @@ impl Borrow {
         self.fees += self.calculate_fees(current_timestamp);
     }
 }
+
+/// What a liquidator repays and receives for a borrow
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LiquidationPreview {
+    pub borrow_id: BorrowId,
+    pub health_factor: f64,
+    pub repay0: U128,
+    pub repay1: U128,
+    pub collateral0: U128,
+    pub collateral1: U128,
+    pub collateral_share: f64,
+    pub seize_position: bool,
+    /// Debt left unpaid after seizing the position, covered by the insurance fund or the depositors
+    pub deficit0: U128,
+    pub deficit1: U128,
+}
+
+/// Borrow or perpetual position that can be liquidated
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum LiquidationTarget {
+    Borrow(BorrowId),
+    Perpetual(PerpetualId),
+}
+
+/// What the liquidator repaid and received for a borrow, or the fee received for a perpetual
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum LiquidationResult {
+    Borrow(LiquidationPreview),
+    Perpetual(U128),
+}
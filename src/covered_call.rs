@@ -0,0 +1,189 @@
+use near_sdk::json_types::{U128, U64};
+
+use crate::*;
+
+/// Largest relative difference between the pool price and the TWAP, and between the swap
+/// result and the amount the TWAP implies, when the settlement of a covered call swaps
+/// through the pool
+pub const COVERED_CALL_MAX_PRICE_DEVIATION: f64 = 0.02;
+
+/// Token0 provided as liquidity in a narrow range above the pool price and locked until
+/// `expiration_ts`. If the price crosses the range the token0 is sold for token1 at prices
+/// from `strike` to `upper_bound_price`, like a covered call exercised at the strike.
+/// At settlement the owner is paid in token1 if the TWAP at expiration is above the strike
+/// and in token0 otherwise, what the position holds of the other token is swapped.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CoveredCall {
+    pub position_id: u128,
+    pub pool_id: usize,
+    pub owner_id: AccountId,
+    pub amount: u128,
+    pub strike: f64,
+    pub upper_bound_price: f64,
+    pub expiration_ts: u64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CoveredCallStatus {
+    pub covered_call: CoveredCall,
+    /// The price is above the range, the position holds only token1.
+    /// When settled, the TWAP at expiration is above the strike and the owner is paid in token1
+    pub exercised: bool,
+    /// The price is within the range, the position holds both tokens
+    pub in_range: bool,
+    pub amount0: U128,
+    pub amount1: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Locks `amount` of token0 from the internal balance in a position from `strike`
+    /// to `upper_bound_price` until `expiration_ts`. The range must be above the pool price.
+    pub fn open_covered_call(
+        &mut self,
+        pool_id: usize,
+        amount: U128,
+        strike: f64,
+        upper_bound_price: f64,
+        expiration_ts: U64,
+    ) -> u128 {
+        self.assert_pool_exists(pool_id);
+        assert!(expiration_ts.0 > env::block_timestamp(), "{}", CVC3);
+        let account_id = env::predecessor_account_id();
+        let initial_storage = self.internal_storage_usage();
+        let pool = &self.pools[pool_id];
+        let position = Position::new(
+            account_id.clone(),
+            Some(amount),
+            None,
+            strike,
+            upper_bound_price,
+            pool.sqrt_price,
+        );
+        assert!(
+            pool.sqrt_price < position.sqrt_lower_bound_price,
+            "{}",
+            CVC1
+        );
+        let token0 = pool.token0.clone();
+        self.decrease_balance(&account_id, &token0, position.token0_locked.round() as u128);
+        let position_id = self.positions_opened;
+        self.positions_opened += 1;
+        let pool = &mut self.pools[pool_id];
+        pool.open_position(position_id, position.clone());
+        pool.refresh(env::block_timestamp());
+        let covered_call = CoveredCall {
+            position_id,
+            pool_id,
+            owner_id: account_id,
+            amount: position.token0_locked.round() as u128,
+            strike,
+            upper_bound_price,
+            expiration_ts: expiration_ts.0,
+        };
+        self.internal_request_settlement_price(pool_id, covered_call.expiration_ts);
+        self.covered_calls.insert(&position_id, &covered_call);
+        self.internal_charge_storage(&covered_call.owner_id, initial_storage);
+        position_id
+    }
+
+    /// Closes an expired covered call, anyone can call it. The owner is paid in token1
+    /// if the pool TWAP over the window ending at expiration is above the strike and
+    /// in token0 otherwise. What the position holds of the other token is swapped
+    /// through the pool, or paid as it is if the pool cannot fill the swap. The swap fails
+    /// if it gives less than the TWAP implies by more than the max price deviation.
+    /// Returns the status with the amounts paid.
+    pub fn settle_covered_call(&mut self, position_id: u128) -> CoveredCallStatus {
+        let covered_call = self.covered_calls.get(&position_id).expect(CVC0);
+        assert!(
+            env::block_timestamp() >= covered_call.expiration_ts,
+            "{}",
+            CVC2
+        );
+        let initial_storage = self.internal_storage_usage();
+        let pool_id = covered_call.pool_id;
+        let settlement_price =
+            self.internal_take_settlement_price(pool_id, covered_call.expiration_ts);
+        let exercised = settlement_price >= covered_call.strike;
+        let pool = &mut self.pools[pool_id];
+        pool.refresh(env::block_timestamp());
+        let mut status = self.internal_covered_call_status(covered_call);
+        let owner_id = status.covered_call.owner_id.clone();
+        let pool = &mut self.pools[pool_id];
+        pool.close_position(position_id);
+        let token0 = pool.token0.clone();
+        let token1 = pool.token1.clone();
+        self.covered_calls.remove(&position_id);
+        self.increase_balance(&owner_id, &token0, status.amount0.0);
+        self.increase_balance(&owner_id, &token1, status.amount1.0);
+        // the storage of the covered call is given back to its owner
+        self.internal_charge_storage(&owner_id, initial_storage);
+        let (token_in, amount_in) = if exercised {
+            (token0, status.amount0.0)
+        } else {
+            (token1, status.amount1.0)
+        };
+        let pool = &self.pools[pool_id];
+        if amount_in > 0
+            && pool
+                .try_get_swap_result(&token_in, amount_in, pool::SwapDirection::Return)
+                .is_some()
+        {
+            let twap_price = self
+                .internal_twap_price(pool_id)
+                .unwrap_or_else(|error| env::panic(error.as_bytes()));
+            let price = pool.sqrt_price * pool.sqrt_price;
+            assert!(
+                (price / twap_price - 1.0).abs() <= COVERED_CALL_MAX_PRICE_DEVIATION,
+                "{}",
+                CVC4
+            );
+            // anyone can settle, so the swap must also give what the TWAP implies
+            let route_id = pool_id as i32 + 1;
+            let route = if exercised { [route_id] } else { [-route_id] };
+            let max_slippage = (COVERED_CALL_MAX_PRICE_DEVIATION * BASIS_POINT_TO_PERCENT) as u16;
+            let amount_out = self.internal_zap_swap(&owner_id, &route, amount_in, max_slippage);
+            if exercised {
+                status.amount0 = U128(0);
+                status.amount1.0 += amount_out;
+            } else {
+                status.amount1 = U128(0);
+                status.amount0.0 += amount_out;
+            }
+        }
+        status.exercised = exercised;
+        status
+    }
+
+    pub fn get_covered_call(&self, position_id: u128) -> CoveredCallStatus {
+        let covered_call = self.covered_calls.get(&position_id).expect(CVC0);
+        self.internal_covered_call_status(covered_call)
+    }
+
+    pub fn get_account_covered_calls(&self, account_id: AccountId) -> Vec<CoveredCall> {
+        self.covered_calls
+            .iter()
+            .filter(|(_, covered_call)| covered_call.owner_id == account_id)
+            .map(|(_, covered_call)| covered_call)
+            .collect()
+    }
+}
+
+impl Contract {
+    fn internal_covered_call_status(&self, covered_call: CoveredCall) -> CoveredCallStatus {
+        let pool = &self.pools[covered_call.pool_id];
+        let position = pool
+            .positions
+            .get(&covered_call.position_id)
+            .expect(PST0);
+        CoveredCallStatus {
+            exercised: pool.sqrt_price > position.sqrt_upper_bound_price,
+            in_range: position.is_active(pool.sqrt_price),
+            amount0: U128(position.token0_locked.round() as u128),
+            amount1: U128(position.token1_locked.round() as u128),
+            covered_call,
+        }
+    }
+}
@@ -19,6 +19,8 @@ pub struct Deposit {
     pub last_update_timestamp: u64,
     pub apr: u16,
     pub growth: u128,
+    /// Supply index of the reserve when `amount` was last updated
+    pub supply_index: f64,
 }
 
 impl Deposit {
@@ -31,8 +33,14 @@ impl Deposit {
             last_update_timestamp: 0,
             apr: 0,
             growth: 0,
+            supply_index: 1.0,
         }
     }
+
+    /// Amount left after the bad debt written off from the reserve since the last update
+    pub fn current_amount(&self, supply_index: f64) -> u128 {
+        (self.amount as f64 * supply_index / self.supply_index) as u128
+    }
     pub fn update_timestamp(&mut self, current_timestamp: u64) {
         self.last_update_timestamp = current_timestamp;
     }
@@ -140,4 +148,11 @@ mod test {
         deposit.refresh_growth(MS_IN_YEAR);
         assert_eq!(deposit.growth, 5);
     }
+
+    #[test]
+    fn current_amount_test() {
+        let deposit = Deposit::new(String::new(), String::new(), 1000);
+        assert_eq!(deposit.current_amount(1.0), 1000);
+        assert_eq!(deposit.current_amount(0.75), 750);
+    }
 }
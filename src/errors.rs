@@ -12,11 +12,20 @@ pub const NFT6: &str = "The token owner and the receiver should be different";
 pub const NFT7: &str = "Token already exists";
 pub const NFT8: &str = "Market cannot payout to that many receivers";
 
+pub const STR0: &str = "Account is not registered, call storage_deposit first";
+pub const STR1: &str = "Not enough storage deposit";
+pub const STR2: &str = "Attached deposit is less than the minimum storage balance";
+pub const STR3: &str = "Account has deposits, borrows, positions, derivatives or token balances";
+
 pub const BAL0: &str = "Token has not been deposited";
 pub const BAL1: &str = "Account is not registered";
+pub const BAL2: &str = "The sender and the receiver should be different";
+pub const BAL3: &str = "Amount must be above 0";
+pub const BAL4: &str = "Withdrawals and transfers require at least 1 yoctoNEAR attached";
 
 pub const SWP0: &str = "Route does not exist!";
 pub const SWP1: &str = "Not enough liquidity in pool to cover this swap";
+pub const SWP2: &str = "Swap gives less than the min amount out";
 
 pub const PST0: &str = "Position not found";
 pub const PST1: &str = "token0 liqudity cannot be 0";
@@ -24,13 +33,112 @@ pub const PST2: &str = "send token1 liquidity instead of token0";
 pub const PST3: &str = "token1 liqudity cannot be 0";
 pub const PST4: &str = "send token0 liquidity instead of token1";
 pub const PST5: &str = "Incorrect token";
+pub const PST6: &str = "Amounts are too small to provide liquidity";
+pub const PST7: &str = "Provided liquidity is below the minimum amounts";
+pub const PST8: &str = "Specify either basis points or liquidity to remove";
+pub const PST9: &str = "Liquidity to remove must be above 0 and not above the position liquidity";
+pub const PST10: &str = "Removed liquidity is below the minimum amounts";
+pub const PST11: &str = "Position is used as collateral, repay the borrow first";
 
 pub const RSR0: &str = "Reserve not found";
+pub const RSR1: &str = "Close factor must be above 0 and not above 1";
+pub const RSR2: &str = "Liquidation bonus is too high for the health factor to be restored";
+pub const RSR3: &str = "Loan to value must be above 0 and not above the liquidation threshold";
+pub const RSR4: &str = "Liquidation threshold must be below 1";
+pub const RSR5: &str = "Reserve factor must not exceed 10000 basis points";
+pub const RSR6: &str = "Supply cap of the reserve is exceeded";
+pub const RSR7: &str = "Borrow cap of the reserve is exceeded";
+pub const RSR8: &str = "Borrowing is disabled for the reserve";
+pub const RSR9: &str = "Reserve does not have enough liquidity to pay the profit";
 
 pub const DPS0: &str = "Deposit not found";
 pub const DPS1: &str = "You do not own this deposit";
 
 pub const BRR0: &str = "Borrow not found";
+pub const BRR1: &str = "You do not own this borrow";
+pub const BRR2: &str = "Target leverage must be at least 1 and below the current leverage";
+pub const BRR3: &str = "Borrow is not sufficiently collateralized";
+pub const BRR4: &str = "Position is already used as collateral, use increase_leverage instead";
+pub const BRR5: &str = "New leverage must be above the current leverage";
+pub const BRR6: &str = "Leverage exceeds the maximum for this position";
+
+pub const LQD0: &str = "Borrow is healthy and cannot be liquidated";
+
+pub const FTR0: &str = "Futures not found";
+pub const FTR1: &str = "Futures are already matched";
+pub const FTR2: &str = "Collateral is below the initial margin";
+pub const FTR3: &str = "Futures are not matched yet";
+pub const FTR4: &str = "Futures have not expired yet";
+pub const FTR5: &str = "Futures have expired";
+pub const FTR6: &str = "Futures are above the maintenance margin";
+pub const FTR7: &str = "You are not a side of these futures";
+pub const FTR8: &str = "Expiration must be in the future";
+pub const FTR9: &str = "Price and size must be above 0";
+pub const FTR10: &str = "Only settlement futures are marked to market";
+pub const FTR11: &str = "Collateral exceeds the delivery amount";
+pub const FTR12: &str = "Futures price is beyond the limit price of the side";
+
+pub const PRP0: &str = "Perpetual not found";
+pub const PRP1: &str = "Margin is below the initial margin";
+pub const PRP2: &str = "Open interest cap of the pool is exceeded";
+pub const PRP3: &str = "Perpetual is above the maintenance margin";
+pub const PRP4: &str = "You do not own the perpetual";
+pub const PRP5: &str = "Size must be above 0";
+pub const PRP6: &str = "Pool price deviates from the mark price more than allowed";
+pub const PRP7: &str = "No perpetual profit to claim in the token";
+
+pub const OPT0: &str = "Option series not found";
+pub const OPT1: &str = "Option series has expired";
+pub const OPT2: &str = "Option series has not expired yet";
+pub const OPT3: &str = "Not enough options offered";
+pub const OPT4: &str = "You don't have enough options";
+pub const OPT5: &str = "Strike must be above 0";
+pub const OPT6: &str = "Expiration must be in the future";
+pub const OPT7: &str = "You have not written options of the series";
+pub const OPT8: &str = "Not enough price history to estimate volatility";
+pub const OPT9: &str = "Premium of the offer is above the max premium";
+
+pub const CVC0: &str = "Covered call not found";
+pub const CVC1: &str = "Range of a covered call must be above the pool price";
+pub const CVC2: &str = "Covered call has not expired yet";
+pub const CVC3: &str = "Expiration must be in the future";
+pub const CVC4: &str = "Pool price deviates from the TWAP more than allowed";
+
+pub const TKN0: &str = "Token is not registered";
+pub const TKN1: &str = "Token is already registered";
+
+pub const OPR0: &str = "Operator is not approved";
+pub const OPR1: &str = "Operator approval has expired";
+pub const OPR2: &str = "Operator is not approved for the action";
+pub const OPR3: &str = "Scopes must not be empty";
+pub const OPR4: &str = "Expiration must be in the future";
+pub const OPR5: &str = "Operators can withdraw only to the account";
+pub const OPR6: &str = "Operators must set slippage bounds, use the actions with min amounts";
+pub const OPR7: &str = "Operator is not approved for the token";
+pub const OPR8: &str = "Operator is not approved for the position";
+pub const OPR9: &str = "Action spends more than the operator limit";
+
+pub const PRQ0: &str = "Open position request not found";
+pub const PRQ1: &str = "Open position request has expired";
+pub const PRQ2: &str = "Only the account can cancel the request before it expires";
+pub const PRQ3: &str = "Expiration must be in the future";
+pub const PRQ4: &str = "Pool and prices must match the open position request";
+pub const PRQ5: &str = "Position needs more of the other token than the second leg allows";
+
+pub const ZAP0: &str = "Swap result is beyond the max slippage";
+pub const ZAP1: &str = "No route with the liquidity to swap the token into a token of the pool";
+pub const ZAP2: &str = "Max slippage must not exceed 10000 basis points";
+pub const ZAP3: &str = "Zap gives less than the min amount out";
+pub const ZAP4: &str = "Lower bound price must be below the upper bound price";
+
+pub const ORC0: &str = "Oracle is not set";
+pub const ORC1: &str = "Oracle price is missing or stale";
+pub const ORC2: &str = "Pool price deviates from the reference price more than allowed";
+pub const ORC3: &str = "Maximum deviation must be above 0";
+pub const ORC4: &str = "Price history of the pool does not cover the TWAP window";
+pub const ORC5: &str = "TWAP window is longer than the price history of the pools";
+
+pub const INS0: &str = "Insurance share must not exceed 10000 basis points";
 
 pub fn withdraw_error(token: &AccountId, amount: u128, amount_actual: u128) -> String {
     format!(
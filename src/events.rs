@@ -0,0 +1,128 @@
+use std::fmt;
+
+use near_sdk::{
+    json_types::U128,
+    serde::{Deserialize, Serialize},
+    serde_json,
+};
+
+pub const EXCHANGE_STANDARD_NAME: &str = "crisp";
+pub const EXCHANGE_EVENTS_VERSION: &str = "1.0.0";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+#[serde(crate = "near_sdk::serde")]
+#[non_exhaustive]
+pub enum EventLogVariant {
+    BadDebt(Vec<BadDebtLog>),
+    Withdraw(Vec<WithdrawLog>),
+    Transfer(Vec<TransferLog>),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EventLog {
+    pub standard: String,
+    pub version: String,
+    // `flatten` to not have "event": {<EventLogVariant>} in the JSON, just have the contents of {<EventLogVariant>}.
+    #[serde(flatten)]
+    pub event: EventLogVariant,
+}
+
+impl EventLog {
+    pub fn new(event: EventLogVariant) -> Self {
+        EventLog {
+            standard: EXCHANGE_STANDARD_NAME.to_string(),
+            version: EXCHANGE_EVENTS_VERSION.to_string(),
+            event,
+        }
+    }
+}
+
+impl fmt::Display for EventLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "EVENT_JSON:{}",
+            &serde_json::to_string(self).map_err(|_| fmt::Error)?
+        ))
+    }
+}
+
+/// Debt left after a liquidation, split into the part paid by the insurance fund
+/// and the part written off from the deposits of the reserve
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BadDebtLog {
+    pub borrow_id: U128,
+    pub token: String,
+    pub deficit: U128,
+    pub covered: U128,
+    pub socialized: U128,
+}
+
+/// Result of a withdrawal, the amount is back in the balance of the account if it failed
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawLog {
+    pub account_id: String,
+    pub receiver_id: String,
+    pub token: String,
+    pub amount: U128,
+    pub success: bool,
+}
+
+/// Transfer between the internal balances of two accounts
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferLog {
+    pub sender_id: String,
+    pub receiver_id: String,
+    pub token: String,
+    pub amount: U128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_debt_format() {
+        let expected = r#"EVENT_JSON:{"standard":"crisp","version":"1.0.0","event":"bad_debt","data":[{"borrow_id":"3","token":"usn.near","deficit":"100","covered":"60","socialized":"40"}]}"#;
+        let log = EventLog::new(EventLogVariant::BadDebt(vec![BadDebtLog {
+            borrow_id: U128(3),
+            token: "usn.near".to_string(),
+            deficit: U128(100),
+            covered: U128(60),
+            socialized: U128(40),
+        }]));
+        assert_eq!(expected, log.to_string());
+    }
+
+    #[test]
+    fn withdraw_format() {
+        let expected = r#"EVENT_JSON:{"standard":"crisp","version":"1.0.0","event":"withdraw","data":[{"account_id":"alice.near","receiver_id":"bob.near","token":"usn.near","amount":"100","success":false}]}"#;
+        let log = EventLog::new(EventLogVariant::Withdraw(vec![WithdrawLog {
+            account_id: "alice.near".to_string(),
+            receiver_id: "bob.near".to_string(),
+            token: "usn.near".to_string(),
+            amount: U128(100),
+            success: false,
+        }]));
+        assert_eq!(expected, log.to_string());
+    }
+    #[test]
+    fn transfer_format() {
+        let expected = r#"EVENT_JSON:{"standard":"crisp","version":"1.0.0","event":"transfer","data":[{"sender_id":"alice.near","receiver_id":"bob.near","token":"usn.near","amount":"100","memo":"salary"}]}"#;
+        let log = EventLog::new(EventLogVariant::Transfer(vec![TransferLog {
+            sender_id: "alice.near".to_string(),
+            receiver_id: "bob.near".to_string(),
+            token: "usn.near".to_string(),
+            amount: U128(100),
+            memo: Some("salary".to_string()),
+        }]));
+        assert_eq!(expected, log.to_string());
+    }
+}
@@ -1,12 +1,556 @@
-enum FuturesType {
+use near_sdk::json_types::U128;
+use near_sdk::StorageUsage;
+
+use crate::action::OpenFuturesAction;
+use crate::*;
+
+/// Collateral of each side must be at least this share of the notional to open
+pub const FUTURES_INITIAL_MARGIN: f64 = 0.1;
+/// A side with equity below this share of the notional can be liquidated
+pub const FUTURES_MAINTENANCE_MARGIN: f64 = 0.05;
+/// Share of the notional the liquidator gets from the liquidated side
+pub const FUTURES_LIQUIDATION_FEE: f64 = 0.01;
+
+pub type FuturesId = u128;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum FuturesType {
     Deliverable,
     Settlement,
 }
 
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum FuturesSide {
+    Long,
+    Short,
+}
+
+/// Agreement to exchange `size` of token0 of the pool at `price` at `expiration_ts`.
+/// Settlement futures are settled in `token` (token1 of the pool) against the pool TWAP
+/// ending at `expiration_ts`, the collateral of both sides is in `token`.
+/// Deliverable futures are settled by delivery of `token` (token0 of the pool), the short
+/// side escrows `size` of it and the long side escrows `size * price` of token1.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
 pub struct Futures {
+    pub id: FuturesId,
+    pub pool_id: usize,
     pub price: f64,
+    pub size: u128,
     pub expiration_ts: u64,
     pub futures_type: FuturesType,
     pub token: AccountId,
-    pub collateral: u128,
-}
\ No newline at end of file
+    /// Side that offered the futures, it pays for their storage except for the id of the taker
+    pub maker: FuturesSide,
+    pub long_id: Option<AccountId>,
+    pub short_id: Option<AccountId>,
+    pub long_collateral: u128,
+    pub short_collateral: u128,
+}
+
+impl Futures {
+    pub fn is_matched(&self) -> bool {
+        self.long_id.is_some() && self.short_id.is_some()
+    }
+
+    /// Accounts of the maker and the taker of matched futures
+    pub fn maker_and_taker(&self) -> (&AccountId, &AccountId) {
+        let long_id = self.long_id.as_ref().unwrap();
+        let short_id = self.short_id.as_ref().unwrap();
+        match self.maker {
+            FuturesSide::Long => (long_id, short_id),
+            FuturesSide::Short => (short_id, long_id),
+        }
+    }
+
+    /// Amount each side has to escrow for delivery, long in token1 and short in token0
+    pub fn delivery_amounts(&self) -> (u128, u128) {
+        ((self.size as f64 * self.price).ceil() as u128, self.size)
+    }
+
+    pub fn notional(&self, price: f64) -> f64 {
+        self.size as f64 * price
+    }
+
+    /// Profit of the long side in `token` if settled at `price`, the short side gets the opposite
+    pub fn long_pnl(&self, price: f64) -> f64 {
+        self.size as f64 * (price - self.price)
+    }
+
+    /// Amounts paid out to the long and the short side when settled at `price`,
+    /// nobody loses more than their collateral
+    pub fn payouts(&self, price: f64) -> (u128, u128) {
+        let total = self.long_collateral + self.short_collateral;
+        let long = (self.long_collateral as f64 + self.long_pnl(price))
+            .max(0.0)
+            .min(total as f64) as u128;
+        (long, total - long)
+    }
+
+    /// Equity of the long and the short side divided by the notional at `price`
+    pub fn margins(&self, price: f64) -> (f64, f64) {
+        let notional = self.notional(price);
+        let pnl = self.long_pnl(price);
+        (
+            (self.long_collateral as f64 + pnl) / notional,
+            (self.short_collateral as f64 - pnl) / notional,
+        )
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FuturesMargin {
+    pub mark_price: f64,
+    pub long_pnl: f64,
+    pub long_margin: f64,
+    pub short_margin: f64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Offers futures on the pool price, the counterparty takes the other side
+    /// with `take_futures`. Collateral is taken from the internal balance, in token1 of the pool
+    /// for settlement futures and in the token the side delivers for deliverable ones.
+    pub fn open_futures(&mut self, action: OpenFuturesAction) -> FuturesId {
+        let account_id = env::predecessor_account_id();
+        self.internal_open_futures(&account_id, &action)
+    }
+
+    pub(crate) fn internal_open_futures(
+        &mut self,
+        account_id: &AccountId,
+        action: &OpenFuturesAction,
+    ) -> FuturesId {
+        let OpenFuturesAction {
+            pool_id,
+            futures_type,
+            side,
+            price,
+            size,
+            collateral,
+            expiration_ts,
+            limit_price,
+        } = *action;
+        self.assert_pool_exists(pool_id);
+        let initial_storage = self.internal_storage_usage();
+        assert!(expiration_ts.0 > env::block_timestamp(), "{}", FTR8);
+        assert!(price > 0.0 && size.0 > 0, "{}", FTR9);
+        assert_futures_limit_price(side, price, limit_price);
+        let pool = &self.pools[pool_id];
+        let token = match futures_type {
+            FuturesType::Settlement => pool.token1.clone(),
+            FuturesType::Deliverable => pool.token0.clone(),
+        };
+        let mut futures = Futures {
+            id: self.futures_number,
+            pool_id,
+            price,
+            size: size.0,
+            expiration_ts: expiration_ts.0,
+            futures_type,
+            token,
+            maker: side,
+            long_id: None,
+            short_id: None,
+            long_collateral: 0,
+            short_collateral: 0,
+        };
+        let token = self.internal_assert_futures_collateral(&futures, side, collateral.0);
+        self.decrease_balance(account_id, &token, collateral.0);
+        match side {
+            FuturesSide::Long => {
+                futures.long_id = Some(account_id.clone());
+                futures.long_collateral = collateral.0;
+            }
+            FuturesSide::Short => {
+                futures.short_id = Some(account_id.clone());
+                futures.short_collateral = collateral.0;
+            }
+        }
+        if futures_type == FuturesType::Settlement {
+            self.internal_request_settlement_price(pool_id, futures.expiration_ts);
+        }
+        self.futures.insert(&futures.id, &futures);
+        self.futures_number += 1;
+        self.internal_charge_storage(account_id, initial_storage);
+        futures.id
+    }
+
+    /// Takes the free side of offered futures
+    pub fn take_futures(&mut self, futures_id: FuturesId, collateral: U128) {
+        let account_id = env::predecessor_account_id();
+        self.internal_take_futures(&account_id, futures_id, collateral, None)
+    }
+
+    pub(crate) fn internal_take_futures(
+        &mut self,
+        account_id: &AccountId,
+        futures_id: FuturesId,
+        collateral: U128,
+        limit_price: Option<f64>,
+    ) {
+        let initial_storage = self.internal_storage_usage();
+        let mut futures = self.futures.get(&futures_id).expect(FTR0);
+        assert!(!futures.is_matched(), "{}", FTR1);
+        assert!(env::block_timestamp() < futures.expiration_ts, "{}", FTR5);
+        let side = if futures.long_id.is_none() {
+            FuturesSide::Long
+        } else {
+            FuturesSide::Short
+        };
+        assert_futures_limit_price(side, futures.price, limit_price);
+        let token = self.internal_assert_futures_collateral(&futures, side, collateral.0);
+        self.decrease_balance(account_id, &token, collateral.0);
+        if side == FuturesSide::Long {
+            futures.long_id = Some(account_id.clone());
+            futures.long_collateral = collateral.0;
+        } else {
+            futures.short_id = Some(account_id.clone());
+            futures.short_collateral = collateral.0;
+        }
+        self.futures.insert(&futures_id, &futures);
+        self.internal_charge_storage(account_id, initial_storage);
+    }
+
+    /// Withdraws an offer nobody has taken yet and returns its collateral
+    pub fn cancel_futures(&mut self, futures_id: FuturesId) {
+        let account_id = env::predecessor_account_id();
+        self.internal_cancel_futures(&account_id, futures_id)
+    }
+
+    pub(crate) fn internal_cancel_futures(
+        &mut self,
+        account_id: &AccountId,
+        futures_id: FuturesId,
+    ) {
+        let initial_storage = self.internal_storage_usage();
+        let futures = self.futures.get(&futures_id).expect(FTR0);
+        assert!(!futures.is_matched(), "{}", FTR1);
+        assert!(
+            futures.long_id.as_ref() == Some(account_id)
+                || futures.short_id.as_ref() == Some(account_id),
+            "{}",
+            FTR7
+        );
+        self.futures.remove(&futures_id);
+        self.internal_release_futures_settlement_price(&futures);
+        let (long_token, short_token) = self.internal_futures_tokens(&futures);
+        let token = if futures.long_id.is_some() {
+            long_token
+        } else {
+            short_token
+        };
+        self.increase_balance(
+            account_id,
+            &token,
+            futures.long_collateral + futures.short_collateral,
+        );
+        self.internal_charge_storage(account_id, initial_storage);
+    }
+
+    /// Adds collateral to your side, for deliverable futures this is how the side
+    /// funds the rest of its delivery before expiration
+    pub fn add_futures_collateral(&mut self, futures_id: FuturesId, amount: U128) {
+        let account_id = env::predecessor_account_id();
+        let mut futures = self.futures.get(&futures_id).expect(FTR0);
+        assert!(env::block_timestamp() < futures.expiration_ts, "{}", FTR5);
+        let (long_token, short_token) = self.internal_futures_tokens(&futures);
+        let (long_delivery, short_delivery) = futures.delivery_amounts();
+        let deliverable = futures.futures_type == FuturesType::Deliverable;
+        if futures.long_id.as_ref() == Some(&account_id) {
+            futures.long_collateral += amount.0;
+            assert!(!deliverable || futures.long_collateral <= long_delivery, "{}", FTR11);
+            self.decrease_balance(&account_id, &long_token, amount.0);
+        } else if futures.short_id.as_ref() == Some(&account_id) {
+            futures.short_collateral += amount.0;
+            assert!(!deliverable || futures.short_collateral <= short_delivery, "{}", FTR11);
+            self.decrease_balance(&account_id, &short_token, amount.0);
+        } else {
+            env::panic(FTR7.as_bytes());
+        }
+        self.futures.insert(&futures_id, &futures);
+    }
+
+    /// Settles matched futures at the mark price if one side is below the maintenance margin.
+    /// The liquidator gets the liquidation fee out of that side's payout.
+    pub fn liquidate_futures(&mut self, futures_id: FuturesId) -> U128 {
+        let account_id = env::predecessor_account_id();
+        self.internal_liquidate_futures(&account_id, futures_id)
+    }
+
+    pub(crate) fn internal_liquidate_futures(
+        &mut self,
+        account_id: &AccountId,
+        futures_id: FuturesId,
+    ) -> U128 {
+        let initial_storage = self.internal_storage_usage();
+        let futures = self.futures.get(&futures_id).expect(FTR0);
+        assert!(futures.is_matched(), "{}", FTR3);
+        assert_eq!(futures.futures_type, FuturesType::Settlement, "{}", FTR10);
+        let mark_price = self.internal_futures_mark_price(futures.pool_id);
+        let (long_margin, short_margin) = futures.margins(mark_price);
+        let long_liquidated = long_margin < FUTURES_MAINTENANCE_MARGIN;
+        assert!(
+            long_liquidated || short_margin < FUTURES_MAINTENANCE_MARGIN,
+            "{}",
+            FTR6
+        );
+        let (mut long_payout, mut short_payout) = futures.payouts(mark_price);
+        let payout = if long_liquidated {
+            &mut long_payout
+        } else {
+            &mut short_payout
+        };
+        let fee = u128::min(
+            (FUTURES_LIQUIDATION_FEE * futures.notional(mark_price)) as u128,
+            *payout,
+        );
+        *payout -= fee;
+        self.internal_release_futures_settlement_price(&futures);
+        self.internal_close_futures(&futures, initial_storage, long_payout, short_payout);
+        let initial_storage = self.internal_storage_usage();
+        self.increase_balance(account_id, &futures.token, fee);
+        self.internal_charge_storage(account_id, initial_storage);
+        U128(fee)
+    }
+
+    /// Settles expired futures, anyone can call it. Settlement futures are settled at the pool
+    /// TWAP over the window ending at expiration, which the pool keeps from its first change
+    /// after expiration. Deliverable futures exchange the escrowed tokens if both sides funded
+    /// their delivery, a side that did not fund it defaults and its escrow goes to the other side.
+    pub fn settle_futures(&mut self, futures_id: FuturesId) {
+        let futures = self.futures.get(&futures_id).expect(FTR0);
+        assert!(futures.is_matched(), "{}", FTR3);
+        assert!(env::block_timestamp() >= futures.expiration_ts, "{}", FTR4);
+        if futures.futures_type == FuturesType::Deliverable {
+            self.internal_deliver_futures(&futures);
+            return;
+        }
+        let initial_storage = self.internal_storage_usage();
+        let settlement_price =
+            self.internal_take_settlement_price(futures.pool_id, futures.expiration_ts);
+        let (long_payout, short_payout) = futures.payouts(settlement_price);
+        self.internal_close_futures(&futures, initial_storage, long_payout, short_payout);
+    }
+
+    pub fn get_futures(&self, futures_id: FuturesId) -> Futures {
+        self.futures.get(&futures_id).expect(FTR0)
+    }
+
+    pub fn get_account_futures(&self, account_id: AccountId) -> Vec<Futures> {
+        self.futures
+            .iter()
+            .filter(|(_, futures)| {
+                futures.long_id.as_ref() == Some(&account_id)
+                    || futures.short_id.as_ref() == Some(&account_id)
+            })
+            .map(|(_, futures)| futures)
+            .collect()
+    }
+
+    /// Futures offered and not taken yet
+    pub fn get_open_futures(&self, from_index: u64, limit: u64) -> Vec<Futures> {
+        self.futures
+            .iter()
+            .filter(|(_, futures)| !futures.is_matched())
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .map(|(_, futures)| futures)
+            .collect()
+    }
+
+    pub fn get_futures_margin(&self, futures_id: FuturesId) -> FuturesMargin {
+        let futures = self.futures.get(&futures_id).expect(FTR0);
+        assert_eq!(futures.futures_type, FuturesType::Settlement, "{}", FTR10);
+        let mark_price = self.internal_futures_mark_price(futures.pool_id);
+        let (long_margin, short_margin) = futures.margins(mark_price);
+        FuturesMargin {
+            mark_price,
+            long_pnl: futures.long_pnl(mark_price),
+            long_margin,
+            short_margin,
+        }
+    }
+
+    /// Pool TWAP the futures are marked against
+    pub fn get_futures_mark_price(&self, pool_id: usize) -> f64 {
+        self.assert_pool_exists(pool_id);
+        self.internal_futures_mark_price(pool_id)
+    }
+}
+
+impl Contract {
+    fn internal_futures_mark_price(&self, pool_id: usize) -> f64 {
+        self.internal_twap_price(pool_id)
+            .unwrap_or_else(|error| env::panic(error.as_bytes()))
+    }
+
+    /// Drops the request for the settlement price of futures closed before settlement
+    fn internal_release_futures_settlement_price(&mut self, futures: &Futures) {
+        if futures.futures_type == FuturesType::Settlement {
+            self.pools[futures.pool_id].release_twap_snapshot(futures.expiration_ts);
+        }
+    }
+
+    /// Tokens the long and the short side put up as collateral
+    fn internal_futures_tokens(&self, futures: &Futures) -> (AccountId, AccountId) {
+        match futures.futures_type {
+            FuturesType::Settlement => (futures.token.clone(), futures.token.clone()),
+            FuturesType::Deliverable => {
+                let pool = &self.pools[futures.pool_id];
+                (pool.token1.clone(), pool.token0.clone())
+            }
+        }
+    }
+
+    /// Checks the initial margin of a side and returns the token its collateral is in
+    fn internal_assert_futures_collateral(
+        &self,
+        futures: &Futures,
+        side: FuturesSide,
+        collateral: u128,
+    ) -> AccountId {
+        let (long_token, short_token) = self.internal_futures_tokens(futures);
+        match futures.futures_type {
+            FuturesType::Settlement => {
+                assert!(
+                    collateral as f64 >= FUTURES_INITIAL_MARGIN * futures.notional(futures.price),
+                    "{}",
+                    FTR2
+                );
+                long_token
+            }
+            FuturesType::Deliverable => {
+                let (long_delivery, short_delivery) = futures.delivery_amounts();
+                let (delivery, token) = match side {
+                    FuturesSide::Long => (long_delivery, long_token),
+                    FuturesSide::Short => (short_delivery, short_token),
+                };
+                assert!(
+                    collateral as f64 >= FUTURES_INITIAL_MARGIN * delivery as f64,
+                    "{}",
+                    FTR2
+                );
+                assert!(collateral <= delivery, "{}", FTR11);
+                token
+            }
+        }
+    }
+
+    fn internal_deliver_futures(&mut self, futures: &Futures) {
+        let initial_storage = self.internal_storage_usage();
+        self.futures.remove(&futures.id);
+        self.internal_release_futures_storage(futures, initial_storage);
+        let (long_token, short_token) = self.internal_futures_tokens(futures);
+        let (long_delivery, short_delivery) = futures.delivery_amounts();
+        let long_id = futures.long_id.as_ref().unwrap();
+        let short_id = futures.short_id.as_ref().unwrap();
+        let long_funded = futures.long_collateral == long_delivery;
+        let short_funded = futures.short_collateral == short_delivery;
+        // the escrow of a side that did not fund its delivery goes to the other side
+        let (long_receiver, short_receiver) = match (long_funded, short_funded) {
+            (true, true) => (short_id, long_id),
+            (true, false) => (long_id, long_id),
+            (false, true) => (short_id, short_id),
+            (false, false) => (long_id, short_id),
+        };
+        self.internal_pay_futures(long_receiver, &long_token, futures.long_collateral);
+        self.internal_pay_futures(short_receiver, &short_token, futures.short_collateral);
+    }
+
+    /// Removes matched futures and pays out the sides, the storage released since
+    /// `initial_storage` goes back to the sides
+    fn internal_close_futures(
+        &mut self,
+        futures: &Futures,
+        initial_storage: StorageUsage,
+        long_payout: u128,
+        short_payout: u128,
+    ) {
+        self.futures.remove(&futures.id);
+        self.internal_release_futures_storage(futures, initial_storage);
+        let long_id = futures.long_id.as_ref().unwrap();
+        let short_id = futures.short_id.as_ref().unwrap();
+        self.internal_pay_futures(long_id, &futures.token, long_payout);
+        self.internal_pay_futures(short_id, &futures.token, short_payout);
+    }
+
+    /// Gives the storage released since `initial_storage` by removing matched futures
+    /// back to the sides that paid for it: the taker gets the storage of its id
+    /// and the maker the rest
+    fn internal_release_futures_storage(
+        &mut self,
+        futures: &Futures,
+        initial_storage: StorageUsage,
+    ) {
+        let (maker_id, taker_id) = futures.maker_and_taker();
+        // the id of the taker is a string with its length in the futures
+        let taker_storage = (4 + taker_id.len()) as StorageUsage;
+        self.internal_move_storage(taker_id, maker_id, taker_storage);
+        self.internal_charge_storage(maker_id, initial_storage);
+    }
+
+    /// Increases the balance of a side, which pays for the storage of a new balance
+    fn internal_pay_futures(&mut self, account_id: &AccountId, token: &AccountId, amount: u128) {
+        let initial_storage = self.internal_storage_usage();
+        self.increase_balance(account_id, token, amount);
+        self.internal_charge_storage(account_id, initial_storage);
+    }
+}
+
+/// Checks the price is not above the limit for a long and not below it for a short
+fn assert_futures_limit_price(side: FuturesSide, price: f64, limit_price: Option<f64>) {
+    if let Some(limit_price) = limit_price {
+        let within_limit = match side {
+            FuturesSide::Long => price <= limit_price,
+            FuturesSide::Short => price >= limit_price,
+        };
+        assert!(within_limit, "{}", FTR12);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::futures::*;
+
+    fn futures() -> Futures {
+        Futures {
+            id: 0,
+            pool_id: 0,
+            price: 100.0,
+            size: 10,
+            expiration_ts: 0,
+            futures_type: FuturesType::Settlement,
+            token: String::new(),
+            maker: FuturesSide::Long,
+            long_id: Some("long".to_string()),
+            short_id: Some("short".to_string()),
+            long_collateral: 100,
+            short_collateral: 200,
+        }
+    }
+
+    #[test]
+    fn payouts_test() {
+        let futures = futures();
+        assert_eq!(futures.payouts(100.0), (100, 200));
+        assert_eq!(futures.payouts(105.0), (150, 150));
+        assert_eq!(futures.payouts(95.0), (50, 250));
+        // losses are limited by the collateral
+        assert_eq!(futures.payouts(150.0), (300, 0));
+        assert_eq!(futures.payouts(50.0), (0, 300));
+    }
+
+    #[test]
+    fn margins_test() {
+        let futures = futures();
+        let (long_margin, short_margin) = futures.margins(100.0);
+        assert_eq!(long_margin, 0.1);
+        assert_eq!(short_margin, 0.2);
+        let (long_margin, _) = futures.margins(95.0);
+        assert!((long_margin - 50.0 / 950.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,90 @@
+use near_sdk::{
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    collections::Vector,
+    json_types::U128,
+    serde::Serialize,
+    AccountId,
+};
+use std::collections::HashMap;
+
+use crate::deposit::BASIS_POINT_BASE;
+
+/// Share of protocol fees and borrow interest sent to the insurance fund, in basis points
+pub const DEFAULT_INSURANCE_SHARE: u16 = 1000;
+
+/// One bad debt event: how much of the deficit in `token` the fund paid
+/// and how much was written off from the deposits
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Coverage {
+    pub borrow_id: U128,
+    pub token: AccountId,
+    pub deficit: U128,
+    pub covered: U128,
+    pub socialized: U128,
+    pub timestamp: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct InsuranceFund {
+    pub balances: HashMap<AccountId, u128>,
+    pub share: u16,
+    pub history: Vector<Coverage>,
+}
+
+impl InsuranceFund {
+    pub fn new(prefix: Vec<u8>) -> Self {
+        InsuranceFund {
+            balances: HashMap::new(),
+            share: DEFAULT_INSURANCE_SHARE,
+            history: Vector::new(prefix),
+        }
+    }
+
+    pub fn get_balance(&self, token: &AccountId) -> u128 {
+        *self.balances.get(token).unwrap_or(&0)
+    }
+
+    /// Takes the fund's share of `amount` and returns it
+    pub fn contribute(&mut self, token: &AccountId, amount: u128) -> u128 {
+        let contribution = amount * self.share as u128 / BASIS_POINT_BASE as u128;
+        if contribution > 0 {
+            *self.balances.entry(token.clone()).or_insert(0) += contribution;
+        }
+        contribution
+    }
+
+    /// Pays as much of the deficit as the fund holds, returns the paid amount
+    pub fn cover(&mut self, token: &AccountId, deficit: u128) -> u128 {
+        let covered = u128::min(self.get_balance(token), deficit);
+        if covered > 0 {
+            *self.balances.get_mut(token).unwrap() -= covered;
+        }
+        covered
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::insurance::*;
+
+    #[test]
+    fn contribute_test() {
+        let mut fund = InsuranceFund::new(b"i".to_vec());
+        let token = "usn".to_string();
+        assert_eq!(fund.contribute(&token, 5000), 500);
+        assert_eq!(fund.contribute(&token, 9), 0);
+        assert_eq!(fund.get_balance(&token), 500);
+    }
+
+    #[test]
+    fn cover_test() {
+        let mut fund = InsuranceFund::new(b"i".to_vec());
+        let token = "usn".to_string();
+        fund.contribute(&token, 5000);
+        assert_eq!(fund.cover(&token, 200), 200);
+        assert_eq!(fund.cover(&token, 1000), 300);
+        assert_eq!(fund.get_balance(&token), 0);
+        assert_eq!(fund.cover(&"wnear".to_string(), 100), 0);
+    }
+}
@@ -1,25 +1,50 @@
-use balance::borrow::{Borrow, BorrowId};
-use balance::deposit::{Deposit, DepositId};
-use balance::reserve::Reserve;
-use balance::token_receiver::OpenPositionRequest;
+use balance::borrow::{Borrow, BorrowId, LiquidationPreview};
+pub use borrow::{LiquidationResult, LiquidationTarget};
+use balance::deposit::{Deposit, DepositId, BASIS_POINT_BASE};
+use balance::insurance::{Coverage, InsuranceFund};
+use balance::reserve::{Reserve, ReserveConfig};
+use covered_call::CoveredCall;
+use futures::{Futures, FuturesId};
 pub use balance::BalancesMap;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::UnorderedMap;
-use near_sdk::{env, ext_contract, near_bindgen};
+use near_sdk::{assert_one_yocto, env, near_bindgen};
 use near_sdk::{AccountId, PanicOnDefault};
 use nft::metadata::{NFTContractMetadata, Token, TokenId, TokenMetadata};
+use operator::Operator;
+use options::{OptionSeries, OptionSeriesId};
+use oracle::{OraclePrice, PriceConfig};
+use perpetual::{Perpetual, PerpetualId, PerpetualMarket};
 use pool::Pool;
+use position_request::{OpenPositionRequest, OpenPositionRequestId};
+use storage::StorageAccount;
+use token_registry::RegisteredToken;
 
 pub use crate::balance::*;
+use crate::action::{
+    AddLiquidityWithAmountsAction, OpenPositionWithAmountsAction, RemoveLiquidityShareAction,
+};
 use crate::errors::*;
+use crate::events::{BadDebtLog, EventLog, EventLogVariant};
 use crate::nft::nft_core::NonFungibleTokenCore;
 use crate::position::Position;
 
+pub mod action;
 pub mod balance;
+pub mod covered_call;
 mod errors;
+pub mod futures;
+pub mod operator;
+pub mod options;
+pub mod oracle;
+pub mod perpetual;
 pub mod pool;
+pub mod portfolio;
 pub mod position;
+pub mod position_request;
 mod token_receiver;
+pub mod token_registry;
+pub mod zap;
 
 use near_sdk::collections::{LazyOption, LookupMap, UnorderedSet};
 use near_sdk::json_types::{Base64VecU8, U128};
@@ -27,11 +52,13 @@ use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{Balance, CryptoHash, Promise, PromiseOrValue};
 use std::collections::HashMap;
 
-mod action;
 mod borrow;
 mod deposit;
+mod events;
+mod insurance;
 mod nft;
 mod reserve;
+mod storage;
 
 #[derive(BorshSerialize)]
 pub enum StorageKey {
@@ -43,6 +70,17 @@ pub enum StorageKey {
     Balances,
     Reserves,
     Borrows,
+    InsuranceHistory,
+    Futures,
+    Perpetuals,
+    PerpetualClaims,
+    OptionSeries,
+    CoveredCalls,
+    StorageAccounts,
+    RegisteredTokens,
+    Operators,
+    OpenPositionRequests,
+    Deposits,
 }
 
 pub const NFT_METADATA_SPEC: &str = "1.0.0";
@@ -52,11 +90,6 @@ pub const BASIS_POINT_TO_PERCENT: f64 = 10000.0;
 pub const APR_DEPOSIT: u16 = 500;
 pub const APR_BORROW: u16 = 1000;
 
-/// Maximum Loan-to-Value (LTV) ratio
-/// This is the maximum ratio of the loan amount to the value of the collateral.
-/// For example, if LTV_MAX is 0.8, you can borrow up to 80% of the value of your collateral.
-pub const LTV_MAX: f64 = 0.8;
-
 pub const TGAS: u64 = 1000000000000;
 
 type Pair = (AccountId, AccountId);
@@ -73,25 +106,32 @@ pub struct Contract {
     pub token_metadata_by_id: UnorderedMap<TokenId, TokenMetadata>,
     pub metadata: LazyOption<NFTContractMetadata>,
     pub positions_opened: u128,
-    pub deposits: HashMap<AccountId, HashMap<TokenId, Deposit>>,
+    pub deposits: UnorderedMap<AccountId, HashMap<TokenId, Deposit>>,
     pub deposits_created_number: DepositId,
     pub reserves: UnorderedMap<AccountId, Reserve>,
     pub borrows: UnorderedMap<BorrowId, Borrow>,
     pub borrows_number: BorrowId,
     pub routes: HashMap<Pair, Vec<i32>>,
     pub routes_counter: i32,
-    pub open_position_requests: HashMap<usize, OpenPositionRequest>,
-}
-
-#[ext_contract(ext_self)]
-pub trait SelfCallbacks {
-    fn nft_transfer(
-        &mut self,
-        receiver_id: AccountId,
-        token_id: TokenId,
-        approval_id: Option<u64>,
-        memo: Option<String>,
-    );
+    pub open_position_requests:
+        LookupMap<AccountId, HashMap<OpenPositionRequestId, OpenPositionRequest>>,
+    pub insurance_fund: InsuranceFund,
+    pub price_config: PriceConfig,
+    pub oracle_prices: HashMap<AccountId, OraclePrice>,
+    pub futures: UnorderedMap<FuturesId, Futures>,
+    pub futures_number: FuturesId,
+    pub perpetuals: UnorderedMap<PerpetualId, Perpetual>,
+    pub perpetuals_number: PerpetualId,
+    pub perpetual_markets: HashMap<usize, PerpetualMarket>,
+    /// Profit of closed perpetuals the reserve did not have the liquidity to pay,
+    /// per account and token
+    pub perpetual_claims: LookupMap<AccountId, HashMap<AccountId, u128>>,
+    pub option_series: UnorderedMap<OptionSeriesId, OptionSeries>,
+    pub option_series_number: OptionSeriesId,
+    pub covered_calls: UnorderedMap<u128, CoveredCall>,
+    pub storage_accounts: LookupMap<AccountId, StorageAccount>,
+    pub registered_tokens: UnorderedMap<AccountId, RegisteredToken>,
+    pub operators: LookupMap<AccountId, HashMap<AccountId, Operator>>,
 }
 
 #[near_bindgen]
@@ -121,14 +161,35 @@ impl Contract {
                 Some(&metadata),
             ),
             positions_opened: 0,
-            deposits: HashMap::new(),
+            deposits: UnorderedMap::new(StorageKey::Deposits.try_to_vec().unwrap()),
             deposits_created_number: 0,
             reserves: UnorderedMap::new(StorageKey::Reserves.try_to_vec().unwrap()),
             borrows: UnorderedMap::new(StorageKey::Borrows.try_to_vec().unwrap()),
             borrows_number: 0,
             routes: HashMap::new(),
             routes_counter: 1,
-            open_position_requests: HashMap::new(),
+            open_position_requests: LookupMap::new(
+                StorageKey::OpenPositionRequests.try_to_vec().unwrap(),
+            ),
+            insurance_fund: InsuranceFund::new(
+                StorageKey::InsuranceHistory.try_to_vec().unwrap(),
+            ),
+            price_config: PriceConfig::default(),
+            oracle_prices: HashMap::new(),
+            futures: UnorderedMap::new(StorageKey::Futures.try_to_vec().unwrap()),
+            futures_number: 0,
+            perpetuals: UnorderedMap::new(StorageKey::Perpetuals.try_to_vec().unwrap()),
+            perpetuals_number: 0,
+            perpetual_markets: HashMap::new(),
+            perpetual_claims: LookupMap::new(StorageKey::PerpetualClaims.try_to_vec().unwrap()),
+            option_series: UnorderedMap::new(StorageKey::OptionSeries.try_to_vec().unwrap()),
+            option_series_number: 0,
+            covered_calls: UnorderedMap::new(StorageKey::CoveredCalls.try_to_vec().unwrap()),
+            storage_accounts: LookupMap::new(StorageKey::StorageAccounts.try_to_vec().unwrap()),
+            registered_tokens: UnorderedMap::new(
+                StorageKey::RegisteredTokens.try_to_vec().unwrap(),
+            ),
+            operators: LookupMap::new(StorageKey::Operators.try_to_vec().unwrap()),
         }
     }
 
@@ -141,13 +202,21 @@ impl Contract {
         protocol_fee: u16,
         rewards: u16,
     ) -> i32 {
-        self.pools.push(Pool::new(
+        for token in [&token1, &token2] {
+            if !self.is_token_registered(token) {
+                self.internal_register_token(token.clone(), None);
+            }
+        }
+        let mut pool = Pool::new(
             token1.clone(),
             token2.clone(),
             initial_price,
             protocol_fee,
             rewards,
-        ));
+        );
+        // the initial price starts the history the TWAP and the settlements read from
+        pool.refresh(env::block_timestamp());
+        self.pools.push(pool);
         assert!(!self.routes.contains_key(&(token1.clone(), token2.clone())));
         assert!(!self.routes.contains_key(&(token2.clone(), token1.clone())));
         let routes = self.routes.clone();
@@ -254,10 +323,35 @@ impl Contract {
         }
     }
 
-    pub fn withdraw(&mut self, token: &AccountId, amount: U128) {
-        let account_id = env::signer_account_id();
+    /// Withdraws to `receiver_id`, or to the account itself if it is not set.
+    /// Requires 1 yoctoNEAR attached.
+    #[payable]
+    pub fn withdraw(
+        &mut self,
+        token: &AccountId,
+        amount: U128,
+        receiver_id: Option<AccountId>,
+    ) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
         let amount: u128 = amount.into();
-        self.balance_withdraw(&account_id, token, amount);
+        let receiver_id = receiver_id.unwrap_or_else(|| account_id.clone());
+        self.balance_withdraw(&account_id, token, amount, &receiver_id)
+    }
+
+    /// Moves tokens to the internal balance of another account with registered storage.
+    /// Requires 1 yoctoNEAR attached.
+    #[payable]
+    pub fn transfer(
+        &mut self,
+        receiver_id: &AccountId,
+        token: &AccountId,
+        amount: U128,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.balance_transfer(&account_id, receiver_id, token, amount.0, memo);
     }
 
     pub fn get_return(&self, pool_id: usize, token_in: &AccountId, amount_in: U128) -> U128 {
@@ -287,21 +381,38 @@ impl Contract {
         token_in: &AccountId,
         amount_in: U128,
         token_out: &AccountId,
+    ) -> U128 {
+        let account_id = env::predecessor_account_id();
+        self.internal_swap(&account_id, pool_id, token_in, amount_in, token_out)
+    }
+
+    pub(crate) fn internal_swap(
+        &mut self,
+        account_id: &AccountId,
+        pool_id: usize,
+        token_in: &AccountId,
+        amount_in: U128,
+        token_out: &AccountId,
     ) -> U128 {
         self.assert_pool_exists(pool_id);
-        let account_id = env::signer_account_id();
+        let initial_storage = self.internal_storage_usage();
         let amount_in: u128 = amount_in.into();
-        self.decrease_balance(&account_id, token_in, amount_in);
+        self.decrease_balance(account_id, token_in, amount_in);
         let pool = &mut self.pools[pool_id];
         let swap_result = pool.get_swap_result(token_in, amount_in, pool::SwapDirection::Return);
         let fees_amount = swap_result.amount * (pool.protocol_fee as f64 + pool.rewards as f64)
             / BASIS_POINT_TO_PERCENT;
+        let protocol_fee_amount =
+            swap_result.amount * pool.protocol_fee as f64 / BASIS_POINT_TO_PERCENT;
+        self.insurance_fund
+            .contribute(token_out, protocol_fee_amount.round() as u128);
         self.apply_collected_fees(&swap_result.collected_fees, token_out);
         let result_amount = swap_result.amount.round() as u128 - fees_amount.round() as u128;
-        self.increase_balance(&account_id, token_out, result_amount);
+        self.increase_balance(account_id, token_out, result_amount);
         let pool = &mut self.pools[pool_id];
         pool.apply_swap_result(&swap_result);
         pool.refresh(env::block_timestamp());
+        self.internal_charge_storage(account_id, initial_storage);
         result_amount.into()
     }
 
@@ -311,24 +422,67 @@ impl Contract {
         amount_in: U128,
         token_out: &AccountId,
     ) -> U128 {
-        let mut amount = amount_in;
+        let account_id = env::predecessor_account_id();
+        self.internal_swap_multihope(&account_id, token_in, amount_in, token_out)
+    }
+
+    pub(crate) fn internal_swap_multihope(
+        &mut self,
+        account_id: &AccountId,
+        token_in: &AccountId,
+        amount_in: U128,
+        token_out: &AccountId,
+    ) -> U128 {
         let route = self
             .routes
             .get(&(token_in.to_string(), token_out.to_string()))
             .expect(SWP0)
             .clone();
-        for pool_id in route {
-            if pool_id > 0 {
-                let pool = self.pools[pool_id as usize].clone();
-                amount = self.swap(pool_id as usize, &pool.token0, amount, &pool.token1);
+        self.internal_swap_route(account_id, &route, amount_in)
+    }
+
+    /// Swaps through the pools of the route, a positive pool id swaps token0 for token1
+    /// in the pool `id - 1`, a negative one token1 for token0 in the pool `-id - 1`
+    pub(crate) fn internal_swap_route(
+        &mut self,
+        account_id: &AccountId,
+        route: &[i32],
+        amount_in: U128,
+    ) -> U128 {
+        let mut amount = amount_in;
+        for &route_id in route {
+            let pool_id = route_id.unsigned_abs() as usize - 1;
+            let pool = self.pools[pool_id].clone();
+            amount = if route_id > 0 {
+                self.internal_swap(account_id, pool_id, &pool.token0, amount, &pool.token1)
             } else {
-                let pool = self.pools[-pool_id as usize].clone();
-                amount = self.swap(-pool_id as usize, &pool.token1, amount, &pool.token0);
-            }
+                self.internal_swap(account_id, pool_id, &pool.token1, amount, &pool.token0)
+            };
         }
         amount
     }
 
+    /// What swapping `amount_in` through the route gives, `None` if a pool of the route
+    /// does not have the liquidity for it
+    pub(crate) fn internal_route_return(&self, route: &[i32], amount_in: u128) -> Option<u128> {
+        let mut amount = amount_in;
+        for &route_id in route {
+            let pool = &self.pools[route_id.unsigned_abs() as usize - 1];
+            let token_in = if route_id > 0 {
+                &pool.token0
+            } else {
+                &pool.token1
+            };
+            let swap_result =
+                pool.try_get_swap_result(token_in, amount, pool::SwapDirection::Return)?;
+            let fees_amount = swap_result.amount
+                * (pool.protocol_fee as f64 + pool.rewards as f64)
+                / BASIS_POINT_TO_PERCENT;
+            amount = swap_result.amount.round() as u128 - fees_amount.round() as u128;
+        }
+        Some(amount)
+    }
+
     pub fn open_position(
         &mut self,
         pool_id: usize,
@@ -336,47 +490,131 @@ impl Contract {
         token1_liquidity: Option<U128>,
         lower_bound_price: f64,
         upper_bound_price: f64,
+    ) -> u128 {
+        let account_id = env::predecessor_account_id();
+        self.internal_open_position(
+            &account_id,
+            pool_id,
+            token0_liquidity,
+            token1_liquidity,
+            lower_bound_price,
+            upper_bound_price,
+        )
+    }
+
+    pub(crate) fn internal_open_position(
+        &mut self,
+        account_id: &AccountId,
+        pool_id: usize,
+        token0_liquidity: Option<U128>,
+        token1_liquidity: Option<U128>,
+        lower_bound_price: f64,
+        upper_bound_price: f64,
     ) -> u128 {
         self.assert_pool_exists(pool_id);
-        let position_id = self.positions_opened;
-        self.positions_opened += 1;
-        let pool = &self.pools[pool_id];
-        let account_id = env::signer_account_id();
         let position = Position::new(
             account_id.clone(),
             token0_liquidity,
             token1_liquidity,
             lower_bound_price,
             upper_bound_price,
-            pool.sqrt_price,
+            self.pools[pool_id].sqrt_price,
+        );
+        self.internal_mint_position(account_id, pool_id, position)
+    }
+
+    /// Opens a position with the largest liquidity that fits in the max amounts of both
+    /// tokens, the rest stays in the balance. Returns position id.
+    pub fn open_position_with_amounts(&mut self, action: OpenPositionWithAmountsAction) -> u128 {
+        let account_id = env::predecessor_account_id();
+        self.internal_open_position_with_amounts(&account_id, &action)
+    }
+
+    pub(crate) fn internal_open_position_with_amounts(
+        &mut self,
+        account_id: &AccountId,
+        action: &OpenPositionWithAmountsAction,
+    ) -> u128 {
+        let OpenPositionWithAmountsAction {
+            pool_id,
+            max_amount0,
+            max_amount1,
+            min_amount0,
+            min_amount1,
+            lower_bound_price,
+            upper_bound_price,
+        } = *action;
+        self.assert_pool_exists(pool_id);
+        let position = Position::with_max_amounts(
+            account_id.clone(),
+            max_amount0.0,
+            max_amount1.0,
+            lower_bound_price,
+            upper_bound_price,
+            self.pools[pool_id].sqrt_price,
         );
+        assert!(
+            position.token0_locked.round() as u128 >= min_amount0.0
+                && position.token1_locked.round() as u128 >= min_amount1.0,
+            "{}",
+            PST7
+        );
+        self.internal_mint_position(account_id, pool_id, position)
+    }
+
+    /// Takes the locked tokens from the balance, adds the position to the pool
+    /// and mints its NFT. Returns position id.
+    fn internal_mint_position(
+        &mut self,
+        account_id: &AccountId,
+        pool_id: usize,
+        position: Position,
+    ) -> u128 {
+        let initial_storage = self.internal_storage_usage();
+        let position_id = self.positions_opened;
+        self.positions_opened += 1;
+        let pool = &self.pools[pool_id];
         let token0 = pool.token0.clone();
         let token1 = pool.token1.clone();
-        self.decrease_balance(&account_id, &token0, position.token0_locked.round() as u128);
-        self.decrease_balance(&account_id, &token1, position.token1_locked.round() as u128);
+        self.decrease_balance(account_id, &token0, position.token0_locked.round() as u128);
+        self.decrease_balance(account_id, &token1, position.token1_locked.round() as u128);
         let pool = &mut self.pools[pool_id];
         pool.open_position(position_id, position.clone());
         pool.refresh(env::block_timestamp());
         let metadata = TokenMetadata::new(pool_id, position_id, &position);
         self.nft_mint(position_id.to_string(), account_id.clone(), metadata);
+        self.internal_charge_storage(account_id, initial_storage);
         position_id
     }
 
     pub fn close_position(&mut self, pool_id: usize, position_id: u128) {
+        let account_id = env::predecessor_account_id();
+        self.internal_close_position(&account_id, pool_id, position_id);
+    }
+
+    /// Returns the amounts of token0 and token1 given back to the balance
+    pub(crate) fn internal_close_position(
+        &mut self,
+        account_id: &AccountId,
+        pool_id: usize,
+        position_id: u128,
+    ) -> (U128, U128) {
         self.assert_pool_exists(pool_id);
+        let initial_storage = self.internal_storage_usage();
         let pool = &self.pools[pool_id];
-        let account_id = env::predecessor_account_id();
         let token = self.tokens_by_id.get(&position_id.to_string()).expect(NFT0);
-        Self::assert_account_owns_nft(&account_id, &token.owner_id);
+        Self::assert_account_owns_nft(account_id, &token.owner_id);
         let position = pool.positions.get(&position_id).expect(PST0);
         let amount0 = position.token0_locked.round() as u128;
         let amount1 = position.token1_locked.round() as u128;
         let token0 = pool.token0.clone();
         let token1 = pool.token1.clone();
-        self.increase_balance(&account_id, &token0, amount0);
-        self.increase_balance(&account_id, &token1, amount1);
+        self.increase_balance(account_id, &token0, amount0);
+        self.increase_balance(account_id, &token1, amount1);
         let pool = &mut self.pools[pool_id];
         pool.close_position(position_id);
+        self.internal_charge_storage(account_id, initial_storage);
+        (U128(amount0), U128(amount1))
     }
 
     pub fn add_liquidity(
@@ -385,12 +623,30 @@ impl Contract {
         position_id: u128,
         token0_liquidity: Option<U128>,
         token1_liquidity: Option<U128>,
+    ) {
+        let account_id = env::predecessor_account_id();
+        self.internal_add_liquidity(
+            &account_id,
+            pool_id,
+            position_id,
+            token0_liquidity,
+            token1_liquidity,
+        )
+    }
+
+    pub(crate) fn internal_add_liquidity(
+        &mut self,
+        account_id: &AccountId,
+        pool_id: usize,
+        position_id: u128,
+        token0_liquidity: Option<U128>,
+        token1_liquidity: Option<U128>,
     ) {
         self.assert_pool_exists(pool_id);
+        let initial_storage = self.internal_storage_usage();
         let pool = &mut self.pools[pool_id];
-        let account_id = env::predecessor_account_id();
         let token = self.tokens_by_id.get(&position_id.to_string()).expect(NFT0);
-        Self::assert_account_owns_nft(&account_id, &token.owner_id);
+        Self::assert_account_owns_nft(account_id, &token.owner_id);
         let mut position = pool.positions.get(&position_id).expect(PST0).clone();
         let token0_locked_before = position.token0_locked as u128;
         let token1_locked_before = position.token1_locked as u128;
@@ -402,15 +658,65 @@ impl Contract {
         let token0 = pool.token0.to_string();
         let token1 = pool.token1.to_string();
         self.decrease_balance(
-            &account_id,
+            account_id,
             &token0,
             token0_locked_after - token0_locked_before,
         );
         self.decrease_balance(
-            &account_id,
+            account_id,
             &token1,
             token1_locked_after - token1_locked_before,
         );
+        self.internal_charge_storage(account_id, initial_storage);
+    }
+
+    /// Adds the largest liquidity that fits in the max amounts of both tokens to the
+    /// position, the rest stays in the balance. Returns the amounts of token0 and token1 added.
+    pub fn add_liquidity_with_amounts(
+        &mut self,
+        action: AddLiquidityWithAmountsAction,
+    ) -> (U128, U128) {
+        let account_id = env::predecessor_account_id();
+        self.internal_add_liquidity_with_amounts(&account_id, &action)
+    }
+
+    pub(crate) fn internal_add_liquidity_with_amounts(
+        &mut self,
+        account_id: &AccountId,
+        action: &AddLiquidityWithAmountsAction,
+    ) -> (U128, U128) {
+        let AddLiquidityWithAmountsAction {
+            pool_id,
+            position_id,
+            max_amount0,
+            max_amount1,
+            min_amount0,
+            min_amount1,
+        } = *action;
+        self.assert_pool_exists(pool_id);
+        let initial_storage = self.internal_storage_usage();
+        let pool = &mut self.pools[pool_id];
+        let token = self.tokens_by_id.get(&position_id.to_string()).expect(NFT0);
+        Self::assert_account_owns_nft(account_id, &token.owner_id);
+        let mut position = pool.positions.get(&position_id).expect(PST0).clone();
+        let token0_locked_before = position.token0_locked.round() as u128;
+        let token1_locked_before = position.token1_locked.round() as u128;
+        position.add_liquidity_with_max_amounts(max_amount0.0, max_amount1.0, pool.sqrt_price);
+        let amount0 = position.token0_locked.round() as u128 - token0_locked_before;
+        let amount1 = position.token1_locked.round() as u128 - token1_locked_before;
+        assert!(
+            amount0 >= min_amount0.0 && amount1 >= min_amount1.0,
+            "{}",
+            PST7
+        );
+        pool.positions.insert(position_id, position);
+        pool.refresh(env::block_timestamp());
+        let token0 = pool.token0.to_string();
+        let token1 = pool.token1.to_string();
+        self.decrease_balance(account_id, &token0, amount0);
+        self.decrease_balance(account_id, &token1, amount1);
+        self.internal_charge_storage(account_id, initial_storage);
+        (U128(amount0), U128(amount1))
     }
 
     pub fn remove_liquidity(
@@ -420,11 +726,29 @@ impl Contract {
         token0_liquidity: Option<U128>,
         token1_liquidity: Option<U128>,
     ) {
+        let account_id = env::predecessor_account_id();
+        self.internal_remove_liquidity(
+            &account_id,
+            pool_id,
+            position_id,
+            token0_liquidity,
+            token1_liquidity,
+        );
+    }
+
+    /// Returns the amounts of token0 and token1 given back to the balance
+    pub(crate) fn internal_remove_liquidity(
+        &mut self,
+        account_id: &AccountId,
+        pool_id: usize,
+        position_id: u128,
+        token0_liquidity: Option<U128>,
+        token1_liquidity: Option<U128>,
+    ) -> (U128, U128) {
         self.assert_pool_exists(pool_id);
         let pool = &mut self.pools[pool_id];
-        let account_id = env::predecessor_account_id();
         let token = self.tokens_by_id.get(&position_id.to_string()).expect(NFT0);
-        Self::assert_account_owns_nft(&account_id, &token.owner_id);
+        Self::assert_account_owns_nft(account_id, &token.owner_id);
         let mut position = pool.positions.get(&position_id).expect(PST0).clone();
         let token0_locked_before = position.token0_locked as u128;
         let token1_locked_before = position.token1_locked as u128;
@@ -435,30 +759,120 @@ impl Contract {
         pool.refresh(env::block_timestamp());
         let token0 = pool.token0.to_string();
         let token1 = pool.token1.to_string();
-        self.increase_balance(
-            &account_id,
-            &token0,
-            token0_locked_before - token0_locked_after,
+        let amount0 = token0_locked_before - token0_locked_after;
+        let amount1 = token1_locked_before - token1_locked_after;
+        self.increase_balance(account_id, &token0, amount0);
+        self.increase_balance(account_id, &token1, amount1);
+        (U128(amount0), U128(amount1))
+    }
+
+    /// Removes `basis_points` of the position liquidity or `liquidity` units of it, and fails
+    /// if less than the min amounts would be given back. Removing all the liquidity closes
+    /// the position and burns its NFT. Returns the amounts of token0 and token1 given back.
+    pub fn remove_liquidity_share(&mut self, action: RemoveLiquidityShareAction) -> (U128, U128) {
+        let account_id = env::predecessor_account_id();
+        self.internal_remove_liquidity_share(&account_id, &action)
+    }
+
+    pub(crate) fn internal_remove_liquidity_share(
+        &mut self,
+        account_id: &AccountId,
+        action: &RemoveLiquidityShareAction,
+    ) -> (U128, U128) {
+        let RemoveLiquidityShareAction {
+            pool_id,
+            position_id,
+            basis_points,
+            liquidity,
+            min_amount0,
+            min_amount1,
+        } = *action;
+        self.assert_pool_exists(pool_id);
+        let token = self.tokens_by_id.get(&position_id.to_string()).expect(NFT0);
+        Self::assert_account_owns_nft(account_id, &token.owner_id);
+        assert!(
+            self.find_borrow_by_position(position_id).is_none(),
+            "{}",
+            PST11
         );
-        self.increase_balance(
-            &account_id,
-            &token1,
-            token1_locked_before - token1_locked_after,
+        let pool = &mut self.pools[pool_id];
+        let mut position = pool.positions.get(&position_id).expect(PST0).clone();
+        let share = match (basis_points, liquidity) {
+            (Some(basis_points), None) => basis_points as f64 / BASIS_POINT_TO_PERCENT,
+            (None, Some(liquidity)) => liquidity / position.liquidity,
+            _ => panic!("{}", PST8),
+        };
+        assert!(share > 0.0 && share <= 1.0, "{}", PST9);
+        let (amount0, amount1) = if share == 1.0 {
+            let (amount0, amount1) =
+                self.internal_close_position(account_id, pool_id, position_id);
+            let initial_storage = self.internal_storage_usage();
+            self.internal_burn(&position_id.to_string());
+            self.internal_charge_storage(account_id, initial_storage);
+            (amount0.0, amount1.0)
+        } else {
+            let token0_locked_before = position.token0_locked.round() as u128;
+            let token1_locked_before = position.token1_locked.round() as u128;
+            position.remove_liquidity_share(share, pool.sqrt_price);
+            let amount0 = token0_locked_before - position.token0_locked.round() as u128;
+            let amount1 = token1_locked_before - position.token1_locked.round() as u128;
+            pool.positions.insert(position_id, position);
+            pool.refresh(env::block_timestamp());
+            let token0 = pool.token0.clone();
+            let token1 = pool.token1.clone();
+            self.increase_balance(account_id, &token0, amount0);
+            self.increase_balance(account_id, &token1, amount1);
+            (amount0, amount1)
+        };
+        assert!(
+            amount0 >= min_amount0.0 && amount1 >= min_amount1.0,
+            "{}",
+            PST10
         );
+        (U128(amount0), U128(amount1))
     }
 
     #[private]
-    pub fn create_reserve(&mut self, reserve_token: &AccountId) {
-        let reserve = Reserve::default();
+    pub fn create_reserve(&mut self, reserve_token: &AccountId, config: Option<ReserveConfig>) {
+        let config = config.unwrap_or_default();
+        config.assert_valid();
+        let reserve = Reserve::new(config);
         self.reserves.insert(reserve_token, &reserve);
+        if !self.is_token_registered(reserve_token) {
+            self.internal_register_token(reserve_token.clone(), None);
+        }
+    }
+
+    #[private]
+    pub fn set_reserve_config(&mut self, reserve_token: &AccountId, config: ReserveConfig) {
+        config.assert_valid();
+        let mut reserve = self.reserves.get(reserve_token).expect(RSR0);
+        reserve.config = config;
+        self.reserves.insert(reserve_token, &reserve);
+    }
+
+    pub fn get_reserve_config(&self, reserve_token: &AccountId) -> ReserveConfig {
+        self.reserves.get(reserve_token).expect(RSR0).config
     }
 
     pub fn create_deposit(&mut self, asset: &AccountId, amount: U128) {
         let account_id = env::predecessor_account_id();
+        self.internal_create_deposit(&account_id, asset, amount)
+    }
+
+    pub(crate) fn internal_create_deposit(
+        &mut self,
+        account_id: &AccountId,
+        asset: &AccountId,
+        amount: U128,
+    ) {
+        let initial_storage = self.internal_storage_usage();
         let timestamp = env::block_timestamp();
-        if let Some(map) = self.deposits.get(&account_id) {
+        let mut reserve = self.reserves.get(&asset).expect(RSR0);
+        let supply_index = reserve.supply_index;
+        if let Some(map) = self.deposits.get(account_id) {
             if let Some(deposit) = map.get(asset) {
-                let old_amount = deposit.amount;
+                let old_amount = deposit.current_amount(supply_index);
                 let old_growth = deposit.growth;
                 let deposit = Deposit {
                     owner_id: account_id.clone(),
@@ -468,10 +882,11 @@ impl Contract {
                     last_update_timestamp: timestamp,
                     apr: APR_DEPOSIT,
                     growth: old_growth,
+                    supply_index,
                 };
                 let mut map = map.clone();
                 map.insert(asset.clone(), deposit);
-                self.deposits.insert(account_id.clone(), map);
+                self.deposits.insert(account_id, &map);
             } else {
                 let deposit = Deposit {
                     owner_id: account_id.clone(),
@@ -481,10 +896,11 @@ impl Contract {
                     last_update_timestamp: timestamp,
                     apr: APR_DEPOSIT,
                     growth: 0,
+                    supply_index,
                 };
                 let mut map = map.clone();
                 map.insert(asset.clone(), deposit);
-                self.deposits.insert(account_id.clone(), map.clone());
+                self.deposits.insert(account_id, &map);
             }
         } else {
             let deposit = Deposit {
@@ -495,24 +911,37 @@ impl Contract {
                 last_update_timestamp: timestamp,
                 apr: APR_DEPOSIT,
                 growth: 0,
+                supply_index,
             };
             let mut map = HashMap::new();
             map.insert(asset.clone(), deposit);
-            self.deposits.insert(account_id.clone(), map);
+            self.deposits.insert(account_id, &map);
         }
-        self.decrease_balance(&account_id, &asset.to_string(), amount.0);
+        self.decrease_balance(account_id, &asset.to_string(), amount.0);
 
-        let mut reserve = self.reserves.get(&asset).expect(RSR0);
         reserve.increase_deposit(amount.0);
         self.reserves.insert(&asset, &reserve);
+        self.internal_charge_storage(account_id, initial_storage);
     }
 
     pub fn close_deposit(&mut self, asset: &AccountId, amount: U128) {
         let account_id = env::predecessor_account_id();
+        self.internal_close_deposit(&account_id, asset, amount);
+    }
+
+    pub(crate) fn internal_close_deposit(
+        &mut self,
+        account_id: &AccountId,
+        asset: &AccountId,
+        amount: U128,
+    ) {
+        let initial_storage = self.internal_storage_usage();
         let timestamp = env::block_timestamp();
-        let map = self.deposits.get(&account_id).unwrap();
+        let map = self.deposits.get(account_id).unwrap();
         let deposit = map.get(asset).unwrap();
-        let old_amount = deposit.amount;
+        let mut reserve = self.reserves.get(&asset).expect(RSR0);
+        let supply_index = reserve.supply_index;
+        let old_amount = deposit.current_amount(supply_index);
         assert!(old_amount >= amount.0);
         let old_growth = deposit.growth;
         let deposit = Deposit {
@@ -523,23 +952,25 @@ impl Contract {
             last_update_timestamp: timestamp,
             apr: APR_DEPOSIT,
             growth: old_growth,
+            supply_index,
         };
         let mut map = map.clone();
         map.insert(asset.clone(), deposit);
-        self.deposits.insert(account_id.clone(), map.clone());
-        self.increase_balance(&account_id, &asset.to_string(), amount.0);
+        self.deposits.insert(account_id, &map);
+        self.increase_balance(account_id, &asset.to_string(), amount.0);
 
-        let mut reserve = self.reserves.get(&asset).expect(RSR0);
-        reserve.decrease_deposit(amount.0);
+        reserve.decrease_deposit(u128::min(amount.0, reserve.deposited));
         self.reserves.insert(&asset, &reserve);
+        self.internal_charge_storage(account_id, initial_storage);
     }
 
     pub fn refresh_deposits_growth(&mut self) {
         let current_timestamp = env::block_timestamp();
-        for (_, map) in &mut self.deposits {
-            for (_, deposit) in map {
+        for (account_id, mut map) in self.deposits.to_vec() {
+            for (_, deposit) in map.iter_mut() {
                 deposit.refresh_growth(current_timestamp);
             }
+            self.deposits.insert(&account_id, &map);
         }
     }
 
@@ -560,8 +991,17 @@ impl Contract {
     //     0.into()
     // }
 
+    /// Deposits of the account with amounts reduced by the bad debt written off since
+    /// they were made, plus the interest accrued over the same time
     pub fn get_account_deposits(&self, account_id: AccountId) -> HashMap<TokenId, Deposit> {
-        self.deposits.get(&account_id).unwrap().clone()
+        let mut deposits = self.deposits.get(&account_id).unwrap();
+        for (asset, deposit) in deposits.iter_mut() {
+            if let Some(reserve) = self.reserves.get(asset) {
+                deposit.amount = deposit.current_amount(reserve.supply_index);
+                deposit.supply_index = reserve.supply_index;
+            }
+        }
+        deposits
     }
 
     // #[payable]
@@ -619,8 +1059,27 @@ impl Contract {
         position_id: u128,
         leverage: f64,
     ) {
-        assert!(leverage > 1.0);
         let account_id = env::predecessor_account_id();
+        self.internal_supply_collateral_and_borrow(&account_id, pool_id, position_id, leverage);
+    }
+
+    pub(crate) fn internal_supply_collateral_and_borrow(
+        &mut self,
+        account_id: &AccountId,
+        pool_id: usize,
+        position_id: u128,
+        leverage: f64,
+    ) -> BorrowId {
+        self.assert_pool_exists(pool_id);
+        let token = self.tokens_by_id.get(&position_id.to_string()).expect(NFT0);
+        Self::assert_account_owns_nft(account_id, &token.owner_id);
+        assert!(leverage > 1.0);
+        assert!(
+            self.find_borrow_by_position(position_id).is_none(),
+            "{}",
+            BRR4
+        );
+        let initial_storage = self.internal_storage_usage();
         let pool = &mut self.pools[pool_id];
         let token0 = pool.token0.clone();
         let token1 = pool.token1.clone();
@@ -629,14 +1088,15 @@ impl Contract {
         let borrowed1 = (position.token1_locked * (leverage - 1.0)) as u128;
 
         let mut reserve = self.reserves.get(&token0).expect(RSR0);
-        reserve.borrowed += borrowed0;
-        assert!(reserve.deposited >= reserve.borrowed);
+        reserve.borrow(&token0, borrowed0);
         self.reserves.insert(&token0, &reserve);
 
         let mut reserve = self.reserves.get(&token1).expect(RSR0);
-        reserve.borrowed += borrowed1;
-        assert!(reserve.deposited >= reserve.borrowed);
+        reserve.borrow(&token1, borrowed1);
         self.reserves.insert(&token1, &reserve);
+        let (_, liquidation_threshold) = self.internal_risk_params(pool_id);
+
+        let pool = &mut self.pools[pool_id];
 
         let mut position = pool.positions.get(&position_id).expect(PST0).clone();
         position.add_liquidity(
@@ -644,12 +1104,16 @@ impl Contract {
             None,
             pool.sqrt_price,
         );
-        let liquidation_price = position.get_liquidation_price(borrowed0 as f64, borrowed1 as f64, LTV_MAX);
+        let liquidation_price = position.get_liquidation_price(
+            borrowed0 as f64,
+            borrowed1 as f64,
+            liquidation_threshold,
+        );
         pool.positions.insert(position_id, position);
 
         let borrow = Borrow {
             id: self.borrows_number,
-            owner_id: account_id,
+            owner_id: account_id.clone(),
             asset0: token0,
             asset1: token1,
             borrowed0,
@@ -665,8 +1129,8 @@ impl Contract {
         self.borrows.insert(&self.borrows_number, &borrow);
         self.borrows_number += 1;
         // Make sure the loan is sufficiently overcollateralized
-        let health_factor = self.get_borrow_health_factor(borrow.id);
-        assert!(health_factor >= 1.0);
+        self.assert_loan_to_value(&borrow);
+        self.internal_charge_storage(&borrow.owner_id, initial_storage);
 
         // commented out for now because when NFT is tranferred away from the owner
         // other functions will fail without it, including return_collateral_and_repay()
@@ -677,26 +1141,40 @@ impl Contract {
         //     None,
         //     None,
         // );
+        borrow.id
     }
 
     pub fn return_collateral_and_repay(&mut self, borrow_id: u128) {
         let account_id = env::predecessor_account_id();
+        self.internal_return_collateral_and_repay(&account_id, borrow_id)
+    }
+
+    pub(crate) fn internal_return_collateral_and_repay(
+        &mut self,
+        account_id: &AccountId,
+        borrow_id: u128,
+    ) {
+        let initial_storage = self.internal_storage_usage();
         let borrow = self.borrows.remove(&borrow_id).expect(BRR0);
-        let pool = &self.pools[borrow.pool_id];
-        let position = pool.positions.get(&borrow.position_id).expect(PST0);
-        assert_eq!(account_id, borrow.owner_id);
+        self.pools[borrow.pool_id]
+            .positions
+            .get(&borrow.position_id)
+            .expect(PST0);
+        assert_eq!(account_id, &borrow.owner_id);
         let mut reserve = self.reserves.get(&borrow.asset0).expect(RSR0);
         reserve.borrowed -= borrow.borrowed0;
         self.reserves.insert(&borrow.asset0, &reserve);
         let mut reserve = self.reserves.get(&borrow.asset1).expect(RSR0);
         reserve.borrowed -= borrow.borrowed1;
         self.reserves.insert(&borrow.asset1, &reserve);
-        self.remove_liquidity(
+        self.internal_remove_liquidity(
+            account_id,
             borrow.pool_id,
             borrow.position_id,
             None,
             Some(U128::from(borrow.borrowed1 + borrow.fees)),
         );
+        self.internal_charge_storage(account_id, initial_storage);
         // ext_self::nft_transfer(
         //     account_id,
         //     borrow.position_id.to_string(),
@@ -708,11 +1186,296 @@ impl Contract {
         // );
     }
 
-    pub fn get_liquidation_list(&self) -> Vec<BorrowId> {
+    /// Repays part of the loan from the owner's balance.
+    /// Accrued fees are covered first out of `amount1`, the rest goes to the principal.
+    /// The borrow is removed once nothing is left to repay.
+    pub fn repay_partial(&mut self, borrow_id: BorrowId, amount0: U128, amount1: U128) {
+        let account_id = env::predecessor_account_id();
+        self.internal_repay_partial(&account_id, borrow_id, amount0, amount1);
+    }
+
+    pub(crate) fn internal_repay_partial(
+        &mut self,
+        account_id: &AccountId,
+        borrow_id: BorrowId,
+        amount0: U128,
+        amount1: U128,
+    ) {
+        let mut borrow = self.borrows.get(&borrow_id).expect(BRR0);
+        assert_eq!(account_id, &borrow.owner_id, "{}", BRR1);
+        self.internal_repay(account_id, &mut borrow, amount0.0, amount1.0);
+        self.internal_refresh_borrow(borrow);
+    }
+
+    /// Removes liquidity from the leveraged position and uses it to repay the loan
+    /// until the position leverage drops to `target_leverage`.
+    /// `target_leverage` of 1.0 repays the loan completely.
+    pub fn reduce_leverage(&mut self, borrow_id: BorrowId, target_leverage: f64) {
+        let account_id = env::predecessor_account_id();
+        self.internal_reduce_leverage(&account_id, borrow_id, target_leverage);
+    }
+
+    pub(crate) fn internal_reduce_leverage(
+        &mut self,
+        account_id: &AccountId,
+        borrow_id: BorrowId,
+        target_leverage: f64,
+    ) {
+        let mut borrow = self.borrows.get(&borrow_id).expect(BRR0);
+        assert_eq!(account_id, &borrow.owner_id, "{}", BRR1);
+        borrow.refresh_fees(env::block_timestamp());
+        borrow.update_timestamp(env::block_timestamp());
+        let position = self.pools[borrow.pool_id]
+            .positions
+            .get(&borrow.position_id)
+            .expect(PST0)
+            .clone();
+        let (debt, value) = self.internal_borrow_values(&borrow);
+        let equity = value - debt;
+        assert!(equity > 0.0, "{}", BRR3);
+        assert!(
+            target_leverage >= 1.0 && target_leverage < value / equity,
+            "{}",
+            BRR2
+        );
+        let repay_value = debt - equity * (target_leverage - 1.0);
+        let share = repay_value / debt;
+        let repay0 = (borrow.borrowed0 as f64 * share).round() as u128;
+        let repay1 = (borrow.borrowed1 as f64 * share).round() as u128;
+        let fraction = repay_value / value;
+        let (token0_liquidity, token1_liquidity) = if position.token0_locked > 0.0 {
+            (Some(U128((position.token0_locked * fraction) as u128)), None)
+        } else {
+            (None, Some(U128((position.token1_locked * fraction) as u128)))
+        };
+        self.internal_remove_liquidity(
+            account_id,
+            borrow.pool_id,
+            borrow.position_id,
+            token0_liquidity,
+            token1_liquidity,
+        );
+        let fees = borrow.fees;
+        self.internal_repay(account_id, &mut borrow, repay0, repay1 + fees);
+        self.internal_refresh_borrow(borrow);
+    }
+
+    /// Adds liquidity from the owner's balance to the leveraged position without borrowing more,
+    /// lowering its leverage.
+    pub fn add_collateral(
+        &mut self,
+        borrow_id: BorrowId,
+        token0_liquidity: Option<U128>,
+        token1_liquidity: Option<U128>,
+    ) {
+        let account_id = env::predecessor_account_id();
+        self.internal_add_collateral(&account_id, borrow_id, token0_liquidity, token1_liquidity);
+    }
+
+    pub(crate) fn internal_add_collateral(
+        &mut self,
+        account_id: &AccountId,
+        borrow_id: BorrowId,
+        token0_liquidity: Option<U128>,
+        token1_liquidity: Option<U128>,
+    ) {
+        let borrow = self.borrows.get(&borrow_id).expect(BRR0);
+        assert_eq!(account_id, &borrow.owner_id, "{}", BRR1);
+        self.internal_add_liquidity(
+            account_id,
+            borrow.pool_id,
+            borrow.position_id,
+            token0_liquidity,
+            token1_liquidity,
+        );
+        self.internal_refresh_borrow(borrow);
+    }
+
+    /// Borrows more from the reserves and adds it to the position
+    /// until the position leverage reaches `new_leverage`.
+    pub fn increase_leverage(&mut self, borrow_id: BorrowId, new_leverage: f64) {
+        let account_id = env::predecessor_account_id();
+        self.internal_increase_leverage(&account_id, borrow_id, new_leverage);
+    }
+
+    pub(crate) fn internal_increase_leverage(
+        &mut self,
+        account_id: &AccountId,
+        borrow_id: BorrowId,
+        new_leverage: f64,
+    ) {
+        let initial_storage = self.internal_storage_usage();
+        let mut borrow = self.borrows.get(&borrow_id).expect(BRR0);
+        assert_eq!(account_id, &borrow.owner_id, "{}", BRR1);
+        borrow.refresh_fees(env::block_timestamp());
+        borrow.update_timestamp(env::block_timestamp());
+        let position = self.pools[borrow.pool_id]
+            .positions
+            .get(&borrow.position_id)
+            .expect(PST0)
+            .clone();
+        let max_leverage = self.get_max_leverage(
+            borrow.pool_id,
+            position.sqrt_lower_bound_price.powi(2),
+            position.sqrt_upper_bound_price.powi(2),
+        );
+        assert!(new_leverage <= max_leverage, "{}", BRR6);
+        let (debt, value) = self.internal_borrow_values(&borrow);
+        let equity = value - debt;
+        assert!(equity > 0.0, "{}", BRR3);
+        assert!(new_leverage > value / equity, "{}", BRR5);
+        let fraction = (equity * (new_leverage - 1.0) - debt) / value;
+        let borrowed0 = (position.token0_locked * fraction) as u128;
+        let borrowed1 = (position.token1_locked * fraction) as u128;
+
+        let mut reserve = self.reserves.get(&borrow.asset0).expect(RSR0);
+        reserve.borrow(&borrow.asset0, borrowed0);
+        self.reserves.insert(&borrow.asset0, &reserve);
+
+        let mut reserve = self.reserves.get(&borrow.asset1).expect(RSR0);
+        reserve.borrow(&borrow.asset1, borrowed1);
+        self.reserves.insert(&borrow.asset1, &reserve);
+
+        let pool = &mut self.pools[borrow.pool_id];
+        let mut position = position;
+        if position.token0_locked > 0.0 {
+            position.add_liquidity(Some(U128(borrowed0)), None, pool.sqrt_price);
+        } else {
+            position.add_liquidity(None, Some(U128(borrowed1)), pool.sqrt_price);
+        }
+        pool.positions.insert(borrow.position_id, position);
+        pool.refresh(env::block_timestamp());
+
+        borrow.borrowed0 += borrowed0;
+        borrow.borrowed1 += borrowed1;
+        self.assert_loan_to_value(&borrow);
+        self.internal_refresh_borrow(borrow);
+        self.internal_charge_storage(account_id, initial_storage);
+    }
+
+    /// Loan to value and liquidation threshold for borrows in the pool,
+    /// the stricter of the two reserves applies
+    fn internal_risk_params(&self, pool_id: usize) -> (f64, f64) {
+        let pool = &self.pools[pool_id];
+        let config0 = self.reserves.get(&pool.token0).expect(RSR0).config;
+        let config1 = self.reserves.get(&pool.token1).expect(RSR0).config;
+        (
+            f64::min(config0.loan_to_value, config1.loan_to_value),
+            f64::min(config0.liquidation_threshold, config1.liquidation_threshold),
+        )
+    }
+
+    fn assert_loan_to_value(&self, borrow: &Borrow) {
+        let (loan_to_value, _) = self.internal_risk_params(borrow.pool_id);
+        let (debt, collateral) = self.internal_borrow_values(borrow);
+        assert!(loan_to_value * collateral >= debt, "{}", BRR3);
+    }
+
+    /// Values the debt and the collateral position of the borrow in token1
+    /// at the price of the configured price source
+    fn internal_borrow_values(&self, borrow: &Borrow) -> (f64, f64) {
+        let price = self
+            .internal_valuation_price(borrow.pool_id)
+            .unwrap_or_else(|error| env::panic(error.as_bytes()));
+        self.internal_borrow_values_at(borrow, price)
+    }
+
+    fn internal_borrow_values_at(&self, borrow: &Borrow, price: f64) -> (f64, f64) {
+        let pool = &self.pools[borrow.pool_id];
+        let position = pool.positions.get(&borrow.position_id).expect(PST0);
+        let debt = borrow.borrowed0 as f64 * price + borrow.borrowed1 as f64;
+        (debt, position.value_at(price.sqrt()))
+    }
+
+    fn find_borrow_by_position(&self, position_id: u128) -> Option<Borrow> {
         self.borrows
             .iter()
-            .filter(|(id, _)| self.get_borrow_health_factor(*id) < 1.0)
-            .map(|(id, _)| id)
+            .find(|(_, borrow)| borrow.position_id == position_id)
+            .map(|(_, borrow)| borrow)
+    }
+
+    fn internal_repay(
+        &mut self,
+        account_id: &AccountId,
+        borrow: &mut Borrow,
+        amount0: u128,
+        amount1: u128,
+    ) {
+        borrow.refresh_fees(env::block_timestamp());
+        borrow.update_timestamp(env::block_timestamp());
+        let repaid0 = u128::min(amount0, borrow.borrowed0);
+        let repaid_fees = u128::min(amount1, borrow.fees);
+        let repaid1 = u128::min(amount1 - repaid_fees, borrow.borrowed1);
+        if repaid0 > 0 {
+            self.decrease_balance(account_id, &borrow.asset0, repaid0);
+        }
+        if repaid_fees + repaid1 > 0 {
+            self.decrease_balance(account_id, &borrow.asset1, repaid_fees + repaid1);
+        }
+        borrow.borrowed0 -= repaid0;
+        borrow.borrowed1 -= repaid1;
+        borrow.fees -= repaid_fees;
+        self.insurance_fund.contribute(&borrow.asset1, repaid_fees);
+        let mut reserve = self.reserves.get(&borrow.asset0).expect(RSR0);
+        reserve.borrowed -= repaid0;
+        reserve.refresh_utilization_rate();
+        self.reserves.insert(&borrow.asset0, &reserve);
+        let mut reserve = self.reserves.get(&borrow.asset1).expect(RSR0);
+        reserve.borrowed -= repaid1;
+        reserve.protocol_fees +=
+            repaid_fees * reserve.config.reserve_factor as u128 / BASIS_POINT_BASE as u128;
+        reserve.refresh_utilization_rate();
+        self.reserves.insert(&borrow.asset1, &reserve);
+    }
+
+    /// Recomputes leverage and liquidation prices of the borrow against the current position
+    /// and stores it, or removes it if the loan has been repaid completely.
+    fn internal_refresh_borrow(&mut self, mut borrow: Borrow) {
+        if borrow.borrowed0 == 0 && borrow.borrowed1 == 0 && borrow.fees == 0 {
+            self.borrows.remove(&borrow.id);
+            return;
+        }
+        let (debt, value) = self.internal_borrow_values(&borrow);
+        if debt < value {
+            borrow.leverage = value / (value - debt);
+        }
+        let (_, liquidation_threshold) = self.internal_risk_params(borrow.pool_id);
+        let position = self.pools[borrow.pool_id]
+            .positions
+            .get(&borrow.position_id)
+            .expect(PST0);
+        // liquidation prices are only defined for a loan in both tokens
+        borrow.liquidation_price = if borrow.borrowed0 > 0 && borrow.borrowed1 > 0 {
+            position.get_liquidation_price(
+                borrow.borrowed0 as f64,
+                borrow.borrowed1 as f64,
+                liquidation_threshold,
+            )
+        } else {
+            (0.0, 0.0)
+        };
+        self.borrows.insert(&borrow.id, &borrow);
+    }
+
+    /// Borrows with the health factor below 1 and perpetuals below the maintenance margin,
+    /// borrows in pools without a valid price are skipped
+    pub fn get_liquidation_list(&self) -> Vec<LiquidationTarget> {
+        self.borrows
+            .iter()
+            .filter(|(_, borrow)| match self.internal_valuation_price(borrow.pool_id) {
+                Ok(price) => {
+                    let (_, liquidation_threshold) = self.internal_risk_params(borrow.pool_id);
+                    let (debt, collateral) = self.internal_borrow_values_at(borrow, price);
+                    liquidation_threshold * collateral < debt
+                }
+                Err(_) => false,
+            })
+            .map(|(id, _)| LiquidationTarget::Borrow(id))
+            .chain(
+                self.internal_perpetual_liquidation_list()
+                    .into_iter()
+                    .map(LiquidationTarget::Perpetual),
+            )
             .collect()
     }
 
@@ -744,7 +1507,8 @@ impl Contract {
             upper_bound_price,
             pool.sqrt_price,
         );
-        position.get_liquidation_price(borrowed0, borrowed1, LTV_MAX)
+        let (_, liquidation_threshold) = self.internal_risk_params(pool_id);
+        position.get_liquidation_price(borrowed0, borrowed1, liquidation_threshold)
     }
 
     pub fn get_max_leverage(
@@ -759,55 +1523,236 @@ impl Contract {
         let rpa = lower_bound_price.sqrt();
         let rpb = upper_bound_price.sqrt();
         let rp = pool.sqrt_price;
+        let (loan_to_value, _) = self.internal_risk_params(pool_id);
         if rp > rpa && rp < rpb {
-            1.0 / (1.0 - LTV_MAX / f64::max(
+            1.0 / (1.0 - loan_to_value / f64::max(
                 ((rp - rpa) / (rpb - rpa)) * (rp / rpa) + ((rpb - rp) / (rpb - rpa)) * (rpa / rp),
                 (pb / rp - rpb + rp - rpa) / (rpb - rpa)
             ))
         } else {
-            1.0 / (1.0 - LTV_MAX * rpa / rpb)
+            1.0 / (1.0 - loan_to_value * rpa / rpb)
         }
     }
 
     pub fn get_borrow_health_factor(&self, borrow_id: BorrowId) -> f64 {
         let borrow = self.borrows.get(&borrow_id).expect(BRR0);
-        let pool = &self.pools[borrow.pool_id];
-        let position = pool.positions.get(&borrow.position_id).unwrap();
-        let price = pool.sqrt_price * pool.sqrt_price;
-        let ltv = (borrow.borrowed0 as f64 * price + borrow.borrowed1 as f64) / (position.total_locked as f64);
-        LTV_MAX / ltv
+        let (_, liquidation_threshold) = self.internal_risk_params(borrow.pool_id);
+        let (debt, collateral) = self.internal_borrow_values(&borrow);
+        liquidation_threshold * collateral / debt
+    }
+
+    /// Shows what a liquidator would repay and receive when liquidating the borrow now.
+    pub fn preview_liquidation(&self, borrow_id: BorrowId) -> LiquidationPreview {
+        let borrow = self.borrows.get(&borrow_id).expect(BRR0);
+        self.internal_liquidation_preview(&borrow)
     }
 
-    pub fn liquidate(&mut self, borrow_id: BorrowId) {
+    /// Repays part of an unhealthy loan in both borrowed tokens from the liquidator's balance
+    /// and gives the liquidator the matching share of the position plus the liquidation bonus.
+    /// At most the close factor of the debt is repaid at once, only as much as needed
+    /// to restore the health factor.
+    /// If the position cannot cover the debt with the bonus, the position is transferred
+    /// to the liquidator. They repay the whole loan, or, if the position is worth less than
+    /// the debt, only the part matching the position with the bonus. The remaining bad debt
+    /// is paid by the insurance fund and what it cannot pay is written off from the deposits.
+    /// A perpetual below the maintenance margin is closed at the mark price, see
+    /// `internal_liquidate_perpetual`.
+    pub fn liquidate(&mut self, target: LiquidationTarget) -> LiquidationResult {
         let account_id = env::predecessor_account_id();
-        let borrow = self.borrows.remove(&borrow_id).expect(BRR0);
-        let pool = &self.pools[borrow.pool_id];
-        let position = pool.positions.get(&borrow.position_id).unwrap();
+        self.internal_liquidate(&account_id, target)
+    }
+
+    pub(crate) fn internal_liquidate(
+        &mut self,
+        account_id: &AccountId,
+        target: LiquidationTarget,
+    ) -> LiquidationResult {
+        match target {
+            LiquidationTarget::Borrow(borrow_id) => {
+                LiquidationResult::Borrow(self.internal_liquidate_borrow(account_id, borrow_id))
+            }
+            LiquidationTarget::Perpetual(perpetual_id) => LiquidationResult::Perpetual(
+                self.internal_liquidate_perpetual(account_id, perpetual_id),
+            ),
+        }
+    }
+
+    fn internal_liquidate_borrow(
+        &mut self,
+        account_id: &AccountId,
+        borrow_id: BorrowId,
+    ) -> LiquidationPreview {
+        let initial_storage = self.internal_storage_usage();
+        let mut borrow = self.borrows.get(&borrow_id).expect(BRR0);
         let health_factor = self.get_borrow_health_factor(borrow_id);
-        assert!(health_factor < 1.0);
-        let discount = (1.0 + health_factor) / 2.0;
-        let discounted_collateral_sum = (position.total_locked * discount / borrow.leverage) as u128;
-        self.decrease_balance(&account_id, &borrow.asset1, discounted_collateral_sum);
-        if let leverage = borrow.leverage {
-            let pool = &mut self.pools[borrow.pool_id];
-            let mut position = pool.positions.get(&borrow.position_id).unwrap().clone();
-            position.remove_liquidity(
-                Some(U128::from(
-                    (position.token0_locked * (leverage - 1.0) / leverage) as u128,
-                )),
+        assert!(health_factor < 1.0, "{}", LQD0);
+        let preview = self.internal_liquidation_preview(&borrow);
+        self.internal_repay(account_id, &mut borrow, preview.repay0.0, preview.repay1.0);
+        let owner_id = borrow.owner_id.clone();
+        let pool_id = borrow.pool_id;
+        let position_id = borrow.position_id;
+        if preview.seize_position {
+            self.internal_write_off_bad_debt(&mut borrow);
+            // the repaid and the written off debt change the balances and the reserves
+            // at the expense of the liquidator
+            self.internal_charge_storage(account_id, initial_storage);
+            let initial_storage = self.internal_storage_usage();
+            self.internal_refresh_borrow(borrow);
+            // the cleared borrow gives its storage back to the owner
+            self.internal_charge_storage(&owner_id, initial_storage);
+            let position_storage = self.internal_position_storage_usage(pool_id, position_id);
+            self.internal_transfer(
+                &owner_id,
+                account_id,
+                &position_id.to_string(),
+                None,
                 None,
-                pool.sqrt_price,
             );
-            pool.positions.insert(borrow.position_id, position);
+            // the liquidator pays for the seized position from now on
+            self.internal_move_storage(&owner_id, account_id, position_storage);
+        } else {
+            let pool = &mut self.pools[pool_id];
+            let mut position = pool.positions.get(&position_id).expect(PST0).clone();
+            let (amount0, amount1) =
+                position.remove_liquidity_share(preview.collateral_share, pool.sqrt_price);
+            pool.positions.insert(position_id, position);
+            pool.refresh(env::block_timestamp());
+            let token0 = pool.token0.clone();
+            let token1 = pool.token1.clone();
+            self.increase_balance(account_id, &token0, amount0 as u128);
+            self.increase_balance(account_id, &token1, amount1 as u128);
+            self.internal_refresh_borrow(borrow);
+            // the balances go to the liquidator
+            self.internal_charge_storage(account_id, initial_storage);
         }
-        ext_self::nft_transfer(
-            account_id,
-            borrow.position_id.to_string(),
-            None,
-            None,
-            &env::current_account_id(),
-            0,
-            10 * TGAS,
+        preview
+    }
+
+    /// Covers what is left of the loan from the insurance fund, writes the rest off
+    /// from the reserve deposits and clears the borrow.
+    fn internal_write_off_bad_debt(&mut self, borrow: &mut Borrow) {
+        let mut logs = vec![];
+        for (token, deficit) in [
+            (borrow.asset0.clone(), borrow.borrowed0),
+            (borrow.asset1.clone(), borrow.borrowed1),
+        ] {
+            if deficit == 0 {
+                continue;
+            }
+            let covered = self.insurance_fund.cover(&token, deficit);
+            let socialized = deficit - covered;
+            let mut reserve = self.reserves.get(&token).expect(RSR0);
+            reserve.write_off(deficit, socialized);
+            self.reserves.insert(&token, &reserve);
+            self.insurance_fund.history.push(&Coverage {
+                borrow_id: U128(borrow.id),
+                token: token.clone(),
+                deficit: U128(deficit),
+                covered: U128(covered),
+                socialized: U128(socialized),
+                timestamp: env::block_timestamp(),
+            });
+            logs.push(BadDebtLog {
+                borrow_id: U128(borrow.id),
+                token,
+                deficit: U128(deficit),
+                covered: U128(covered),
+                socialized: U128(socialized),
+            });
+        }
+        if !logs.is_empty() {
+            let log = EventLog::new(EventLogVariant::BadDebt(logs));
+            env::log(log.to_string().as_bytes());
+        }
+        borrow.borrowed0 = 0;
+        borrow.borrowed1 = 0;
+        borrow.fees = 0;
+    }
+
+    #[private]
+    pub fn set_insurance_share(&mut self, share: u16) {
+        assert!(share as f64 <= BASIS_POINT_TO_PERCENT, "{}", INS0);
+        self.insurance_fund.share = share;
+    }
+
+    pub fn get_insurance_fund(&self) -> HashMap<AccountId, U128> {
+        self.insurance_fund
+            .balances
+            .iter()
+            .map(|(token, amount)| (token.clone(), U128(*amount)))
+            .collect()
+    }
+
+    pub fn get_insurance_coverage_history(&self, from_index: u64, limit: u64) -> Vec<Coverage> {
+        (from_index..u64::min(from_index + limit, self.insurance_fund.history.len()))
+            .filter_map(|index| self.insurance_fund.history.get(index))
+            .collect()
+    }
+
+    fn internal_liquidation_preview(&self, borrow: &Borrow) -> LiquidationPreview {
+        let pool = &self.pools[borrow.pool_id];
+        let position = pool.positions.get(&borrow.position_id).expect(PST0);
+        let (debt, collateral) = self.internal_borrow_values(borrow);
+        let (_, liquidation_threshold) = self.internal_risk_params(borrow.pool_id);
+        let health_factor = liquidation_threshold * collateral / debt;
+        let reserve0 = self.reserves.get(&borrow.asset0).expect(RSR0);
+        let reserve1 = self.reserves.get(&borrow.asset1).expect(RSR0);
+        let debt0 = debt - borrow.borrowed1 as f64;
+        let debt1 = borrow.borrowed1 as f64;
+        // each leg of the loan earns the bonus of its own reserve
+        let liquidation_bonus = (debt0 * reserve0.config.liquidation_bonus
+            + debt1 * reserve1.config.liquidation_bonus)
+            / debt;
+        let close_factor = f64::min(reserve0.config.close_factor, reserve1.config.close_factor);
+        let seize_position = collateral <= debt * (1.0 + liquidation_bonus);
+        let debt_share = if health_factor >= 1.0 {
+            0.0
+        } else if seize_position && collateral >= debt {
+            1.0
+        } else if seize_position {
+            // with bad debt the liquidator still gets the bonus for the part they repay,
+            // the rest is covered by the insurance fund or written off
+            collateral / (debt * (1.0 + liquidation_bonus))
+        } else {
+            // share of the debt after which liquidation_threshold * collateral >= debt again
+            let needed = (debt - liquidation_threshold * collateral)
+                / (1.0 - liquidation_threshold * (1.0 + liquidation_bonus))
+                / debt;
+            f64::min(needed, close_factor)
+        };
+        let collateral_share = if seize_position && debt_share > 0.0 {
+            1.0
+        } else {
+            debt_share * debt * (1.0 + liquidation_bonus) / collateral
+        };
+        let fees = borrow.fees + borrow.calculate_fees(env::block_timestamp());
+        let repay0 = u128::min(
+            (borrow.borrowed0 as f64 * debt_share).ceil() as u128,
+            borrow.borrowed0,
+        );
+        let repay1 = u128::min(
+            ((borrow.borrowed1 + fees) as f64 * debt_share).ceil() as u128,
+            borrow.borrowed1 + fees,
         );
+        let (deficit0, deficit1) = if seize_position && debt_share > 0.0 {
+            (
+                borrow.borrowed0 - repay0,
+                borrow.borrowed1 - repay1.saturating_sub(fees),
+            )
+        } else {
+            (0, 0)
+        };
+        LiquidationPreview {
+            borrow_id: borrow.id,
+            health_factor,
+            repay0: repay0.into(),
+            repay1: repay1.into(),
+            collateral0: ((position.token0_locked * collateral_share) as u128).into(),
+            collateral1: ((position.token1_locked * collateral_share) as u128).into(),
+            collateral_share,
+            seize_position,
+            deficit0: deficit0.into(),
+            deficit1: deficit1.into(),
+        }
     }
 }
@@ -1,5 +1,5 @@
 use crate::{
-    nft::events::{EventLog, EventLogVariant, NftTransferLog},
+    nft::events::{EventLog, EventLogVariant, NftBurnLog, NftTransferLog},
     *,
 };
 use near_sdk::CryptoHash;
@@ -96,6 +96,24 @@ impl Contract {
         }
     }
 
+    /// Removes the token with its metadata and logs the burn
+    pub(crate) fn internal_burn(&mut self, token_id: &TokenId) {
+        let token = self.tokens_by_id.remove(token_id).expect(NFT0);
+        self.token_metadata_by_id.remove(token_id);
+        self.internal_remove_token_from_owner(&token.owner_id, token_id);
+        let nft_burn_log = EventLog {
+            standard: NFT_STANDARD_NAME.to_string(),
+            version: NFT_METADATA_SPEC.to_string(),
+            event: EventLogVariant::NftBurn(vec![NftBurnLog {
+                owner_id: token.owner_id,
+                token_ids: vec![token_id.to_string()],
+                authorized_id: None,
+                memo: None,
+            }]),
+        };
+        env::log(nft_burn_log.to_string().as_bytes());
+    }
+
     pub(crate) fn internal_transfer(
         &mut self,
         sender_id: &AccountId,
@@ -124,11 +142,7 @@ impl Contract {
         if receiver_id.to_string() != env::current_account_id() {
             let id = token_id.parse::<u128>().unwrap();
             for pool in &mut self.pools {
-                if let Some(position) = pool.positions.get(&id) {
-                    let mut position = position.clone();
-                    position.owner_id = receiver_id.to_string();
-                    pool.positions.insert(id, position);
-                }
+                pool.transfer_position(id, receiver_id);
             }
         }
         let new_token = Token {
@@ -40,9 +40,6 @@ impl Contract {
     #[private]
     #[payable]
     pub fn nft_burn(&mut self, token_id: TokenId) {
-        assert!(self.tokens_by_id.get(&token_id).is_some(), "{}", NFT0);
-        let token = self.tokens_by_id.get(&token_id).unwrap();
-        self.token_metadata_by_id.remove(&token_id);
-        self.internal_remove_token_from_owner(&token.owner_id, &token_id);
+        self.internal_burn(&token_id);
     }
 }
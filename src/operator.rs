@@ -0,0 +1,304 @@
+use near_sdk::assert_one_yocto;
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+
+use std::collections::HashSet;
+
+use crate::action::*;
+use crate::*;
+
+/// Largest max slippage of the zaps operators run, in basis points of the TWAP prices
+pub const OPERATOR_MAX_SLIPPAGE: u16 = 100;
+
+/// Part of an account an operator can act on
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum OperatorScope {
+    /// Swaps and multihop swaps
+    Swap,
+    /// Withdrawals, only to the account itself
+    Withdraw,
+    /// Opening, changing and closing positions
+    Liquidity,
+    /// Deposits, borrows and their liquidations
+    Lending,
+    /// Futures, perpetuals and options, liquidations of perpetuals
+    Derivatives,
+    /// Transfers to internal balances of other accounts
+    Transfer,
+}
+
+/// Approval of an operator by an account
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Operator {
+    pub scopes: Vec<OperatorScope>,
+    /// The approval is not valid from this time, it never expires if not set
+    pub expiration_ts: Option<u64>,
+    /// Tokens whose balances the actions can change, any token if not set
+    pub tokens: Option<Vec<AccountId>>,
+    /// Positions the actions can change, including the positions of borrows,
+    /// any position if not set
+    pub position_ids: Option<Vec<u128>>,
+    /// What is left of the amounts of tokens the operator can spend from the balances,
+    /// tokens without a limit can be spent without one
+    pub limits: HashMap<AccountId, U128>,
+}
+
+impl Operator {
+    pub fn is_expired(&self, timestamp: u64) -> bool {
+        self.expiration_ts
+            .map(|expiration_ts| timestamp >= expiration_ts)
+            .unwrap_or(false)
+    }
+
+    /// Checks the tokens whose balances an action changed are allowed and charges
+    /// what it spent against the limits
+    fn spend(
+        &mut self,
+        balances: &HashMap<AccountId, u128>,
+        balances_after: &HashMap<AccountId, u128>,
+    ) {
+        let tokens: HashSet<&AccountId> = balances.keys().chain(balances_after.keys()).collect();
+        for token in tokens {
+            let amount = balances.get(token).copied().unwrap_or(0);
+            let amount_after = balances_after.get(token).copied().unwrap_or(0);
+            if amount == amount_after {
+                continue;
+            }
+            if let Some(tokens) = &self.tokens {
+                assert!(tokens.contains(token), "{}", OPR7);
+            }
+            if let Some(limit) = self.limits.get_mut(token) {
+                let spent = amount.saturating_sub(amount_after);
+                assert!(spent <= limit.0, "{}", OPR9);
+                limit.0 -= spent;
+            }
+        }
+    }
+}
+
+impl Action {
+    /// Scopes an operator needs for the action, zaps swap and change positions
+    pub fn scopes(&self) -> Vec<OperatorScope> {
+        match self {
+            Action::Swap(_) | Action::MultihopeSwap(_) => vec![OperatorScope::Swap],
+            Action::Withdraw(_) => vec![OperatorScope::Withdraw],
+            Action::OpenPosition(_)
+            | Action::AddLiquidity(_)
+            | Action::OpenPositionWithAmounts(_)
+            | Action::AddLiquidityWithAmounts(_)
+            | Action::RemoveLiquidity(_)
+            | Action::RemoveLiquidityShare(_)
+            | Action::ClosePosition(_) => vec![OperatorScope::Liquidity],
+            Action::CreateDeposit(_)
+            | Action::CloseDeposit(_)
+            | Action::SupplyCollateralAndBorrow(_)
+            | Action::ReturnCollateralAndRepay(_)
+            | Action::RepayPartial(_)
+            | Action::ReduceLeverage(_)
+            | Action::AddCollateral(_)
+            | Action::IncreaseLeverage(_)
+            | Action::Liquidate(LiquidateAction {
+                target: LiquidationTarget::Borrow(_),
+            }) => vec![OperatorScope::Lending],
+            Action::OpenFutures(_)
+            | Action::TakeFutures(_)
+            | Action::CancelFutures(_)
+            | Action::SettleFutures(_)
+            | Action::LiquidateFutures(_)
+            | Action::OpenPerpetual(_)
+            | Action::ClosePerpetual(_)
+            | Action::Liquidate(LiquidateAction {
+                target: LiquidationTarget::Perpetual(_),
+            })
+            | Action::WriteOptions(_)
+            | Action::BuyOptions(_)
+            | Action::ExerciseOptions(_)
+            | Action::ClaimOptionCollateral(_) => vec![OperatorScope::Derivatives],
+            Action::Transfer(_) => vec![OperatorScope::Transfer],
+            Action::ZapOpenPosition(_)
+            | Action::ZapClosePosition(_)
+            | Action::ZapRemoveLiquidityShare(_) => {
+                vec![OperatorScope::Liquidity, OperatorScope::Swap]
+            }
+        }
+    }
+
+    /// Whether an operator can run the action: swaps need the min amount out, zaps a nonzero
+    /// min amount out or a max slippage of at most `OPERATOR_MAX_SLIPPAGE`, futures a limit
+    /// price and options a max premium. Liquidity is changed only by the actions with
+    /// min amounts, leverage and written premiums have no bounds.
+    pub fn has_slippage_bounds(&self) -> bool {
+        match self {
+            Action::Swap(action) => action.min_amount_out.is_some(),
+            Action::MultihopeSwap(action) => action.min_amount_out.is_some(),
+            Action::ZapOpenPosition(action) => action.max_slippage <= OPERATOR_MAX_SLIPPAGE,
+            Action::ZapClosePosition(action) => action.min_amount_out.0 > 0,
+            Action::ZapRemoveLiquidityShare(action) => action.min_amount_out.0 > 0,
+            Action::OpenFutures(action) => action.limit_price.is_some(),
+            Action::TakeFutures(action) => action.limit_price.is_some(),
+            Action::BuyOptions(action) => action.max_premium.is_some(),
+            Action::OpenPosition(_)
+            | Action::AddLiquidity(_)
+            | Action::RemoveLiquidity(_)
+            | Action::ClosePosition(_)
+            | Action::SupplyCollateralAndBorrow(_)
+            | Action::ReduceLeverage(_)
+            | Action::IncreaseLeverage(_)
+            | Action::WriteOptions(_) => false,
+            Action::Withdraw(_)
+            | Action::Transfer(_)
+            | Action::OpenPositionWithAmounts(_)
+            | Action::AddLiquidityWithAmounts(_)
+            | Action::RemoveLiquidityShare(_)
+            | Action::CreateDeposit(_)
+            | Action::CloseDeposit(_)
+            | Action::ReturnCollateralAndRepay(_)
+            | Action::RepayPartial(_)
+            | Action::AddCollateral(_)
+            | Action::Liquidate(_)
+            | Action::CancelFutures(_)
+            | Action::SettleFutures(_)
+            | Action::LiquidateFutures(_)
+            | Action::OpenPerpetual(_)
+            | Action::ClosePerpetual(_)
+            | Action::ExerciseOptions(_)
+            | Action::ClaimOptionCollateral(_) => true,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Allows `operator_id` to execute actions of `scopes` for you until `expiration_ts`,
+    /// replaces an earlier approval of the operator. The actions can change only the balances
+    /// of `tokens` and only `position_ids` if they are set, and spend at most `limits`
+    /// of the tokens. Requires 1 yoctoNEAR attached.
+    #[payable]
+    pub fn approve_operator(
+        &mut self,
+        operator_id: ValidAccountId,
+        scopes: Vec<OperatorScope>,
+        expiration_ts: Option<U64>,
+        tokens: Option<Vec<ValidAccountId>>,
+        position_ids: Option<Vec<U128>>,
+        limits: Option<HashMap<AccountId, U128>>,
+    ) {
+        assert_one_yocto();
+        assert!(!scopes.is_empty(), "{}", OPR3);
+        if let Some(expiration_ts) = expiration_ts {
+            assert!(expiration_ts.0 > env::block_timestamp(), "{}", OPR4);
+        }
+        let account_id = env::predecessor_account_id();
+        let initial_storage = self.internal_storage_usage();
+        let mut operators = self.operators.get(&account_id).unwrap_or_default();
+        operators.insert(
+            operator_id.into(),
+            Operator {
+                scopes,
+                expiration_ts: expiration_ts.map(|expiration_ts| expiration_ts.0),
+                tokens: tokens.map(|tokens| tokens.into_iter().map(|token| token.into()).collect()),
+                position_ids: position_ids.map(|ids| ids.into_iter().map(|id| id.0).collect()),
+                limits: limits.unwrap_or_default(),
+            },
+        );
+        self.operators.insert(&account_id, &operators);
+        self.internal_charge_storage(&account_id, initial_storage);
+    }
+
+    pub fn revoke_operator(&mut self, operator_id: AccountId) {
+        let account_id = env::predecessor_account_id();
+        let initial_storage = self.internal_storage_usage();
+        let mut operators = self.operators.get(&account_id).expect(OPR0);
+        operators.remove(&operator_id).expect(OPR0);
+        if operators.is_empty() {
+            self.operators.remove(&account_id);
+        } else {
+            self.operators.insert(&account_id, &operators);
+        }
+        self.internal_charge_storage(&account_id, initial_storage);
+    }
+
+    pub fn get_operators(&self, account_id: AccountId) -> HashMap<AccountId, Operator> {
+        self.operators.get(&account_id).unwrap_or_default()
+    }
+
+    /// Executes actions for `account_id`, which approved the caller for the scopes,
+    /// tokens, positions and limits of the actions. The actions need slippage bounds,
+    /// see `Action::has_slippage_bounds`. Withdrawals and transfers require
+    /// at least 1 yoctoNEAR attached.
+    #[payable]
+    pub fn execute_for(
+        &mut self,
+        account_id: AccountId,
+        actions: Vec<Action>,
+    ) -> Vec<ActionResult> {
+        Self::assert_deposit_for_transfers(&actions);
+        let operator_id = env::predecessor_account_id();
+        let mut operators = self.operators.get(&account_id).expect(OPR0);
+        let mut operator = operators.get(&operator_id).cloned().expect(OPR0);
+        assert!(!operator.is_expired(env::block_timestamp()), "{}", OPR1);
+        let mut results = vec![];
+        for action in &actions {
+            for scope in action.scopes() {
+                assert!(operator.scopes.contains(&scope), "{}", OPR2);
+            }
+            assert!(action.has_slippage_bounds(), "{}", OPR6);
+            if let Action::Withdraw(action) = action {
+                let receiver_id = action.receiver_id.as_ref().unwrap_or(&account_id);
+                assert_eq!(receiver_id, &account_id, "{}", OPR5);
+            }
+            if let (Some(position_ids), Some(position_id)) = (
+                &operator.position_ids,
+                self.internal_action_position_id(action),
+            ) {
+                assert!(position_ids.contains(&position_id), "{}", OPR8);
+            }
+            let balances = self.internal_account_balances(&account_id);
+            results.push(self.internal_execute_action(&account_id, None, action));
+            operator.spend(&balances, &self.internal_account_balances(&account_id));
+        }
+        operators.insert(operator_id, operator);
+        self.operators.insert(&account_id, &operators);
+        results
+    }
+}
+
+impl Contract {
+    /// Position the action changes, the position of the borrow for the borrow actions
+    fn internal_action_position_id(&self, action: &Action) -> Option<u128> {
+        let borrow_id = match action {
+            Action::AddLiquidity(AddLiquidityAction { position_id, .. })
+            | Action::AddLiquidityWithAmounts(AddLiquidityWithAmountsAction {
+                position_id, ..
+            })
+            | Action::RemoveLiquidity(RemoveLiquidityAction { position_id, .. })
+            | Action::RemoveLiquidityShare(RemoveLiquidityShareAction { position_id, .. })
+            | Action::ClosePosition(ClosePositionAction { position_id, .. })
+            | Action::ZapClosePosition(ZapClosePositionAction { position_id, .. })
+            | Action::ZapRemoveLiquidityShare(ZapRemoveLiquidityShareAction {
+                position_id, ..
+            })
+            | Action::SupplyCollateralAndBorrow(SupplyCollateralAndBorrowAction {
+                position_id,
+                ..
+            }) => return Some(*position_id),
+            Action::ReturnCollateralAndRepay(action) => action.borrow_id,
+            Action::RepayPartial(action) => action.borrow_id,
+            Action::ReduceLeverage(action) => action.borrow_id,
+            Action::AddCollateral(action) => action.borrow_id,
+            Action::IncreaseLeverage(action) => action.borrow_id,
+            _ => return None,
+        };
+        self.borrows
+            .get(&borrow_id)
+            .map(|borrow| borrow.position_id)
+    }
+
+    fn internal_account_balances(&self, account_id: &AccountId) -> HashMap<AccountId, u128> {
+        self.balances_map
+            .get(account_id)
+            .map(|balance| balance.iter().collect())
+            .unwrap_or_default()
+    }
+}
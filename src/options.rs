@@ -0,0 +1,497 @@
+use near_sdk::json_types::{U128, U64};
+use std::f64::consts::{PI, SQRT_2};
+
+use crate::pool::NS_IN_YEAR;
+use crate::*;
+
+pub type OptionSeriesId = u128;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum OptionType {
+    Put,
+    Call,
+}
+
+/// Options written and not bought yet, `premium` is in token1 per option
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OptionOffer {
+    pub amount: U128,
+    pub premium: U128,
+}
+
+/// European options on 1 token0 of the pool each with the same strike and expiration.
+/// Options are fungible within the series. Writers lock `insurance` per option in `token`:
+/// 1 token0 for calls and `strike` of token1 rounded up for puts. At expiration the series
+/// settles in cash against the pool TWAP, holders get the payoff from the collateral and
+/// writers get back the rest.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OptionSeries {
+    pub id: OptionSeriesId,
+    pub pool_id: usize,
+    pub option_type: OptionType,
+    pub strike: f64,
+    pub expiration_ts: u64,
+    pub insurance: u128,
+    pub token: AccountId,
+    pub offers: HashMap<AccountId, OptionOffer>,
+    /// Options each writer has sold, their collateral stays locked until settlement
+    pub written: HashMap<AccountId, u128>,
+    pub holders: HashMap<AccountId, u128>,
+    pub settlement_price: Option<f64>,
+}
+
+impl OptionSeries {
+    /// Collateral of an option paid to its holder at `price`, in `token`: the gain of the call
+    /// in token0 and the gain of the put in token1, at most the collateral of the option
+    pub fn payoff(&self, price: f64) -> f64 {
+        let payoff = match self.option_type {
+            OptionType::Call => self.insurance as f64 * (price - self.strike) / price,
+            OptionType::Put => self.strike - price,
+        };
+        payoff.max(0.0).min(self.insurance as f64)
+    }
+
+    pub fn get_balance(&self, account_id: &AccountId) -> u128 {
+        *self.holders.get(account_id).unwrap_or(&0)
+    }
+
+    fn decrease_balance(&mut self, account_id: &AccountId, amount: u128) {
+        let balance = self.get_balance(account_id);
+        assert!(balance >= amount, "{}", OPT4);
+        if balance == amount {
+            self.holders.remove(account_id);
+        } else {
+            self.holders.insert(account_id.clone(), balance - amount);
+        }
+    }
+
+    fn increase_balance(&mut self, account_id: &AccountId, amount: u128) {
+        *self.holders.entry(account_id.clone()).or_insert(0) += amount;
+    }
+}
+
+/// Black-Scholes price of an option on 1 token0 in token1 and its greeks.
+/// Theta is per year and vega per 1.0 of volatility.
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OptionQuote {
+    pub spot_price: f64,
+    pub volatility: f64,
+    pub premium: f64,
+    pub delta: f64,
+    pub gamma: f64,
+    pub theta: f64,
+    pub vega: f64,
+}
+
+/// Error function by Abramowitz and Stegun 7.1.26, the error is below 1.5e-7
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let polynomial = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let y = 1.0 - polynomial * (-x * x).exp();
+    if x < 0.0 {
+        -y
+    } else {
+        y
+    }
+}
+
+fn normal_cdf(x: f64) -> f64 {
+    0.5 * (1.0 + erf(x / SQRT_2))
+}
+
+fn normal_pdf(x: f64) -> f64 {
+    (-x * x / 2.0).exp() / (2.0 * PI).sqrt()
+}
+
+/// Prices an option expiring in `time` years with no interest rate. Without volatility
+/// the option is worth its intrinsic value.
+pub fn black_scholes(
+    option_type: OptionType,
+    spot_price: f64,
+    strike: f64,
+    volatility: f64,
+    time: f64,
+) -> OptionQuote {
+    let deviation = volatility * time.sqrt();
+    if deviation == 0.0 {
+        let (premium, delta) = match option_type {
+            OptionType::Call if spot_price > strike => (spot_price - strike, 1.0),
+            OptionType::Put if spot_price < strike => (strike - spot_price, -1.0),
+            _ => (0.0, 0.0),
+        };
+        return OptionQuote {
+            spot_price,
+            volatility,
+            premium,
+            delta,
+            gamma: 0.0,
+            theta: 0.0,
+            vega: 0.0,
+        };
+    }
+    let d1 = ((spot_price / strike).ln() + deviation * deviation / 2.0) / deviation;
+    let d2 = d1 - deviation;
+    let (premium, delta) = match option_type {
+        OptionType::Call => (
+            spot_price * normal_cdf(d1) - strike * normal_cdf(d2),
+            normal_cdf(d1),
+        ),
+        OptionType::Put => (
+            strike * normal_cdf(-d2) - spot_price * normal_cdf(-d1),
+            normal_cdf(d1) - 1.0,
+        ),
+    };
+    OptionQuote {
+        spot_price,
+        volatility,
+        premium,
+        delta,
+        gamma: normal_pdf(d1) / (spot_price * deviation),
+        theta: -spot_price * normal_pdf(d1) * volatility / (2.0 * time.sqrt()),
+        vega: spot_price * normal_pdf(d1) * time.sqrt(),
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Prices an option on the pool with Black-Scholes at the spot price,
+    /// with the volatility estimated from the price history of the pool
+    pub fn quote_option(
+        &self,
+        pool_id: usize,
+        strike: f64,
+        expiration_ts: U64,
+        option_type: OptionType,
+    ) -> OptionQuote {
+        self.assert_pool_exists(pool_id);
+        assert!(strike > 0.0, "{}", OPT5);
+        assert!(expiration_ts.0 > env::block_timestamp(), "{}", OPT6);
+        let pool = &self.pools[pool_id];
+        let volatility = pool.get_volatility().expect(OPT8);
+        let time = (expiration_ts.0 - env::block_timestamp()) as f64 / NS_IN_YEAR;
+        black_scholes(
+            option_type,
+            pool.sqrt_price * pool.sqrt_price,
+            strike,
+            volatility,
+            time,
+        )
+    }
+
+    /// Creates a series of options on the pool price, anyone can write and buy them.
+    /// The storage of the series is charged to the creator.
+    pub fn create_option_series(
+        &mut self,
+        pool_id: usize,
+        option_type: OptionType,
+        strike: f64,
+        expiration_ts: U64,
+    ) -> OptionSeriesId {
+        let account_id = env::predecessor_account_id();
+        self.assert_pool_exists(pool_id);
+        assert!(strike > 0.0, "{}", OPT5);
+        assert!(expiration_ts.0 > env::block_timestamp(), "{}", OPT6);
+        let pool = &self.pools[pool_id];
+        let (insurance, token) = match option_type {
+            OptionType::Call => (1, pool.token0.clone()),
+            OptionType::Put => (strike.ceil() as u128, pool.token1.clone()),
+        };
+        let series = OptionSeries {
+            id: self.option_series_number,
+            pool_id,
+            option_type,
+            strike,
+            expiration_ts: expiration_ts.0,
+            insurance,
+            token,
+            offers: HashMap::new(),
+            written: HashMap::new(),
+            holders: HashMap::new(),
+            settlement_price: None,
+        };
+        let initial_storage = self.internal_storage_usage();
+        self.internal_request_settlement_price(pool_id, series.expiration_ts);
+        self.option_series.insert(&series.id, &series);
+        self.option_series_number += 1;
+        self.internal_charge_storage(&account_id, initial_storage);
+        series.id
+    }
+
+    /// Locks the collateral of `amount` options from the internal balance and offers them
+    /// for `premium` of token1 each. Writing again adds to the offer and replaces its premium.
+    pub fn write_options(&mut self, series_id: OptionSeriesId, amount: U128, premium: U128) {
+        let account_id = env::predecessor_account_id();
+        self.internal_write_options(&account_id, series_id, amount, premium)
+    }
+
+    pub(crate) fn internal_write_options(
+        &mut self,
+        account_id: &AccountId,
+        series_id: OptionSeriesId,
+        amount: U128,
+        premium: U128,
+    ) {
+        let initial_storage = self.internal_storage_usage();
+        let mut series = self.option_series.get(&series_id).expect(OPT0);
+        assert!(env::block_timestamp() < series.expiration_ts, "{}", OPT1);
+        self.decrease_balance(account_id, &series.token, amount.0 * series.insurance);
+        let offered = series
+            .offers
+            .get(account_id)
+            .map(|offer| offer.amount.0)
+            .unwrap_or(0);
+        series.offers.insert(
+            account_id.clone(),
+            OptionOffer {
+                amount: U128(offered + amount.0),
+                premium,
+            },
+        );
+        self.option_series.insert(&series_id, &series);
+        self.internal_charge_storage(account_id, initial_storage);
+    }
+
+    /// Withdraws the options you offered and nobody bought and returns their collateral
+    pub fn cancel_option_offer(&mut self, series_id: OptionSeriesId) {
+        let account_id = env::predecessor_account_id();
+        let initial_storage = self.internal_storage_usage();
+        let mut series = self.option_series.get(&series_id).expect(OPT0);
+        let offer = series.offers.remove(&account_id).expect(OPT3);
+        self.increase_balance(&account_id, &series.token, offer.amount.0 * series.insurance);
+        self.option_series.insert(&series_id, &series);
+        self.internal_charge_storage(&account_id, initial_storage);
+    }
+
+    /// Buys `amount` options offered by `writer_id`, the premium is paid in token1
+    /// of the pool from the internal balance
+    pub fn buy_options(&mut self, series_id: OptionSeriesId, writer_id: AccountId, amount: U128) {
+        let account_id = env::predecessor_account_id();
+        self.internal_buy_options(&account_id, series_id, writer_id, amount, None)
+    }
+
+    pub(crate) fn internal_buy_options(
+        &mut self,
+        account_id: &AccountId,
+        series_id: OptionSeriesId,
+        writer_id: AccountId,
+        amount: U128,
+        max_premium: Option<U128>,
+    ) {
+        let initial_storage = self.internal_storage_usage();
+        let mut series = self.option_series.get(&series_id).expect(OPT0);
+        assert!(env::block_timestamp() < series.expiration_ts, "{}", OPT1);
+        let mut offer = series.offers.get(&writer_id).cloned().expect(OPT3);
+        assert!(offer.amount.0 >= amount.0, "{}", OPT3);
+        if let Some(max_premium) = max_premium {
+            assert!(offer.premium.0 <= max_premium.0, "{}", OPT9);
+        }
+        offer.amount = U128(offer.amount.0 - amount.0);
+        if offer.amount.0 == 0 {
+            series.offers.remove(&writer_id);
+        } else {
+            series.offers.insert(writer_id.clone(), offer.clone());
+        }
+        *series.written.entry(writer_id.clone()).or_insert(0) += amount.0;
+        series.increase_balance(account_id, amount.0);
+        let token1 = self.pools[series.pool_id].token1.clone();
+        let premium = offer.premium.0 * amount.0;
+        self.decrease_balance(account_id, &token1, premium);
+        self.increase_balance(&writer_id, &token1, premium);
+        self.option_series.insert(&series_id, &series);
+        // the buyer pays for the holder and the writer entries
+        self.internal_charge_storage(account_id, initial_storage);
+    }
+
+    pub fn transfer_options(
+        &mut self,
+        series_id: OptionSeriesId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) {
+        let account_id = env::predecessor_account_id();
+        let initial_storage = self.internal_storage_usage();
+        let mut series = self.option_series.get(&series_id).expect(OPT0);
+        series.decrease_balance(&account_id, amount.0);
+        series.increase_balance(&receiver_id, amount.0);
+        self.option_series.insert(&series_id, &series);
+        // the sender pays for the entry of the new holder
+        self.internal_charge_storage(&account_id, initial_storage);
+    }
+
+    /// Fixes the settlement price of an expired series at the pool TWAP over the window ending
+    /// at expiration, anyone can call it.
+    /// Exercising and claiming settle the series as well.
+    pub fn settle_option_series(&mut self, series_id: OptionSeriesId) -> f64 {
+        let series = self.internal_settle_option_series(series_id);
+        series.settlement_price.unwrap()
+    }
+
+    /// Pays the payoff of all your options of a settled series, returns the amount paid
+    pub fn exercise_options(&mut self, series_id: OptionSeriesId) -> U128 {
+        let account_id = env::predecessor_account_id();
+        self.internal_exercise_options(&account_id, series_id)
+    }
+
+    pub(crate) fn internal_exercise_options(
+        &mut self,
+        account_id: &AccountId,
+        series_id: OptionSeriesId,
+    ) -> U128 {
+        let mut series = self.internal_settle_option_series(series_id);
+        let initial_storage = self.internal_storage_usage();
+        let amount = series.holders.remove(account_id).expect(OPT4);
+        let payoff = (amount as f64 * series.payoff(series.settlement_price.unwrap())) as u128;
+        self.increase_balance(account_id, &series.token, payoff);
+        self.option_series.insert(&series_id, &series);
+        self.internal_charge_storage(account_id, initial_storage);
+        U128(payoff)
+    }
+
+    /// Returns the collateral of your options after the payoff to the holders
+    /// and of the options nobody bought, returns the amount
+    pub fn claim_option_collateral(&mut self, series_id: OptionSeriesId) -> U128 {
+        let account_id = env::predecessor_account_id();
+        self.internal_claim_option_collateral(&account_id, series_id)
+    }
+
+    pub(crate) fn internal_claim_option_collateral(
+        &mut self,
+        account_id: &AccountId,
+        series_id: OptionSeriesId,
+    ) -> U128 {
+        let mut series = self.internal_settle_option_series(series_id);
+        let initial_storage = self.internal_storage_usage();
+        let written = series.written.remove(account_id).unwrap_or(0);
+        let offered = series
+            .offers
+            .remove(account_id)
+            .map(|offer| offer.amount.0)
+            .unwrap_or(0);
+        assert!(written + offered > 0, "{}", OPT7);
+        let payoff = series.payoff(series.settlement_price.unwrap());
+        let amount = (written as f64 * (series.insurance as f64 - payoff)) as u128
+            + offered * series.insurance;
+        self.increase_balance(account_id, &series.token, amount);
+        self.option_series.insert(&series_id, &series);
+        self.internal_charge_storage(account_id, initial_storage);
+        U128(amount)
+    }
+
+    pub fn get_option_series(&self, series_id: OptionSeriesId) -> OptionSeries {
+        self.option_series.get(&series_id).expect(OPT0)
+    }
+
+    /// Series that have not expired yet
+    pub fn get_active_option_series(&self, from_index: u64, limit: u64) -> Vec<OptionSeries> {
+        self.option_series
+            .iter()
+            .filter(|(_, series)| env::block_timestamp() < series.expiration_ts)
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .map(|(_, series)| series)
+            .collect()
+    }
+
+    pub fn get_option_balance(&self, series_id: OptionSeriesId, account_id: AccountId) -> U128 {
+        let series = self.option_series.get(&series_id).expect(OPT0);
+        U128(series.get_balance(&account_id))
+    }
+}
+
+impl Contract {
+    fn internal_settle_option_series(&mut self, series_id: OptionSeriesId) -> OptionSeries {
+        let mut series = self.option_series.get(&series_id).expect(OPT0);
+        if series.settlement_price.is_none() {
+            assert!(env::block_timestamp() >= series.expiration_ts, "{}", OPT2);
+            let settlement_price =
+                self.internal_take_settlement_price(series.pool_id, series.expiration_ts);
+            series.settlement_price = Some(settlement_price);
+            self.option_series.insert(&series_id, &series);
+        }
+        series
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::options::*;
+
+    fn series(option_type: OptionType, strike: f64) -> OptionSeries {
+        let insurance = match option_type {
+            OptionType::Call => 1,
+            OptionType::Put => strike.ceil() as u128,
+        };
+        OptionSeries {
+            id: 0,
+            pool_id: 0,
+            option_type,
+            strike,
+            expiration_ts: 0,
+            insurance,
+            token: "usn".to_string(),
+            offers: HashMap::new(),
+            written: HashMap::new(),
+            holders: HashMap::new(),
+            settlement_price: None,
+        }
+    }
+
+    #[test]
+    fn payoff() {
+        let call = series(OptionType::Call, 100.0);
+        let put = series(OptionType::Put, 100.0);
+        assert_eq!(call.payoff(125.0), 0.2);
+        assert_eq!(call.payoff(80.0), 0.0);
+        assert_eq!(put.payoff(80.0), 20.0);
+        assert_eq!(put.payoff(125.0), 0.0);
+    }
+
+    #[test]
+    fn put_payoff_with_fractional_strike() {
+        // the put locks 101 of token1 and pays the difference to the strike
+        let put = series(OptionType::Put, 100.5);
+        assert_eq!(put.insurance, 101);
+        assert_eq!(put.payoff(90.0), 10.5);
+        assert_eq!(put.payoff(0.0), 100.5);
+    }
+
+    #[test]
+    fn black_scholes_at_the_money() {
+        let call = black_scholes(OptionType::Call, 100.0, 100.0, 0.2, 1.0);
+        let put = black_scholes(OptionType::Put, 100.0, 100.0, 0.2, 1.0);
+        assert!((call.premium - 7.9656).abs() < 1e-3);
+        assert!((put.premium - 7.9656).abs() < 1e-3);
+        assert!((call.delta - 0.5398).abs() < 1e-4);
+        assert!((put.delta + 0.4602).abs() < 1e-4);
+        assert!((call.gamma - 0.019848).abs() < 1e-5);
+        assert!((call.vega - 39.695).abs() < 1e-2);
+        assert!((call.theta + 3.9695).abs() < 1e-3);
+    }
+
+    #[test]
+    fn black_scholes_put_call_parity() {
+        let call = black_scholes(OptionType::Call, 120.0, 100.0, 0.5, 0.25);
+        let put = black_scholes(OptionType::Put, 120.0, 100.0, 0.5, 0.25);
+        assert!((call.premium - put.premium - 20.0).abs() < 1e-4);
+        let call = black_scholes(OptionType::Call, 120.0, 100.0, 0.0, 0.25);
+        assert_eq!(call.premium, 20.0);
+        assert_eq!(call.delta, 1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "You don't have enough options")]
+    fn option_balance() {
+        let mut series = series(OptionType::Call, 100.0);
+        let alice = "alice".to_string();
+        series.increase_balance(&alice, 10);
+        series.decrease_balance(&alice, 10);
+        assert!(series.holders.is_empty());
+        series.decrease_balance(&alice, 1);
+    }
+}
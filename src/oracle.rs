@@ -0,0 +1,213 @@
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{ext_contract, Gas};
+
+use crate::pool::MAX_TWAP_WINDOW_SEC;
+use crate::*;
+
+pub const GAS_FOR_GET_PRICE_DATA: Gas = 10 * TGAS;
+pub const GAS_FOR_ON_ORACLE_PRICES: Gas = 10 * TGAS;
+
+/// How long the pool TWAP looks back by default
+pub const DEFAULT_TWAP_WINDOW_SEC: u32 = 600;
+/// Oracle prices older than this are not used by default
+pub const DEFAULT_MAX_ORACLE_AGE_SEC: u32 = 90;
+
+pub type AssetId = String;
+
+/// Price of the smallest unit of a token in USD: `multiplier / 10^decimals`,
+/// as returned by the NEAR price oracle
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Price {
+    pub multiplier: U128,
+    pub decimals: u8,
+}
+
+impl Price {
+    pub fn to_f64(&self) -> f64 {
+        self.multiplier.0 as f64 / 10f64.powi(self.decimals as i32)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AssetOptionalPrice {
+    pub asset_id: AssetId,
+    pub price: Option<Price>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceData {
+    pub timestamp: U64,
+    pub recency_duration_sec: u32,
+    pub prices: Vec<AssetOptionalPrice>,
+}
+
+/// Oracle price stored together with the time it was reported
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Copy, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OraclePrice {
+    pub price: Price,
+    pub timestamp: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PriceSource {
+    Oracle,
+    Twap,
+    Spot,
+}
+
+/// Which price is used to value debt and collateral of borrows
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceConfig {
+    pub source: PriceSource,
+    pub oracle_id: Option<AccountId>,
+    pub twap_window_sec: u32,
+    pub max_oracle_age_sec: u32,
+    /// Largest allowed relative difference between the pool spot price and the price
+    /// of the source, not checked if not set
+    pub max_deviation: Option<f64>,
+}
+
+impl Default for PriceConfig {
+    fn default() -> Self {
+        PriceConfig {
+            source: PriceSource::Twap,
+            oracle_id: None,
+            twap_window_sec: DEFAULT_TWAP_WINDOW_SEC,
+            max_oracle_age_sec: DEFAULT_MAX_ORACLE_AGE_SEC,
+            max_deviation: None,
+        }
+    }
+}
+
+#[ext_contract(ext_oracle)]
+pub trait PriceOracle {
+    fn get_price_data(&self, asset_ids: Option<Vec<AssetId>>) -> PriceData;
+}
+
+#[ext_contract(ext_self)]
+pub trait OracleCallbacks {
+    fn on_oracle_prices(&mut self, #[callback] data: PriceData);
+}
+
+#[near_bindgen]
+impl Contract {
+    #[private]
+    pub fn set_price_config(&mut self, config: PriceConfig) {
+        if config.source == PriceSource::Oracle {
+            assert!(config.oracle_id.is_some(), "{}", ORC0);
+        }
+        if let Some(max_deviation) = config.max_deviation {
+            assert!(max_deviation > 0.0, "{}", ORC3);
+        }
+        assert!(
+            config.twap_window_sec as u64 <= MAX_TWAP_WINDOW_SEC,
+            "{}",
+            ORC5
+        );
+        self.price_config = config;
+    }
+
+    pub fn get_price_config(&self) -> PriceConfig {
+        self.price_config.clone()
+    }
+
+    /// Requests prices of all the reserve tokens from the oracle
+    pub fn refresh_oracle_prices(&mut self) -> Promise {
+        let oracle_id = self.price_config.oracle_id.clone().expect(ORC0);
+        let asset_ids = self.reserves.keys().collect();
+        ext_oracle::get_price_data(Some(asset_ids), &oracle_id, 0, GAS_FOR_GET_PRICE_DATA).then(
+            ext_self::on_oracle_prices(&env::current_account_id(), 0, GAS_FOR_ON_ORACLE_PRICES),
+        )
+    }
+
+    #[private]
+    pub fn on_oracle_prices(&mut self, #[callback] data: PriceData) {
+        for asset in data.prices {
+            if let Some(price) = asset.price {
+                self.oracle_prices.insert(
+                    asset.asset_id,
+                    OraclePrice {
+                        price,
+                        timestamp: data.timestamp.0,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn get_oracle_prices(&self) -> HashMap<AccountId, OraclePrice> {
+        self.oracle_prices.clone()
+    }
+
+    /// Price of token0 in token1 used to value borrows in the pool
+    pub fn get_valuation_price(&self, pool_id: usize) -> f64 {
+        self.assert_pool_exists(pool_id);
+        self.internal_valuation_price(pool_id)
+            .unwrap_or_else(|error| env::panic(error.as_bytes()))
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_valuation_price(&self, pool_id: usize) -> Result<f64, &'static str> {
+        let pool = &self.pools[pool_id];
+        let spot = pool.sqrt_price * pool.sqrt_price;
+        let price = match self.price_config.source {
+            PriceSource::Spot => spot,
+            PriceSource::Twap => self.internal_twap_price(pool_id)?,
+            PriceSource::Oracle => {
+                self.internal_oracle_price(&pool.token0)?
+                    / self.internal_oracle_price(&pool.token1)?
+            }
+        };
+        if let Some(max_deviation) = self.price_config.max_deviation {
+            if (spot / price - 1.0).abs() > max_deviation {
+                return Err(ORC2);
+            }
+        }
+        Ok(price)
+    }
+
+    /// Pool price averaged over the configured TWAP window, an error if the price history
+    /// of the pool is shorter than the window
+    pub(crate) fn internal_twap_price(&self, pool_id: usize) -> Result<f64, &'static str> {
+        let sqrt_price = self.pools[pool_id].get_twap_sqrt_price(
+            env::block_timestamp(),
+            self.price_config.twap_window_sec as u64 * 1_000_000_000,
+        )?;
+        Ok(sqrt_price.powi(2))
+    }
+
+    /// Makes the pool keep its TWAP at `timestamp` for an instrument settled at it,
+    /// see `Pool::request_twap_snapshot`
+    pub(crate) fn internal_request_settlement_price(&mut self, pool_id: usize, timestamp: u64) {
+        let window = self.price_config.twap_window_sec as u64 * 1_000_000_000;
+        self.pools[pool_id]
+            .request_twap_snapshot(timestamp, window)
+            .unwrap_or_else(|error| env::panic(error.as_bytes()));
+    }
+
+    /// Pool TWAP at `timestamp` that settles an instrument, taken from the snapshot
+    /// of the pool, see `Pool::take_twap_snapshot`
+    pub(crate) fn internal_take_settlement_price(&mut self, pool_id: usize, timestamp: u64) -> f64 {
+        let window = self.price_config.twap_window_sec as u64 * 1_000_000_000;
+        self.pools[pool_id]
+            .take_twap_snapshot(timestamp, window)
+            .unwrap_or_else(|error| env::panic(error.as_bytes()))
+            .powi(2)
+    }
+
+    fn internal_oracle_price(&self, token: &AccountId) -> Result<f64, &'static str> {
+        let oracle_price = self.oracle_prices.get(token).ok_or(ORC1)?;
+        let age = env::block_timestamp().saturating_sub(oracle_price.timestamp);
+        if age > self.price_config.max_oracle_age_sec as u64 * 1_000_000_000 {
+            return Err(ORC1);
+        }
+        Ok(oracle_price.price.to_f64())
+    }
+}
@@ -0,0 +1,529 @@
+use near_sdk::json_types::U128;
+
+use crate::futures::FuturesSide;
+use crate::*;
+
+/// Margin must be at least this share of the notional to open or change a position
+pub const PERPETUAL_INITIAL_MARGIN: f64 = 0.1;
+/// A position with equity below this share of the notional can be liquidated
+pub const PERPETUAL_MAINTENANCE_MARGIN: f64 = 0.05;
+/// Share of the notional the liquidator gets from the margin
+pub const PERPETUAL_LIQUIDATION_FEE: f64 = 0.01;
+/// Window of the pool TWAP used as the mark price
+pub const PERPETUAL_MARK_WINDOW_SEC: u64 = 60;
+/// Largest relative difference between the spot price positions open and close at
+/// and the mark price
+pub const PERPETUAL_MAX_PRICE_DEVIATION: f64 = 0.02;
+/// Funding is the premium of the mark over the index price paid once per this period
+pub const PERPETUAL_FUNDING_PERIOD_SEC: u64 = 3600;
+/// Largest funding rate per period
+pub const PERPETUAL_MAX_FUNDING_RATE: f64 = 0.01;
+
+pub type PerpetualId = u128;
+
+/// Position on token0 of the pool priced in token1 without expiration.
+/// The reserve of token1 is the counterparty of all the positions: it pays the profit of the
+/// traders and receives their losses. Margin is held in `token` (token1 of the pool).
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Perpetual {
+    pub id: PerpetualId,
+    pub pool_id: usize,
+    pub owner_id: AccountId,
+    pub side: FuturesSide,
+    pub size: u128,
+    pub entry_price: f64,
+    pub token: AccountId,
+    pub margin: u128,
+    /// Cumulative funding of the market when the position was opened
+    pub funding_index: f64,
+}
+
+impl Perpetual {
+    fn sign(&self) -> f64 {
+        match self.side {
+            FuturesSide::Long => 1.0,
+            FuturesSide::Short => -1.0,
+        }
+    }
+
+    pub fn notional(&self, price: f64) -> f64 {
+        self.size as f64 * price
+    }
+
+    pub fn pnl(&self, price: f64) -> f64 {
+        self.sign() * self.size as f64 * (price - self.entry_price)
+    }
+
+    /// Funding paid by the position since it was opened, negative if it was received
+    pub fn funding(&self, cumulative_funding: f64) -> f64 {
+        self.sign() * self.size as f64 * (cumulative_funding - self.funding_index)
+    }
+
+    pub fn equity(&self, price: f64, cumulative_funding: f64) -> f64 {
+        self.margin as f64 + self.pnl(price) - self.funding(cumulative_funding)
+    }
+}
+
+/// Open interest and funding of the perpetuals on a pool
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PerpetualMarket {
+    pub open_interest_long: u128,
+    pub open_interest_short: u128,
+    /// Largest open interest of each side in token0, no limit if not set
+    pub open_interest_cap: Option<U128>,
+    /// Funding paid by a long position of size 1 since the market was created, in token1
+    pub cumulative_funding: f64,
+    pub last_funding_ts: u64,
+}
+
+impl PerpetualMarket {
+    pub fn new(timestamp: u64) -> Self {
+        PerpetualMarket {
+            open_interest_long: 0,
+            open_interest_short: 0,
+            open_interest_cap: None,
+            cumulative_funding: 0.0,
+            last_funding_ts: timestamp,
+        }
+    }
+
+    /// Funding rate per period, the premium of the mark over the index price
+    pub fn funding_rate(mark_price: f64, index_price: f64) -> f64 {
+        ((mark_price - index_price) / index_price)
+            .clamp(-PERPETUAL_MAX_FUNDING_RATE, PERPETUAL_MAX_FUNDING_RATE)
+    }
+
+    /// Accrues funding at `rate` for the time since the last update
+    pub fn accrue_funding(&mut self, rate: f64, index_price: f64, timestamp: u64) {
+        let elapsed = timestamp.saturating_sub(self.last_funding_ts) as f64 / 1_000_000_000.0;
+        self.cumulative_funding +=
+            rate * index_price * elapsed / PERPETUAL_FUNDING_PERIOD_SEC as f64;
+        self.last_funding_ts = u64::max(timestamp, self.last_funding_ts);
+    }
+
+    pub fn open_interest(&self, side: FuturesSide) -> u128 {
+        match side {
+            FuturesSide::Long => self.open_interest_long,
+            FuturesSide::Short => self.open_interest_short,
+        }
+    }
+
+    pub fn increase_open_interest(&mut self, side: FuturesSide, size: u128) {
+        if let Some(cap) = self.open_interest_cap {
+            assert!(self.open_interest(side) + size <= cap.0, "{}", PRP2);
+        }
+        match side {
+            FuturesSide::Long => self.open_interest_long += size,
+            FuturesSide::Short => self.open_interest_short += size,
+        }
+    }
+
+    pub fn decrease_open_interest(&mut self, side: FuturesSide, size: u128) {
+        match side {
+            FuturesSide::Long => self.open_interest_long -= size,
+            FuturesSide::Short => self.open_interest_short -= size,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PerpetualStatus {
+    pub mark_price: f64,
+    pub index_price: f64,
+    pub pnl: f64,
+    pub funding: f64,
+    pub equity: f64,
+    /// Equity divided by the notional at the mark price
+    pub margin_ratio: f64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Opens a position of `size` token0 at the pool price with `margin` of token1
+    /// from the internal balance. Token1 of the pool must have a reserve.
+    pub fn open_perpetual(
+        &mut self,
+        pool_id: usize,
+        side: FuturesSide,
+        size: U128,
+        margin: U128,
+    ) -> PerpetualId {
+        let account_id = env::predecessor_account_id();
+        self.internal_open_perpetual(&account_id, pool_id, side, size, margin)
+    }
+
+    pub(crate) fn internal_open_perpetual(
+        &mut self,
+        account_id: &AccountId,
+        pool_id: usize,
+        side: FuturesSide,
+        size: U128,
+        margin: U128,
+    ) -> PerpetualId {
+        self.assert_pool_exists(pool_id);
+        assert!(size.0 > 0, "{}", PRP5);
+        let initial_storage = self.internal_storage_usage();
+        let token = self.pools[pool_id].token1.clone();
+        self.reserves.get(&token).expect(RSR0);
+        let mut market = self.internal_refresh_perpetual_market(pool_id);
+        market.increase_open_interest(side, size.0);
+        let perpetual = Perpetual {
+            id: self.perpetuals_number,
+            pool_id,
+            owner_id: account_id.clone(),
+            side,
+            size: size.0,
+            entry_price: self.internal_perpetual_trade_price(pool_id),
+            token,
+            margin: margin.0,
+            funding_index: market.cumulative_funding,
+        };
+        assert!(
+            margin.0 as f64 >= PERPETUAL_INITIAL_MARGIN * perpetual.notional(perpetual.entry_price),
+            "{}",
+            PRP1
+        );
+        self.decrease_balance(account_id, &perpetual.token, margin.0);
+        self.perpetual_markets.insert(pool_id, market);
+        self.perpetuals.insert(&perpetual.id, &perpetual);
+        self.perpetuals_number += 1;
+        self.internal_charge_storage(account_id, initial_storage);
+        perpetual.id
+    }
+
+    pub fn add_perpetual_margin(&mut self, perpetual_id: PerpetualId, amount: U128) {
+        let account_id = env::predecessor_account_id();
+        let mut perpetual = self.perpetuals.get(&perpetual_id).expect(PRP0);
+        assert_eq!(perpetual.owner_id, account_id, "{}", PRP4);
+        self.decrease_balance(&account_id, &perpetual.token, amount.0);
+        perpetual.margin += amount.0;
+        self.perpetuals.insert(&perpetual_id, &perpetual);
+    }
+
+    /// Closes the position at the pool price, returns the amount paid to the owner, the profit
+    /// the reserve does not have the liquidity for is left as a claim
+    pub fn close_perpetual(&mut self, perpetual_id: PerpetualId) -> U128 {
+        let account_id = env::predecessor_account_id();
+        self.internal_close_perpetual(&account_id, perpetual_id)
+    }
+
+    pub(crate) fn internal_close_perpetual(
+        &mut self,
+        account_id: &AccountId,
+        perpetual_id: PerpetualId,
+    ) -> U128 {
+        let perpetual = self.perpetuals.get(&perpetual_id).expect(PRP0);
+        assert_eq!(&perpetual.owner_id, account_id, "{}", PRP4);
+        let market = self.internal_refresh_perpetual_market(perpetual.pool_id);
+        let price = self.internal_perpetual_trade_price(perpetual.pool_id);
+        let equity = perpetual.equity(price, market.cumulative_funding);
+        let payout = equity.max(0.0) as u128;
+        let paid =
+            self.internal_settle_perpetual(&perpetual, market, equity, payout, 0, account_id);
+        U128(paid)
+    }
+
+    /// Pays the profit of closed positions the reserve of `token` did not have the liquidity
+    /// for, as much of it as the reserve can pay now. Returns the amount paid.
+    pub fn claim_perpetual_profit(&mut self, token: AccountId) -> U128 {
+        let account_id = env::predecessor_account_id();
+        self.internal_claim_perpetual_profit(&account_id, &token)
+    }
+
+    pub(crate) fn internal_claim_perpetual_profit(
+        &mut self,
+        account_id: &AccountId,
+        token: &AccountId,
+    ) -> U128 {
+        let initial_storage = self.internal_storage_usage();
+        let mut claims = self.perpetual_claims.get(account_id).expect(PRP7);
+        let claim = *claims.get(token).expect(PRP7);
+        let mut reserve = self.reserves.get(token).expect(RSR0);
+        let paid = reserve.pay_trading_profit(claim);
+        assert!(paid > 0, "{}", RSR9);
+        self.reserves.insert(token, &reserve);
+        if paid == claim {
+            claims.remove(token);
+        } else {
+            claims.insert(token.clone(), claim - paid);
+        }
+        if claims.is_empty() {
+            self.perpetual_claims.remove(account_id);
+        } else {
+            self.perpetual_claims.insert(account_id, &claims);
+        }
+        self.increase_balance(account_id, token, paid);
+        self.internal_charge_storage(account_id, initial_storage);
+        U128(paid)
+    }
+
+    /// Profit of closed positions the reserves still owe to the account, per token
+    pub fn get_perpetual_claims(&self, account_id: AccountId) -> HashMap<AccountId, U128> {
+        self.perpetual_claims
+            .get(&account_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(token, amount)| (token, U128(amount)))
+            .collect()
+    }
+
+    /// Accrues funding of the pool at the current rate, positions accrue it only when they
+    /// change, so keepers call it regularly to follow the rate
+    pub fn refresh_perpetual_funding(&mut self, pool_id: usize) {
+        self.assert_pool_exists(pool_id);
+        self.internal_refresh_perpetual_market(pool_id);
+    }
+
+    #[private]
+    pub fn set_perpetual_open_interest_cap(&mut self, pool_id: usize, cap: Option<U128>) {
+        self.assert_pool_exists(pool_id);
+        let mut market = self.internal_refresh_perpetual_market(pool_id);
+        market.open_interest_cap = cap;
+        self.perpetual_markets.insert(pool_id, market);
+    }
+
+    pub fn get_perpetual(&self, perpetual_id: PerpetualId) -> Perpetual {
+        self.perpetuals.get(&perpetual_id).expect(PRP0)
+    }
+
+    pub fn get_account_perpetuals(&self, account_id: AccountId) -> Vec<Perpetual> {
+        self.perpetuals
+            .iter()
+            .filter(|(_, perpetual)| perpetual.owner_id == account_id)
+            .map(|(_, perpetual)| perpetual)
+            .collect()
+    }
+
+    /// Open interest and funding of the pool with the funding accrued up to now
+    pub fn get_perpetual_market(&self, pool_id: usize) -> PerpetualMarket {
+        self.assert_pool_exists(pool_id);
+        self.internal_perpetual_market(pool_id)
+    }
+
+    /// Current funding rate per period, longs pay shorts if it is positive
+    pub fn get_perpetual_funding_rate(&self, pool_id: usize) -> f64 {
+        self.assert_pool_exists(pool_id);
+        PerpetualMarket::funding_rate(
+            self.internal_perpetual_mark_price(pool_id),
+            self.internal_perpetual_index_price(pool_id),
+        )
+    }
+
+    pub fn get_perpetual_status(&self, perpetual_id: PerpetualId) -> PerpetualStatus {
+        let perpetual = self.perpetuals.get(&perpetual_id).expect(PRP0);
+        let market = self.internal_perpetual_market(perpetual.pool_id);
+        let mark_price = self.internal_perpetual_mark_price(perpetual.pool_id);
+        let equity = perpetual.equity(mark_price, market.cumulative_funding);
+        PerpetualStatus {
+            mark_price,
+            index_price: self.internal_perpetual_index_price(perpetual.pool_id),
+            pnl: perpetual.pnl(mark_price),
+            funding: perpetual.funding(market.cumulative_funding),
+            equity,
+            margin_ratio: equity / perpetual.notional(mark_price),
+        }
+    }
+}
+
+impl Contract {
+    /// Closes a position below the maintenance margin at the mark price. The liquidator gets
+    /// the liquidation fee from the margin and the owner what is left of the equity.
+    /// If the margin cannot pay the loss and the fee, the insurance fund covers the rest
+    /// to the reserve. Returns the fee received.
+    pub(crate) fn internal_liquidate_perpetual(
+        &mut self,
+        account_id: &AccountId,
+        perpetual_id: PerpetualId,
+    ) -> U128 {
+        let perpetual = self.perpetuals.get(&perpetual_id).expect(PRP0);
+        let market = self.internal_refresh_perpetual_market(perpetual.pool_id);
+        let mark_price = self.internal_perpetual_mark_price(perpetual.pool_id);
+        let equity = perpetual.equity(mark_price, market.cumulative_funding);
+        let notional = perpetual.notional(mark_price);
+        assert!(equity < PERPETUAL_MAINTENANCE_MARGIN * notional, "{}", PRP3);
+        let fee = u128::min(
+            (PERPETUAL_LIQUIDATION_FEE * notional) as u128,
+            perpetual.margin,
+        );
+        let payout = (equity - fee as f64).max(0.0) as u128;
+        self.internal_settle_perpetual(&perpetual, market, equity, payout, fee, account_id);
+        U128(fee)
+    }
+
+    /// Positions below the maintenance margin
+    pub(crate) fn internal_perpetual_liquidation_list(&self) -> Vec<PerpetualId> {
+        self.perpetuals
+            .iter()
+            .filter(|(_, perpetual)| {
+                let market = self.internal_perpetual_market(perpetual.pool_id);
+                let mark_price = self.internal_perpetual_mark_price(perpetual.pool_id);
+                perpetual.equity(mark_price, market.cumulative_funding)
+                    < PERPETUAL_MAINTENANCE_MARGIN * perpetual.notional(mark_price)
+            })
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Pool spot price positions open and close at, it must be close to the mark price
+    fn internal_perpetual_trade_price(&self, pool_id: usize) -> f64 {
+        let sqrt_price = self.pools[pool_id].sqrt_price;
+        let price = sqrt_price * sqrt_price;
+        let mark_price = self.internal_perpetual_mark_price(pool_id);
+        assert!(
+            (price / mark_price - 1.0).abs() <= PERPETUAL_MAX_PRICE_DEVIATION,
+            "{}",
+            PRP6
+        );
+        price
+    }
+
+    fn internal_perpetual_mark_price(&self, pool_id: usize) -> f64 {
+        self.pools[pool_id]
+            .get_twap_sqrt_price(
+                env::block_timestamp(),
+                PERPETUAL_MARK_WINDOW_SEC * 1_000_000_000,
+            )
+            .unwrap_or_else(|error| env::panic(error.as_bytes()))
+            .powi(2)
+    }
+
+    fn internal_perpetual_index_price(&self, pool_id: usize) -> f64 {
+        self.internal_valuation_price(pool_id)
+            .unwrap_or_else(|error| env::panic(error.as_bytes()))
+    }
+
+    /// Market of the pool with the funding accrued up to now, not stored
+    fn internal_perpetual_market(&self, pool_id: usize) -> PerpetualMarket {
+        let now = env::block_timestamp();
+        let mut market = self
+            .perpetual_markets
+            .get(&pool_id)
+            .cloned()
+            .unwrap_or_else(|| PerpetualMarket::new(now));
+        if now > market.last_funding_ts {
+            let index_price = self.internal_perpetual_index_price(pool_id);
+            let rate = PerpetualMarket::funding_rate(
+                self.internal_perpetual_mark_price(pool_id),
+                index_price,
+            );
+            market.accrue_funding(rate, index_price, now);
+        }
+        market
+    }
+
+    fn internal_refresh_perpetual_market(&mut self, pool_id: usize) -> PerpetualMarket {
+        let market = self.internal_perpetual_market(pool_id);
+        self.perpetual_markets.insert(pool_id, market.clone());
+        market
+    }
+
+    /// Pays `payout` to the owner and `fee` to the liquidator and settles the rest
+    /// of the margin with the reserve, which owes the position `equity - margin`.
+    /// The profit the reserve does not have the liquidity for is left to the owner as a claim.
+    /// Returns the amount paid to the owner.
+    fn internal_settle_perpetual(
+        &mut self,
+        perpetual: &Perpetual,
+        mut market: PerpetualMarket,
+        equity: f64,
+        payout: u128,
+        fee: u128,
+        liquidator_id: &AccountId,
+    ) -> u128 {
+        let initial_storage = self.internal_storage_usage();
+        self.perpetuals.remove(&perpetual.id);
+        market.decrease_open_interest(perpetual.side, perpetual.size);
+        self.perpetual_markets.insert(perpetual.pool_id, market);
+        let mut reserve = self.reserves.get(&perpetual.token).expect(RSR0);
+        let mut unpaid = 0;
+        if payout + fee > perpetual.margin {
+            let profit = payout + fee - perpetual.margin;
+            unpaid = profit - reserve.pay_trading_profit(profit);
+        } else {
+            let mut received = perpetual.margin - payout - fee;
+            // the margin did not pay the whole loss of the position
+            let deficit = (perpetual.margin as f64 - equity).max(0.0) as u128;
+            if deficit > received {
+                received += self
+                    .insurance_fund
+                    .cover(&perpetual.token, deficit - received);
+            }
+            reserve.add_trading_loss(received);
+        }
+        self.reserves.insert(&perpetual.token, &reserve);
+        // the fee is paid out of the margin, so only the owner can be left with a claim
+        let paid = payout - unpaid;
+        self.increase_balance(&perpetual.owner_id, &perpetual.token, paid);
+        if unpaid > 0 {
+            let mut claims = self
+                .perpetual_claims
+                .get(&perpetual.owner_id)
+                .unwrap_or_default();
+            *claims.entry(perpetual.token.clone()).or_insert(0) += unpaid;
+            self.perpetual_claims.insert(&perpetual.owner_id, &claims);
+        }
+        self.internal_charge_storage(&perpetual.owner_id, initial_storage);
+        if fee > 0 {
+            let initial_storage = self.internal_storage_usage();
+            self.increase_balance(liquidator_id, &perpetual.token, fee);
+            self.internal_charge_storage(liquidator_id, initial_storage);
+        }
+        paid
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::perpetual::*;
+
+    fn perpetual(side: FuturesSide) -> Perpetual {
+        Perpetual {
+            id: 0,
+            pool_id: 0,
+            owner_id: "alice".to_string(),
+            side,
+            size: 10,
+            entry_price: 100.0,
+            token: "usn".to_string(),
+            margin: 100,
+            funding_index: 1.0,
+        }
+    }
+
+    #[test]
+    fn perpetual_equity() {
+        let long = perpetual(FuturesSide::Long);
+        let short = perpetual(FuturesSide::Short);
+        assert_eq!(long.pnl(110.0), 100.0);
+        assert_eq!(short.pnl(110.0), -100.0);
+        assert_eq!(long.funding(1.5), 5.0);
+        assert_eq!(short.funding(1.5), -5.0);
+        assert_eq!(long.equity(110.0, 1.5), 195.0);
+        assert_eq!(short.equity(110.0, 1.5), 5.0);
+    }
+
+    #[test]
+    fn perpetual_funding() {
+        assert_eq!(PerpetualMarket::funding_rate(100.5, 100.0), 0.005);
+        assert_eq!(
+            PerpetualMarket::funding_rate(90.0, 100.0),
+            -PERPETUAL_MAX_FUNDING_RATE
+        );
+        let mut market = PerpetualMarket::new(0);
+        market.accrue_funding(0.01, 100.0, 1800 * 1_000_000_000);
+        assert!((market.cumulative_funding - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    #[should_panic(expected = "Open interest cap of the pool is exceeded")]
+    fn perpetual_open_interest_cap() {
+        let mut market = PerpetualMarket::new(0);
+        market.open_interest_cap = Some(U128(100));
+        market.increase_open_interest(FuturesSide::Long, 100);
+        market.increase_open_interest(FuturesSide::Short, 100);
+        market.increase_open_interest(FuturesSide::Long, 1);
+    }
+}
@@ -3,15 +3,21 @@ use std::collections::HashMap;
 use near_sdk::{
     borsh::{self, BorshDeserialize, BorshSerialize},
     serde::Serialize,
-    AccountId,
+    AccountId, StorageUsage,
 };
 
 use crate::{
     balance::{PST0, SWP1},
+    errors::ORC4,
     position::{sqrt_price_to_tick, tick_to_sqrt_price, Position},
     BASIS_POINT_TO_PERCENT,
 };
 
+/// Bytes a position takes in the contract state together with its id
+pub(crate) fn position_storage_usage(position: &Position) -> StorageUsage {
+    (std::mem::size_of::<u128>() + position.try_to_vec().unwrap().len()) as StorageUsage
+}
+
 #[derive(Clone)]
 pub struct CollectedFee {
     pub account_id: AccountId,
@@ -27,6 +33,36 @@ pub struct SwapResult {
     pub collected_fees: HashMap<u128, CollectedFee>,
 }
 
+/// Maximum number of price observations kept for the TWAP
+pub const OBSERVATIONS_MAX: usize = 64;
+/// Observations are at least this far apart, the last one moves with the price
+/// until this much time has passed since the one before it
+pub const OBSERVATION_PERIOD_SEC: u64 = 60;
+/// Longest TWAP window the kept observations always cover
+pub const MAX_TWAP_WINDOW_SEC: u64 = (OBSERVATIONS_MAX as u64 - 2) * OBSERVATION_PERIOD_SEC;
+pub const NS_IN_YEAR: f64 = 31_536_000_000_000_000.0;
+
+/// Price of the pool since `timestamp` together with the time-weighted sum of earlier prices
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Observation {
+    pub timestamp: u64,
+    pub sqrt_price: f64,
+    pub cumulative: f64,
+}
+
+/// TWAP over `window` nanoseconds ending at `timestamp` that settles the instruments
+/// expiring then, taken at the first refresh past `timestamp` so that settling later
+/// does not need the observations that are dropped meanwhile
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
+pub struct TwapSnapshot {
+    pub timestamp: u64,
+    pub window: u64,
+    pub sqrt_price: Option<f64>,
+    /// Number of unsettled instruments waiting for the snapshot
+    pub requests: u32,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum SwapDirection {
     Return,
@@ -46,6 +82,13 @@ pub struct Pool {
     pub positions: HashMap<u128, Position>,
     pub protocol_fee: u16,
     pub rewards: u16,
+    #[serde(skip)]
+    pub observations: Vec<Observation>,
+    #[serde(skip)]
+    pub twap_snapshots: Vec<TwapSnapshot>,
+    /// Bytes the positions with their ids take in the contract state
+    #[serde(skip)]
+    pub positions_storage_usage: StorageUsage,
 }
 
 impl Pool {
@@ -68,6 +111,9 @@ impl Pool {
             tick,
             protocol_fee,
             rewards,
+            observations: Vec::new(),
+            twap_snapshots: Vec::new(),
+            positions_storage_usage: 0,
         }
     }
 
@@ -77,14 +123,25 @@ impl Pool {
         amount: u128,
         direction: SwapDirection,
     ) -> SwapResult {
+        self.try_get_swap_result(token, amount, direction)
+            .unwrap_or_else(|| panic!("{}", SWP1))
+    }
+
+    /// Same as `get_swap_result`, `None` if the pool does not have the liquidity for the swap
+    pub fn try_get_swap_result(
+        &self,
+        token: &AccountId,
+        amount: u128,
+        direction: SwapDirection,
+    ) -> Option<SwapResult> {
         if direction == SwapDirection::Return {
             if token == &self.token0 {
                 if amount > self.token0_locked {
-                    panic!("{}", SWP1);
+                    return None;
                 }
             } else {
                 if amount > self.token1_locked {
-                    panic!("{}", SWP1);
+                    return None;
                 }
             }
         }
@@ -96,7 +153,7 @@ impl Pool {
         while remaining > 0.0 {
             let liquidity = self.calculate_liquidity_within_tick(price);
             if liquidity == 0.0 && !self.check_available_liquidity(price, token, direction) {
-                panic!("{}", SWP1);
+                return None;
             }
             let temp = match direction {
                 SwapDirection::Expense => self.get_amount_in_within_tick(
@@ -118,12 +175,12 @@ impl Pool {
             collected += temp;
         }
         let liquidity = self.calculate_liquidity_within_tick(price);
-        SwapResult {
+        Some(SwapResult {
             amount: collected,
             new_liquidity: liquidity,
             new_sqrt_price: price,
             collected_fees,
-        }
+        })
     }
 
     fn collect_fees(
@@ -305,9 +362,171 @@ impl Pool {
         self.liquidity = liquidity;
         self.token0_locked = token0_locked.round() as u128;
         self.token1_locked = token1_locked.round() as u128;
+        self.record_observation(current_timestamp);
+    }
+
+    fn record_observation(&mut self, current_timestamp: u64) {
+        // the snapshots are taken before the new observation may replace the one they need
+        for index in 0..self.twap_snapshots.len() {
+            let snapshot = &self.twap_snapshots[index];
+            if snapshot.sqrt_price.is_none() && snapshot.timestamp <= current_timestamp {
+                let sqrt_price = self
+                    .get_twap_sqrt_price(snapshot.timestamp, snapshot.window)
+                    .ok();
+                self.twap_snapshots[index].sqrt_price = sqrt_price;
+            }
+        }
+        let sqrt_price = self.sqrt_price;
+        let len = self.observations.len();
+        match self.observations.last() {
+            Some(last) if last.timestamp >= current_timestamp => {
+                self.observations[len - 1].sqrt_price = sqrt_price;
+            }
+            Some(last) => {
+                let observation = Observation {
+                    timestamp: current_timestamp,
+                    sqrt_price,
+                    cumulative: last.cumulative
+                        + last.sqrt_price * (current_timestamp - last.timestamp) as f64,
+                };
+                // the sum up to the moved observation stays exact, only the prices
+                // between it and the one before it are lost
+                let period = OBSERVATION_PERIOD_SEC * 1_000_000_000;
+                match len.checked_sub(2).map(|index| &self.observations[index]) {
+                    Some(before) if last.timestamp - before.timestamp < period => {
+                        self.observations[len - 1] = observation;
+                    }
+                    _ => self.observations.push(observation),
+                }
+            }
+            None => self.observations.push(Observation {
+                timestamp: current_timestamp,
+                sqrt_price,
+                cumulative: 0.0,
+            }),
+        }
+        if self.observations.len() > OBSERVATIONS_MAX {
+            self.observations.remove(0);
+        }
+    }
+
+    /// Annualized volatility of the price estimated from the log returns between
+    /// the observations, `None` if the history covers no time
+    pub fn get_volatility(&self) -> Option<f64> {
+        let mut variance = 0.0;
+        let mut duration = 0;
+        for pair in self.observations.windows(2) {
+            let log_return = 2.0 * (pair[1].sqrt_price / pair[0].sqrt_price).ln();
+            variance += log_return * log_return;
+            duration += pair[1].timestamp - pair[0].timestamp;
+        }
+        match duration {
+            0 => None,
+            _ => Some((variance / duration as f64 * NS_IN_YEAR).sqrt()),
+        }
+    }
+
+    /// Time-weighted average of the sqrt price over the `window` nanoseconds ending at
+    /// `end_timestamp`, an error if the observations do not cover the window
+    pub fn get_twap_sqrt_price(
+        &self,
+        end_timestamp: u64,
+        window: u64,
+    ) -> Result<f64, &'static str> {
+        let first = self.observations.first().ok_or(ORC4)?;
+        let start = end_timestamp.checked_sub(window).ok_or(ORC4)?;
+        if start < first.timestamp {
+            return Err(ORC4);
+        }
+        let (sqrt_price_end, cumulative_end) = self.get_cumulative(end_timestamp);
+        if window == 0 {
+            return Ok(sqrt_price_end);
+        }
+        let (_, cumulative_start) = self.get_cumulative(start);
+        Ok((cumulative_end - cumulative_start) / window as f64)
+    }
+
+    /// Asks for the TWAP snapshot at `timestamp`, the window of the first request for
+    /// the timestamp is used. An error if the window would start before the price history.
+    pub fn request_twap_snapshot(
+        &mut self,
+        timestamp: u64,
+        window: u64,
+    ) -> Result<(), &'static str> {
+        match self
+            .twap_snapshots
+            .iter_mut()
+            .find(|snapshot| snapshot.timestamp == timestamp)
+        {
+            Some(snapshot) => snapshot.requests += 1,
+            None => {
+                let first = self.observations.first().ok_or(ORC4)?;
+                if timestamp.checked_sub(window).ok_or(ORC4)? < first.timestamp {
+                    return Err(ORC4);
+                }
+                self.twap_snapshots.push(TwapSnapshot {
+                    timestamp,
+                    window,
+                    sqrt_price: None,
+                    requests: 1,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops a request for the TWAP snapshot at `timestamp`, the snapshot is removed
+    /// with the last one
+    pub fn release_twap_snapshot(&mut self, timestamp: u64) {
+        if let Some(index) = self
+            .twap_snapshots
+            .iter()
+            .position(|snapshot| snapshot.timestamp == timestamp)
+        {
+            self.twap_snapshots[index].requests -= 1;
+            if self.twap_snapshots[index].requests == 0 {
+                self.twap_snapshots.remove(index);
+            }
+        }
+    }
+
+    /// TWAP sqrt price at `timestamp` for a settlement, from the snapshot if it was taken
+    /// and from the observations otherwise. The request for the snapshot is dropped.
+    pub fn take_twap_snapshot(&mut self, timestamp: u64, window: u64) -> Result<f64, &'static str> {
+        let snapshot = self
+            .twap_snapshots
+            .iter()
+            .find(|snapshot| snapshot.timestamp == timestamp);
+        let sqrt_price = match snapshot {
+            Some(TwapSnapshot {
+                sqrt_price: Some(sqrt_price),
+                ..
+            }) => *sqrt_price,
+            Some(snapshot) => self.get_twap_sqrt_price(timestamp, snapshot.window)?,
+            None => self.get_twap_sqrt_price(timestamp, window)?,
+        };
+        self.release_twap_snapshot(timestamp);
+        Ok(sqrt_price)
+    }
+
+    /// Sqrt price at `timestamp` and the time-weighted sum of the sqrt price up to it,
+    /// taken from the last observation before it
+    fn get_cumulative(&self, timestamp: u64) -> (f64, f64) {
+        let observation = self
+            .observations
+            .iter()
+            .rev()
+            .find(|observation| observation.timestamp <= timestamp)
+            .unwrap_or(&self.observations[0]);
+        let elapsed = timestamp.saturating_sub(observation.timestamp);
+        (
+            observation.sqrt_price,
+            observation.cumulative + observation.sqrt_price * elapsed as f64,
+        )
     }
 
     pub fn open_position(&mut self, id: u128, position: Position) {
+        self.positions_storage_usage += position_storage_usage(&position);
         self.positions.insert(id, position);
     }
 
@@ -318,9 +537,19 @@ impl Pool {
             self.token0_locked -= position.token0_locked.round() as u128;
             self.token1_locked -= position.token1_locked.round() as u128;
         }
+        self.positions_storage_usage -= position_storage_usage(position);
         self.positions.remove(&id);
     }
 
+    /// Gives the position to a new owner if the pool has it
+    pub fn transfer_position(&mut self, id: u128, owner_id: &AccountId) {
+        if let Some(position) = self.positions.get_mut(&id) {
+            self.positions_storage_usage -= position_storage_usage(position);
+            position.owner_id = owner_id.clone();
+            self.positions_storage_usage += position_storage_usage(position);
+        }
+    }
+
     pub fn apply_swap_result(&mut self, swap_result: &SwapResult) {
         self.liquidity = swap_result.new_liquidity;
         self.sqrt_price = swap_result.new_sqrt_price;
@@ -339,7 +568,11 @@ impl Pool {
 
 #[cfg(test)]
 mod test {
-    use crate::{pool::SwapDirection, position::sqrt_price_to_tick, *};
+    use crate::{
+        pool::{SwapDirection, MAX_TWAP_WINDOW_SEC, NS_IN_YEAR, OBSERVATIONS_MAX},
+        position::sqrt_price_to_tick,
+        *,
+    };
     #[test]
     fn pool_get_expense_x() {
         let token0 = "first".to_string();
@@ -750,4 +983,122 @@ mod test {
         let token0_locked2 = position.token0_locked;
         assert!((liquidity1 / liquidity2) == (token0_locked1 / token0_locked2));
     }
+
+    #[test]
+    fn pool_twap() {
+        const S: u64 = 1_000_000_000;
+        let mut pool = Pool::new("first".to_string(), "second".to_string(), 100.0, 0, 0);
+        assert_eq!(pool.get_twap_sqrt_price(1000 * S, 100 * S), Err(ORC4));
+        pool.refresh(0);
+        pool.sqrt_price = 12.0;
+        pool.refresh(100 * S);
+        assert_eq!(pool.get_twap_sqrt_price(100 * S, 100 * S), Ok(10.0));
+        assert_eq!(pool.get_twap_sqrt_price(150 * S, 100 * S), Ok(11.0));
+        assert_eq!(pool.get_twap_sqrt_price(300 * S, 100 * S), Ok(12.0));
+        // history shorter than the window
+        assert_eq!(pool.get_twap_sqrt_price(200 * S, 1000 * S), Err(ORC4));
+        // windows in the past are not affected by later prices
+        pool.sqrt_price = 20.0;
+        pool.refresh(200 * S);
+        assert_eq!(pool.get_twap_sqrt_price(150 * S, 100 * S), Ok(11.0));
+        assert_eq!(pool.get_twap_sqrt_price(300 * S, 100 * S), Ok(20.0));
+    }
+
+    #[test]
+    fn pool_twap_same_block() {
+        const S: u64 = 1_000_000_000;
+        let mut pool = Pool::new("first".to_string(), "second".to_string(), 100.0, 0, 0);
+        pool.refresh(0);
+        pool.sqrt_price = 12.0;
+        pool.refresh(100 * S);
+        pool.sqrt_price = 14.0;
+        pool.refresh(100 * S);
+        assert_eq!(pool.observations.len(), 2);
+        assert_eq!(pool.get_twap_sqrt_price(200 * S, 200 * S), Ok(12.0));
+    }
+
+    #[test]
+    fn pool_twap_observation_period() {
+        const S: u64 = 1_000_000_000;
+        let mut pool = Pool::new("first".to_string(), "second".to_string(), 100.0, 0, 0);
+        pool.refresh(0);
+        // the observation after the first one moves with the price within the period
+        for second in 1..=30 {
+            pool.sqrt_price = 10.0 + second as f64;
+            pool.refresh(second * S);
+        }
+        assert_eq!(pool.observations.len(), 2);
+        assert_eq!(pool.get_twap_sqrt_price(30 * S, 30 * S), Ok(24.5));
+    }
+
+    #[test]
+    fn pool_twap_covers_max_window() {
+        const S: u64 = 1_000_000_000;
+        let mut pool = Pool::new("first".to_string(), "second".to_string(), 100.0, 0, 0);
+        // a refresh every second does not shorten the history below the longest window
+        for second in 0..=10_000 {
+            pool.refresh(second * S);
+        }
+        assert_eq!(pool.observations.len(), OBSERVATIONS_MAX);
+        let window = MAX_TWAP_WINDOW_SEC * S;
+        assert_eq!(pool.get_twap_sqrt_price(10_000 * S, window), Ok(10.0));
+        assert_eq!(
+            pool.get_twap_sqrt_price(10_000 * S, window + 60 * S),
+            Err(ORC4)
+        );
+    }
+
+    #[test]
+    fn pool_twap_snapshot() {
+        const S: u64 = 1_000_000_000;
+        let mut pool = Pool::new("first".to_string(), "second".to_string(), 100.0, 0, 0);
+        pool.refresh(0);
+        assert_eq!(pool.request_twap_snapshot(50 * S, 100 * S), Err(ORC4));
+        pool.request_twap_snapshot(200 * S, 100 * S).unwrap();
+        pool.request_twap_snapshot(200 * S, 100 * S).unwrap();
+        pool.sqrt_price = 12.0;
+        pool.refresh(150 * S);
+        assert!(pool.twap_snapshots[0].sqrt_price.is_none());
+        // taken at the first refresh past the timestamp, before the later prices
+        pool.sqrt_price = 20.0;
+        pool.refresh(250 * S);
+        for second in 251..=10_000 {
+            pool.refresh(second * S);
+        }
+        assert_eq!(pool.get_twap_sqrt_price(200 * S, 100 * S), Err(ORC4));
+        assert_eq!(pool.take_twap_snapshot(200 * S, 100 * S), Ok(11.0));
+        assert_eq!(pool.twap_snapshots.len(), 1);
+        assert_eq!(pool.take_twap_snapshot(200 * S, 100 * S), Ok(11.0));
+        assert!(pool.twap_snapshots.is_empty());
+    }
+
+    #[test]
+    fn pool_twap_snapshot_released() {
+        const S: u64 = 1_000_000_000;
+        let mut pool = Pool::new("first".to_string(), "second".to_string(), 100.0, 0, 0);
+        pool.refresh(0);
+        pool.request_twap_snapshot(200 * S, 100 * S).unwrap();
+        pool.release_twap_snapshot(200 * S);
+        assert!(pool.twap_snapshots.is_empty());
+        // without a snapshot the settlement price comes from the observations
+        pool.refresh(300 * S);
+        assert_eq!(pool.take_twap_snapshot(200 * S, 100 * S), Ok(10.0));
+    }
+
+    #[test]
+    fn pool_volatility() {
+        let mut pool = Pool::new("first".to_string(), "second".to_string(), 100.0, 0, 0);
+        assert_eq!(pool.get_volatility(), None);
+        pool.refresh(0);
+        pool.refresh(NS_IN_YEAR as u64 / 2);
+        assert_eq!(pool.get_volatility(), Some(0.0));
+        // the price goes up 10% in half a year and back in the other half
+        pool.sqrt_price = (110.0f64).sqrt();
+        pool.refresh(NS_IN_YEAR as u64 * 3 / 4);
+        pool.sqrt_price = 10.0;
+        pool.refresh(NS_IN_YEAR as u64);
+        let log_return = (1.1f64).ln();
+        let expected = (2.0 * log_return * log_return).sqrt();
+        assert!((pool.get_volatility().unwrap() - expected).abs() < 1e-9);
+    }
 }
@@ -0,0 +1,232 @@
+use crate::position::{calculate_x, calculate_y};
+use crate::*;
+
+/// Liquidity position at the current pool price
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PortfolioPosition {
+    pub pool_id: usize,
+    pub position_id: u128,
+    pub token0: AccountId,
+    pub token1: AccountId,
+    pub amount0: U128,
+    pub amount1: U128,
+    pub is_active: bool,
+    /// Fees are credited to the balance of the owner with every swap,
+    /// so they are already part of `balances`
+    pub fees_earned0: U128,
+    pub fees_earned1: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PortfolioDeposit {
+    pub asset: AccountId,
+    pub amount: U128,
+    /// Growth not taken yet including the one accrued since the last update
+    pub interest: U128,
+    pub apr: u16,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PortfolioBorrow {
+    pub borrow_id: BorrowId,
+    pub pool_id: usize,
+    pub position_id: u128,
+    pub borrowed0: U128,
+    pub borrowed1: U128,
+    /// Fees in token1 including the ones accrued since the last update
+    pub fees: U128,
+    /// None if the price source of the pool is unavailable
+    pub health_factor: Option<f64>,
+}
+
+/// Value of the portfolio in `quote_token` at the spot prices of pools,
+/// borrows are subtracted
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PortfolioValuation {
+    pub quote_token: AccountId,
+    pub value: f64,
+    /// Tokens without a pool with the quote token, they are not part of `value`
+    pub unpriced_tokens: Vec<AccountId>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountPortfolio {
+    pub balances: HashMap<AccountId, U128>,
+    pub positions: Vec<PortfolioPosition>,
+    pub deposits: Vec<PortfolioDeposit>,
+    pub borrows: Vec<PortfolioBorrow>,
+    pub valuation: Option<PortfolioValuation>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Everything the account holds in the exchange, valued in `quote_token` if it is set.
+    /// Unknown accounts have an empty portfolio.
+    pub fn get_account_portfolio(
+        &self,
+        account_id: AccountId,
+        quote_token: Option<AccountId>,
+    ) -> AccountPortfolio {
+        let timestamp = env::block_timestamp();
+        let balances = self
+            .balances_map
+            .get(&account_id)
+            .map(|balance| {
+                balance
+                    .iter()
+                    .map(|(token, amount)| (token.clone(), U128(amount)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut positions = vec![];
+        for (pool_id, pool) in self.pools.iter().enumerate() {
+            for (position_id, position) in &pool.positions {
+                if position.owner_id != account_id {
+                    continue;
+                }
+                let (sqrt_lower, sqrt_upper) =
+                    (position.sqrt_lower_bound_price, position.sqrt_upper_bound_price);
+                let amount0 =
+                    calculate_x(position.liquidity, pool.sqrt_price, sqrt_lower, sqrt_upper);
+                let amount1 =
+                    calculate_y(position.liquidity, pool.sqrt_price, sqrt_lower, sqrt_upper);
+                positions.push(PortfolioPosition {
+                    pool_id,
+                    position_id: *position_id,
+                    token0: pool.token0.clone(),
+                    token1: pool.token1.clone(),
+                    amount0: U128(amount0.round() as u128),
+                    amount1: U128(amount1.round() as u128),
+                    is_active: position.is_active(pool.sqrt_price),
+                    fees_earned0: U128(position.fees_earned_token0),
+                    fees_earned1: U128(position.fees_earned_token1),
+                });
+            }
+        }
+        positions.sort_by_key(|position| position.position_id);
+
+        let mut deposits: Vec<PortfolioDeposit> = self
+            .deposits
+            .get(&account_id)
+            .map(|deposits| {
+                deposits
+                    .values()
+                    .map(|deposit| {
+                        let amount = match self.reserves.get(&deposit.asset) {
+                            Some(reserve) => deposit.current_amount(reserve.supply_index),
+                            None => deposit.amount,
+                        };
+                        PortfolioDeposit {
+                            asset: deposit.asset.clone(),
+                            amount: U128(amount),
+                            interest: U128(deposit.growth + deposit.calculate_growth(timestamp)),
+                            apr: deposit.apr,
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        deposits.sort_by(|a, b| a.asset.cmp(&b.asset));
+
+        let mut borrows: Vec<PortfolioBorrow> = self
+            .borrows
+            .iter()
+            .filter(|(_, borrow)| borrow.owner_id == account_id)
+            .map(|(borrow_id, borrow)| PortfolioBorrow {
+                borrow_id,
+                pool_id: borrow.pool_id,
+                position_id: borrow.position_id,
+                borrowed0: U128(borrow.borrowed0),
+                borrowed1: U128(borrow.borrowed1),
+                fees: U128(borrow.fees + borrow.calculate_fees(timestamp)),
+                health_factor: self.internal_health_factor(&borrow),
+            })
+            .collect();
+        borrows.sort_by_key(|borrow| borrow.borrow_id);
+
+        let mut portfolio = AccountPortfolio {
+            balances,
+            positions,
+            deposits,
+            borrows,
+            valuation: None,
+        };
+        if let Some(quote_token) = quote_token {
+            portfolio.valuation = Some(self.internal_portfolio_valuation(&portfolio, quote_token));
+        }
+        portfolio
+    }
+}
+
+impl Contract {
+    fn internal_health_factor(&self, borrow: &Borrow) -> Option<f64> {
+        let price = self.internal_valuation_price(borrow.pool_id).ok()?;
+        let (_, liquidation_threshold) = self.internal_risk_params(borrow.pool_id);
+        let (debt, collateral) = self.internal_borrow_values_at(borrow, price);
+        Some(liquidation_threshold * collateral / debt)
+    }
+
+    fn internal_portfolio_valuation(
+        &self,
+        portfolio: &AccountPortfolio,
+        quote_token: AccountId,
+    ) -> PortfolioValuation {
+        let mut amounts: Vec<(&AccountId, f64)> = vec![];
+        for (token, amount) in &portfolio.balances {
+            amounts.push((token, amount.0 as f64));
+        }
+        for position in &portfolio.positions {
+            amounts.push((&position.token0, position.amount0.0 as f64));
+            amounts.push((&position.token1, position.amount1.0 as f64));
+        }
+        for deposit in &portfolio.deposits {
+            amounts.push((&deposit.asset, (deposit.amount.0 + deposit.interest.0) as f64));
+        }
+        for borrow in &portfolio.borrows {
+            let pool = &self.pools[borrow.pool_id];
+            amounts.push((&pool.token0, -(borrow.borrowed0.0 as f64)));
+            amounts.push((&pool.token1, -((borrow.borrowed1.0 + borrow.fees.0) as f64)));
+        }
+
+        let mut value = 0.0;
+        let mut unpriced_tokens: Vec<AccountId> = vec![];
+        for (token, amount) in amounts {
+            match self.internal_quote_price(token, &quote_token) {
+                Some(price) => value += amount * price,
+                None => {
+                    if !unpriced_tokens.contains(token) {
+                        unpriced_tokens.push(token.clone());
+                    }
+                }
+            }
+        }
+        PortfolioValuation {
+            quote_token,
+            value,
+            unpriced_tokens,
+        }
+    }
+
+    /// Spot price of `token` in `quote_token` from the first pool of the pair
+    fn internal_quote_price(&self, token: &AccountId, quote_token: &AccountId) -> Option<f64> {
+        if token == quote_token {
+            return Some(1.0);
+        }
+        self.pools.iter().find_map(|pool| {
+            let price = pool.sqrt_price * pool.sqrt_price;
+            if &pool.token0 == token && &pool.token1 == quote_token {
+                Some(price)
+            } else if &pool.token1 == token && &pool.token0 == quote_token {
+                Some(1.0 / price)
+            } else {
+                None
+            }
+        })
+    }
+}
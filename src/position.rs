@@ -121,6 +121,70 @@ impl Position {
         }
     }
 
+    /// Position with the largest liquidity that fits in `max_amount0` of token0 and
+    /// `max_amount1` of token1 at the current price
+    pub fn with_max_amounts(
+        owner_id: AccountId,
+        max_amount0: u128,
+        max_amount1: u128,
+        lower_bound_price: f64,
+        upper_bound_price: f64,
+        sqrt_price: f64,
+    ) -> Position {
+        assert!(lower_bound_price < upper_bound_price);
+        let tick_lower_bound_price = sqrt_price_to_tick(lower_bound_price.sqrt());
+        let tick_upper_bound_price = sqrt_price_to_tick(upper_bound_price.sqrt());
+        let mut position = Position {
+            owner_id,
+            tick_lower_bound_price,
+            tick_upper_bound_price,
+            sqrt_lower_bound_price: tick_to_sqrt_price(tick_lower_bound_price),
+            sqrt_upper_bound_price: tick_to_sqrt_price(tick_upper_bound_price),
+            is_active: true,
+            ..Default::default()
+        };
+        position.add_liquidity_with_max_amounts(max_amount0, max_amount1, sqrt_price);
+        position
+    }
+
+    /// Adds the largest liquidity that fits in `max_amount0` of token0 and `max_amount1`
+    /// of token1. Returns the amounts of token0 and token1 added to the position.
+    pub fn add_liquidity_with_max_amounts(
+        &mut self,
+        max_amount0: u128,
+        max_amount1: u128,
+        sqrt_price: f64,
+    ) -> (f64, f64) {
+        let liquidity = _get_liquidity(
+            max_amount0 as f64,
+            max_amount1 as f64,
+            sqrt_price,
+            self.sqrt_lower_bound_price,
+            self.sqrt_upper_bound_price,
+        );
+        assert!(liquidity > 0.0, "{}", PST6);
+        let token0_locked_before = self.token0_locked;
+        let token1_locked_before = self.token1_locked;
+        self.liquidity += liquidity;
+        self.token0_locked = calculate_x(
+            self.liquidity,
+            sqrt_price,
+            self.sqrt_lower_bound_price,
+            self.sqrt_upper_bound_price,
+        );
+        self.token1_locked = calculate_y(
+            self.liquidity,
+            sqrt_price,
+            self.sqrt_lower_bound_price,
+            self.sqrt_upper_bound_price,
+        );
+        self.total_locked = self.token1_locked + self.token0_locked * sqrt_price * sqrt_price;
+        (
+            self.token0_locked - token0_locked_before,
+            self.token1_locked - token1_locked_before,
+        )
+    }
+
     pub fn refresh(&mut self, sqrt_price: f64, current_timestamp: u64) {
         self.token0_locked = calculate_x(
             self.liquidity,
@@ -142,6 +206,23 @@ impl Position {
         self.last_update = current_timestamp;
     }
 
+    /// Value of the position in token1 if the pool were at `sqrt_price`
+    pub fn value_at(&self, sqrt_price: f64) -> f64 {
+        let x = calculate_x(
+            self.liquidity,
+            sqrt_price,
+            self.sqrt_lower_bound_price,
+            self.sqrt_upper_bound_price,
+        );
+        let y = calculate_y(
+            self.liquidity,
+            sqrt_price,
+            self.sqrt_lower_bound_price,
+            self.sqrt_upper_bound_price,
+        );
+        y + x * sqrt_price * sqrt_price
+    }
+
     pub fn is_active(&self, sqrt_price: f64) -> bool {
         self.sqrt_lower_bound_price <= sqrt_price && self.sqrt_upper_bound_price >= sqrt_price
     }
@@ -264,6 +345,32 @@ impl Position {
         self.total_locked = self.token1_locked + self.token0_locked * sqrt_price * sqrt_price;
     }
 
+    /// Removes `share` (0..=1) of the position liquidity.
+    /// Returns the amounts of token0 and token1 taken out of the position.
+    pub fn remove_liquidity_share(&mut self, share: f64, sqrt_price: f64) -> (f64, f64) {
+        assert!((0.0..=1.0).contains(&share));
+        let token0_locked_before = self.token0_locked;
+        let token1_locked_before = self.token1_locked;
+        self.liquidity *= 1.0 - share;
+        self.token0_locked = calculate_x(
+            self.liquidity,
+            sqrt_price,
+            self.sqrt_lower_bound_price,
+            self.sqrt_upper_bound_price,
+        );
+        self.token1_locked = calculate_y(
+            self.liquidity,
+            sqrt_price,
+            self.sqrt_lower_bound_price,
+            self.sqrt_upper_bound_price,
+        );
+        self.total_locked = self.token1_locked + self.token0_locked * sqrt_price * sqrt_price;
+        (
+            token0_locked_before - self.token0_locked,
+            token1_locked_before - self.token1_locked,
+        )
+    }
+
     pub fn get_liquidation_price(&self, xd: f64, yd: f64, ltv_max: f64) -> (f64, f64) {
         // for brevity
         let sb = self.sqrt_upper_bound_price;
@@ -361,7 +468,7 @@ pub fn _calculate_sp(l: f64, x: f64, sb: f64) -> f64 {
 mod test {
     use super::min;
     use crate::position::max;
-    use crate::{position::*, LTV_MAX};
+    use crate::{position::*, reserve::DEFAULT_LIQUIDATION_THRESHOLD};
 
     #[test]
     fn debug_info() {
@@ -521,6 +628,30 @@ mod test {
         assert!(position.sqrt_upper_bound_price == 10.999833188399927,);
     }
 
+    #[test]
+    fn open_position_with_max_amounts() {
+        let position = Position::with_max_amounts(String::new(), 50, 100000, 25.0, 121.0, 10.0);
+        let expected = Position::new(String::new(), Some(U128(50)), None, 25.0, 121.0, 10.0);
+        assert!((position.liquidity - expected.liquidity).abs() < 1e-6);
+        assert!((position.token0_locked - 50.0).abs() < 1e-9);
+        assert!(position.token1_locked.round() == 27505.0);
+        assert!(position.tick_lower_bound_price == 32190);
+        // token1 limits the liquidity
+        let position = Position::with_max_amounts(String::new(), 1000, 27000, 25.0, 121.0, 10.0);
+        assert!((position.token1_locked - 27000.0).abs() < 1e-6);
+        assert!(position.token0_locked < 50.0);
+        // below the range only token0 is used
+        let position = Position::with_max_amounts(String::new(), 50, 0, 121.0, 144.0, 10.0);
+        assert!((position.token0_locked - 50.0).abs() < 1e-9);
+        assert!(position.token1_locked == 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Amounts are too small to provide liquidity")]
+    fn open_position_with_max_amounts_zero() {
+        Position::with_max_amounts(String::new(), 50, 0, 25.0, 121.0, 10.0);
+    }
+
     #[test]
     fn open_position_less_than_lower_bound() {
         let position = Position::new(String::new(), Some(U128(50)), None, 121.0, 144.0, 10.0);
@@ -705,7 +836,7 @@ mod test {
     #[test] 
     fn liquidation_prices1() {
         let position = Position::new(String::new(), None, Some(U128(50)), 121.0, 169.0, 12.0);
-        let prices = position.get_liquidation_price(position.token0_locked, position.token1_locked, LTV_MAX);
+        let prices = position.get_liquidation_price(position.token0_locked, position.token1_locked, DEFAULT_LIQUIDATION_THRESHOLD);
         println!("prices are {} {}", prices.0, prices.1);
         assert_eq!(prices.0.round(), 209.0);
         assert_eq!(prices.1.round(), 94.0);
@@ -714,7 +845,7 @@ mod test {
     #[test] 
     fn liquidation_prices2() {
         let position = Position::new(String::new(), None, Some(U128(50)), 1.0, 1000.0, 20.0);
-        let prices = position.get_liquidation_price(position.token0_locked, position.token1_locked, LTV_MAX);
+        let prices = position.get_liquidation_price(position.token0_locked, position.token1_locked, DEFAULT_LIQUIDATION_THRESHOLD);
         println!("prices are {} {}", prices.0, prices.1);
         assert_eq!(prices.0.round(), 25.0);
         assert_eq!(prices.1.round(), 299.0);
@@ -724,10 +855,22 @@ mod test {
     #[test] 
     fn liquidation_prices3() {
         let position = Position::new(String::new(), None, Some(U128(50)), 121.0, 144.0, 13.0);
-        let prices = position.get_liquidation_price(position.token0_locked, position.token1_locked, LTV_MAX);
+        let prices = position.get_liquidation_price(position.token0_locked, position.token1_locked, DEFAULT_LIQUIDATION_THRESHOLD);
         println!("prices are {} {}", prices.0, prices.1);
     }
 
+    #[test]
+    fn remove_liquidity_share() {
+        let mut position = Position::new(String::new(), Some(U128(50)), None, 25.0, 121.0, 10.0);
+        let token1_locked = position.token1_locked;
+        let liquidity = position.liquidity;
+        let (removed0, removed1) = position.remove_liquidity_share(0.25, 10.0);
+        assert!((removed0 - 12.5).abs() < 1e-9);
+        assert!((removed1 - token1_locked / 4.0).abs() < 1e-9);
+        assert!((position.liquidity - liquidity * 0.75).abs() < 1e-9);
+        assert!((position.token0_locked - 37.5).abs() < 1e-9);
+    }
+
     #[should_panic]
     #[test] 
     fn add_liquidity_both_tokens() {
@@ -0,0 +1,167 @@
+use crate::action::OpenPositionAction;
+use crate::*;
+
+/// Time an open position request waits for its second leg if the first leg does not set it
+pub const OPEN_POSITION_REQUEST_DURATION: u64 = 3600 * 1_000_000_000;
+
+pub type OpenPositionRequestId = usize;
+
+/// First leg of a position funded by two deposits. The liquidity of the first leg is held
+/// until the second leg brings the other token or the request is cancelled.
+#[derive(Clone, Serialize, BorshDeserialize, BorshSerialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OpenPositionRequest {
+    pub pool_id: usize,
+    pub token0_liquidity: Option<U128>,
+    pub token1_liquidity: Option<U128>,
+    pub lower_bound_price: f64,
+    pub upper_bound_price: f64,
+    /// Anyone can cancel the request from this time
+    pub expiration_ts: u64,
+}
+
+impl OpenPositionRequest {
+    pub fn is_expired(&self, timestamp: u64) -> bool {
+        timestamp >= self.expiration_ts
+    }
+
+    /// Token and amount held by the request
+    fn held(&self, pool: &Pool) -> (AccountId, u128) {
+        match self.token0_liquidity {
+            Some(amount) => (pool.token0.clone(), amount.0),
+            None => (pool.token1.clone(), self.token1_liquidity.unwrap().0),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Cancels the request and withdraws the held liquidity to the account. The account
+    /// can cancel it at any time and anyone else once it has expired.
+    pub fn cancel_open_position_request(
+        &mut self,
+        account_id: AccountId,
+        request_id: OpenPositionRequestId,
+    ) -> Promise {
+        let request = self.internal_take_open_position_request(&account_id, request_id);
+        assert!(
+            env::predecessor_account_id() == account_id
+                || request.is_expired(env::block_timestamp()),
+            "{}",
+            PRQ2
+        );
+        let (token, amount) = request.held(&self.pools[request.pool_id]);
+        self.increase_balance(&account_id, &token, amount);
+        self.balance_withdraw(&account_id, &token, amount, &account_id)
+    }
+
+    pub fn get_open_position_requests(
+        &self,
+        account_id: AccountId,
+    ) -> HashMap<OpenPositionRequestId, OpenPositionRequest> {
+        self.open_position_requests
+            .get(&account_id)
+            .unwrap_or_default()
+    }
+}
+
+impl Contract {
+    /// Holds the liquidity of the first leg, or opens the position with the liquidity of
+    /// the first leg when the second leg brings at most `token0_liquidity` or
+    /// `token1_liquidity` of the other token. Returns the position opened.
+    pub(crate) fn internal_open_position_leg(
+        &mut self,
+        account_id: &AccountId,
+        action: &OpenPositionAction,
+    ) -> Option<u128> {
+        let timestamp = env::block_timestamp();
+        let pending = self
+            .open_position_requests
+            .get(account_id)
+            .map(|requests| requests.contains_key(&action.request_id))
+            .unwrap_or(false);
+        if !pending {
+            assert!(
+                action.token0_liquidity.is_some() ^ action.token1_liquidity.is_some(),
+                "{}",
+                PST5
+            );
+            self.assert_pool_exists(action.pool_id);
+            let expiration_ts = action
+                .expiration_ts
+                .map(|expiration_ts| expiration_ts.0)
+                .unwrap_or(timestamp + OPEN_POSITION_REQUEST_DURATION);
+            assert!(expiration_ts > timestamp, "{}", PRQ3);
+            let request = OpenPositionRequest {
+                pool_id: action.pool_id,
+                token0_liquidity: action.token0_liquidity,
+                token1_liquidity: action.token1_liquidity,
+                lower_bound_price: action.lower_bound_price,
+                upper_bound_price: action.upper_bound_price,
+                expiration_ts,
+            };
+            let (token, amount) = request.held(&self.pools[action.pool_id]);
+            self.decrease_balance(account_id, &token, amount);
+            let initial_storage = self.internal_storage_usage();
+            let mut requests = self
+                .open_position_requests
+                .get(account_id)
+                .unwrap_or_default();
+            requests.insert(action.request_id, request);
+            self.open_position_requests.insert(account_id, &requests);
+            self.internal_charge_storage(account_id, initial_storage);
+            return None;
+        }
+
+        let request = self.internal_take_open_position_request(account_id, action.request_id);
+        assert!(!request.is_expired(timestamp), "{}", PRQ1);
+        assert!(
+            action.pool_id == request.pool_id
+                && action.lower_bound_price == request.lower_bound_price
+                && action.upper_bound_price == request.upper_bound_price,
+            "{}",
+            PRQ4
+        );
+        // the second leg brings the other token
+        let max_amount = if request.token0_liquidity.is_some() {
+            action.token1_liquidity.expect(PST5).0
+        } else {
+            action.token0_liquidity.expect(PST5).0
+        };
+        let (token, amount) = request.held(&self.pools[request.pool_id]);
+        self.increase_balance(account_id, &token, amount);
+        let position_id = self.internal_open_position(
+            account_id,
+            request.pool_id,
+            request.token0_liquidity,
+            request.token1_liquidity,
+            request.lower_bound_price,
+            request.upper_bound_price,
+        );
+        let position = &self.pools[request.pool_id].positions[&position_id];
+        let needed = if request.token0_liquidity.is_some() {
+            position.token1_locked
+        } else {
+            position.token0_locked
+        };
+        assert!(needed.round() as u128 <= max_amount, "{}", PRQ5);
+        Some(position_id)
+    }
+
+    fn internal_take_open_position_request(
+        &mut self,
+        account_id: &AccountId,
+        request_id: OpenPositionRequestId,
+    ) -> OpenPositionRequest {
+        let initial_storage = self.internal_storage_usage();
+        let mut requests = self.open_position_requests.get(account_id).expect(PRQ0);
+        let request = requests.remove(&request_id).expect(PRQ0);
+        if requests.is_empty() {
+            self.open_position_requests.remove(account_id);
+        } else {
+            self.open_position_requests.insert(account_id, &requests);
+        }
+        self.internal_charge_storage(account_id, initial_storage);
+        request
+    }
+}
@@ -1,4 +1,71 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::AccountId;
+
+use crate::deposit::BASIS_POINT_BASE;
+use crate::errors::*;
+
+/// Maximum ratio of the debt to the collateral value when borrowing
+pub const DEFAULT_LOAN_TO_VALUE: f64 = 0.8;
+/// Ratio of the debt to the collateral value above which the borrow can be liquidated
+pub const DEFAULT_LIQUIDATION_THRESHOLD: f64 = 0.8;
+/// Share of the debt that can be repaid in a single liquidation
+pub const DEFAULT_CLOSE_FACTOR: f64 = 0.5;
+/// Premium on the repaid amount the liquidator receives in collateral
+pub const DEFAULT_LIQUIDATION_BONUS: f64 = 0.05;
+/// Share of the borrow interest kept by the protocol, in basis points
+pub const DEFAULT_RESERVE_FACTOR: u16 = 1000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReserveConfig {
+    pub loan_to_value: f64,
+    pub liquidation_threshold: f64,
+    pub liquidation_bonus: f64,
+    pub close_factor: f64,
+    pub reserve_factor: u16,
+    /// No limit if not set
+    pub supply_cap: Option<U128>,
+    /// No limit if not set
+    pub borrow_cap: Option<U128>,
+    pub borrowing_enabled: bool,
+}
+
+impl Default for ReserveConfig {
+    fn default() -> Self {
+        ReserveConfig {
+            loan_to_value: DEFAULT_LOAN_TO_VALUE,
+            liquidation_threshold: DEFAULT_LIQUIDATION_THRESHOLD,
+            liquidation_bonus: DEFAULT_LIQUIDATION_BONUS,
+            close_factor: DEFAULT_CLOSE_FACTOR,
+            reserve_factor: DEFAULT_RESERVE_FACTOR,
+            supply_cap: None,
+            borrow_cap: None,
+            borrowing_enabled: true,
+        }
+    }
+}
+
+impl ReserveConfig {
+    pub fn assert_valid(&self) {
+        assert!(
+            self.loan_to_value > 0.0 && self.loan_to_value <= self.liquidation_threshold,
+            "{}",
+            RSR3
+        );
+        assert!(self.liquidation_threshold < 1.0, "{}", RSR4);
+        assert!(self.close_factor > 0.0 && self.close_factor <= 1.0, "{}", RSR1);
+        // a bonus this high would make it impossible to restore the health factor
+        assert!(
+            self.liquidation_bonus >= 0.0
+                && self.liquidation_threshold * (1.0 + self.liquidation_bonus) < 1.0,
+            "{}",
+            RSR2
+        );
+        assert!(self.reserve_factor <= BASIS_POINT_BASE, "{}", RSR5);
+    }
+}
 
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct Reserve {
@@ -6,28 +73,94 @@ pub struct Reserve {
     pub borrowed: u128,
     pub utilization_rate: f64,
     pub target_utilization_rate: f64,
+    pub config: ReserveConfig,
+    /// Value of one unit deposited at the start, decreases when bad debt is written off
+    pub supply_index: f64,
+    /// Part of the repaid interest kept by the protocol according to the reserve factor
+    pub protocol_fees: u128,
 }
 
 impl Default for Reserve {
     fn default() -> Self {
+        Reserve::new(ReserveConfig::default())
+    }
+}
+
+impl Reserve {
+    pub fn new(config: ReserveConfig) -> Self {
         Reserve {
             deposited: 0,
             borrowed: 0,
             utilization_rate: 0.0,
             target_utilization_rate: 0.0,
+            config,
+            supply_index: 1.0,
+            protocol_fees: 0,
         }
     }
-}
 
-impl Reserve {
     pub fn increase_deposit(&mut self, amount: u128) {
+        if let Some(supply_cap) = self.config.supply_cap {
+            assert!(self.deposited + amount <= supply_cap.0, "{}", RSR6);
+        }
         self.deposited += amount;
     }
 
+    /// Lends `amount` out of the reserve, checking the borrow cap and the available liquidity
+    pub fn borrow(&mut self, token: &AccountId, amount: u128) {
+        assert!(self.config.borrowing_enabled, "{}", RSR8);
+        if let Some(borrow_cap) = self.config.borrow_cap {
+            assert!(self.borrowed + amount <= borrow_cap.0, "{}", RSR7);
+        }
+        assert!(
+            self.deposited >= self.borrowed + amount,
+            "{}",
+            borrow_error(token, amount, self.deposited - self.borrowed)
+        );
+        self.borrowed += amount;
+        self.refresh_utilization_rate();
+    }
+
     pub fn decrease_deposit(&mut self, amount: u128) {
         self.deposited -= amount;
     }
 
+    /// Removes `deficit` that will never be repaid from the borrowed amount,
+    /// `loss` is the part of it not paid by the insurance fund and is taken from the depositors
+    pub fn write_off(&mut self, deficit: u128, loss: u128) {
+        self.borrowed -= u128::min(deficit, self.borrowed);
+        let loss = u128::min(loss, self.deposited);
+        if loss > 0 {
+            self.supply_index *= (self.deposited - loss) as f64 / self.deposited as f64;
+            self.deposited -= loss;
+        }
+        self.refresh_utilization_rate();
+    }
+
+    /// Adds the loss of a perpetual position, the reserve is its counterparty, to the deposits
+    pub fn add_trading_loss(&mut self, amount: u128) {
+        if self.deposited == 0 {
+            self.protocol_fees += amount;
+            return;
+        }
+        self.supply_index *= (self.deposited + amount) as f64 / self.deposited as f64;
+        self.deposited += amount;
+        self.refresh_utilization_rate();
+    }
+
+    /// Pays the profit of a perpetual position from the liquidity that is not borrowed,
+    /// returns the part of `amount` the liquidity covers
+    pub fn pay_trading_profit(&mut self, amount: u128) -> u128 {
+        let paid = u128::min(amount, self.deposited.saturating_sub(self.borrowed));
+        if paid == 0 {
+            return 0;
+        }
+        self.supply_index *= (self.deposited - paid) as f64 / self.deposited as f64;
+        self.deposited -= paid;
+        self.refresh_utilization_rate();
+        paid
+    }
+
     pub fn refresh_utilization_rate(&mut self) {
         self.utilization_rate = match self.deposited {
             0 => 0.0,
@@ -58,4 +191,76 @@ mod test {
         reserve.decrease_deposit(200);
         assert!(reserve.deposited == 300);
     }
+    #[test]
+    fn write_off_test() {
+        let mut reserve = Reserve::default();
+        reserve.increase_deposit(1000);
+        reserve.borrowed = 400;
+        reserve.write_off(100, 0);
+        assert_eq!(reserve.borrowed, 300);
+        assert_eq!(reserve.deposited, 1000);
+        assert_eq!(reserve.supply_index, 1.0);
+        reserve.write_off(100, 250);
+        assert_eq!(reserve.borrowed, 200);
+        assert_eq!(reserve.deposited, 750);
+        assert_eq!(reserve.supply_index, 0.75);
+    }
+    #[test]
+    fn trading_pnl_test() {
+        let mut reserve = Reserve::default();
+        reserve.increase_deposit(1000);
+        reserve.add_trading_loss(500);
+        assert_eq!(reserve.deposited, 1500);
+        assert_eq!(reserve.supply_index, 1.5);
+        assert_eq!(reserve.pay_trading_profit(750), 750);
+        assert_eq!(reserve.deposited, 750);
+        assert_eq!(reserve.supply_index, 0.75);
+    }
+    #[test]
+    fn trading_profit_above_liquidity_test() {
+        let mut reserve = Reserve::default();
+        reserve.increase_deposit(1000);
+        reserve.borrowed = 600;
+        assert_eq!(reserve.pay_trading_profit(401), 400);
+        assert_eq!(reserve.deposited, 600);
+        assert_eq!(reserve.supply_index, 0.6);
+        assert_eq!(reserve.pay_trading_profit(1), 0);
+        assert_eq!(reserve.supply_index, 0.6);
+    }
+    #[test]
+    fn trading_profit_without_deposits_test() {
+        let mut reserve = Reserve::default();
+        assert_eq!(reserve.pay_trading_profit(100), 0);
+        assert_eq!(reserve.supply_index, 1.0);
+    }
+    #[test]
+    #[should_panic(expected = "Supply cap of the reserve is exceeded")]
+    fn supply_cap_test() {
+        let mut reserve = Reserve::new(ReserveConfig {
+            supply_cap: Some(U128(1000)),
+            ..ReserveConfig::default()
+        });
+        reserve.increase_deposit(1000);
+        reserve.increase_deposit(1);
+    }
+    #[test]
+    #[should_panic(expected = "Borrow cap of the reserve is exceeded")]
+    fn borrow_cap_test() {
+        let mut reserve = Reserve::new(ReserveConfig {
+            borrow_cap: Some(U128(100)),
+            ..ReserveConfig::default()
+        });
+        reserve.increase_deposit(1000);
+        reserve.borrow(&"usn".to_string(), 100);
+        reserve.borrow(&"usn".to_string(), 1);
+    }
+    #[test]
+    #[should_panic(expected = "Loan to value must be above 0 and not above the liquidation threshold")]
+    fn config_ltv_above_threshold_test() {
+        ReserveConfig {
+            loan_to_value: 0.85,
+            ..ReserveConfig::default()
+        }
+        .assert_valid();
+    }
 }
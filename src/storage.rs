@@ -0,0 +1,234 @@
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::{assert_one_yocto, StorageUsage};
+
+use crate::pool::position_storage_usage;
+use crate::*;
+
+/// Storage an account needs to register, covers the registration and the internal balance
+pub const MIN_STORAGE_USAGE: StorageUsage = 500;
+
+/// NEAR an account attached for storage and the storage charged against it
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct StorageAccount {
+    pub deposit: Balance,
+    pub used: StorageUsage,
+}
+
+impl StorageAccount {
+    pub fn available(&self) -> Balance {
+        self.deposit
+            .saturating_sub(self.used as Balance * env::storage_byte_cost())
+    }
+
+    fn to_storage_balance(&self) -> StorageBalance {
+        StorageBalance {
+            total: U128(self.deposit),
+            available: U128(self.available()),
+        }
+    }
+}
+
+#[near_bindgen]
+impl StorageManagement for Contract {
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<ValidAccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id = account_id
+            .map(|account_id| account_id.into())
+            .unwrap_or_else(env::predecessor_account_id);
+        let registration_only = registration_only.unwrap_or(false);
+        if let Some(mut account) = self.storage_accounts.get(&account_id) {
+            if registration_only {
+                Promise::new(env::predecessor_account_id()).transfer(amount);
+            } else {
+                account.deposit += amount;
+                self.storage_accounts.insert(&account_id, &account);
+            }
+            return self.storage_accounts.get(&account_id).unwrap().to_storage_balance();
+        }
+        let min_balance = self.storage_balance_bounds().min.0;
+        assert!(amount >= min_balance, "{}", STR2);
+        let deposit = if registration_only {
+            Promise::new(env::predecessor_account_id()).transfer(amount - min_balance);
+            min_balance
+        } else {
+            amount
+        };
+        let initial_storage = env::storage_usage();
+        let mut account = StorageAccount { deposit, used: 0 };
+        self.storage_accounts.insert(&account_id, &account);
+        account.used = env::storage_usage() - initial_storage;
+        self.storage_accounts.insert(&account_id, &account);
+        account.to_storage_balance()
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut account = self.storage_accounts.get(&account_id).expect(STR0);
+        let available = account.available();
+        let amount = amount.map(|amount| amount.0).unwrap_or(available);
+        assert!(amount <= available, "{}", STR1);
+        account.deposit -= amount;
+        self.storage_accounts.insert(&account_id, &account);
+        if amount > 0 {
+            Promise::new(account_id).transfer(amount);
+        }
+        account.to_storage_balance()
+    }
+
+    /// Removes the registration and returns the whole storage deposit. Fails if the account
+    /// has deposits, borrows, positions or open position requests, futures, perpetuals
+    /// or options, or token balances unless `force` is set, in which case the balances
+    /// are given up.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let account = match self.storage_accounts.get(&account_id) {
+            Some(account) => account,
+            None => return false,
+        };
+        let has_deposits = self
+            .deposits
+            .get(&account_id)
+            .map(|deposits| !deposits.is_empty())
+            .unwrap_or(false);
+        let has_borrows = self
+            .borrows
+            .iter()
+            .any(|(_, borrow)| borrow.owner_id == account_id);
+        let has_positions = self
+            .tokens_per_owner
+            .get(&account_id)
+            .map(|tokens| !tokens.is_empty())
+            .unwrap_or(false)
+            || self
+                .covered_calls
+                .iter()
+                .any(|(_, covered_call)| covered_call.owner_id == account_id)
+            || self.open_position_requests.get(&account_id).is_some();
+        let has_derivatives = self.futures.iter().any(|(_, futures)| {
+            futures.long_id.as_ref() == Some(&account_id)
+                || futures.short_id.as_ref() == Some(&account_id)
+        }) || self
+            .perpetuals
+            .iter()
+            .any(|(_, perpetual)| perpetual.owner_id == account_id)
+            || self.perpetual_claims.get(&account_id).is_some()
+            || self.option_series.iter().any(|(_, series)| {
+                series.offers.contains_key(&account_id)
+                    || series.written.contains_key(&account_id)
+                    || series.holders.contains_key(&account_id)
+            });
+        assert!(
+            !has_deposits && !has_borrows && !has_positions && !has_derivatives,
+            "{}",
+            STR3
+        );
+        if let Some(mut balance) = self.balances_map.get(&account_id) {
+            let has_tokens = balance.values().any(|amount| amount > 0);
+            assert!(!has_tokens || force.unwrap_or(false), "{}", STR3);
+            balance.clear();
+            self.balances_map.remove(&account_id);
+        }
+        self.operators.remove(&account_id);
+        self.storage_accounts.remove(&account_id);
+        Promise::new(account_id).transfer(account.deposit);
+        true
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(MIN_STORAGE_USAGE as Balance * env::storage_byte_cost()),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.storage_accounts
+            .get(account_id.as_ref())
+            .map(|account| account.to_storage_balance())
+    }
+}
+
+impl Contract {
+    /// Storage used by the collections and by the positions, which are kept in the pools
+    /// in the contract state and written after the call
+    pub(crate) fn internal_storage_usage(&self) -> StorageUsage {
+        env::storage_usage()
+            + self
+                .pools
+                .iter()
+                .map(|pool| pool.positions_storage_usage)
+                .sum::<StorageUsage>()
+    }
+
+    /// Charges the storage used since `initial_storage` to the account, or gives back
+    /// the released storage, and checks the account has deposited enough for it
+    pub(crate) fn internal_charge_storage(
+        &mut self,
+        account_id: &AccountId,
+        initial_storage: StorageUsage,
+    ) {
+        let mut account = self.storage_accounts.get(account_id).expect(STR0);
+        let storage = self.internal_storage_usage();
+        if storage > initial_storage {
+            account.used += storage - initial_storage;
+            assert!(
+                account.used as Balance * env::storage_byte_cost() <= account.deposit,
+                "{}",
+                STR1
+            );
+        } else {
+            account.used = account.used.saturating_sub(initial_storage - storage);
+        }
+        self.storage_accounts.insert(account_id, &account);
+    }
+
+    /// Storage of the position and of its NFT, which the owner of the position pays for
+    pub(crate) fn internal_position_storage_usage(
+        &self,
+        pool_id: usize,
+        position_id: u128,
+    ) -> StorageUsage {
+        let position = self.pools[pool_id].positions.get(&position_id).expect(PST0);
+        let token_id = position_id.to_string();
+        let token = self.tokens_by_id.get(&token_id).expect(NFT0);
+        let metadata = self.token_metadata_by_id.get(&token_id).expect(NFT0);
+        position_storage_usage(position)
+            + (2 * token_id.len()
+                + token.try_to_vec().unwrap().len()
+                + metadata.try_to_vec().unwrap().len()) as StorageUsage
+    }
+
+    /// Moves `storage` charged to `sender_id` to `receiver_id`, who must have deposited
+    /// enough for it
+    pub(crate) fn internal_move_storage(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        storage: StorageUsage,
+    ) {
+        if let Some(mut account) = self.storage_accounts.get(sender_id) {
+            account.used = account.used.saturating_sub(storage);
+            self.storage_accounts.insert(sender_id, &account);
+        }
+        let mut account = self.storage_accounts.get(receiver_id).expect(STR0);
+        account.used += storage;
+        assert!(
+            account.used as Balance * env::storage_byte_cost() <= account.deposit,
+            "{}",
+            STR1
+        );
+        self.storage_accounts.insert(receiver_id, &account);
+    }
+}
@@ -1,10 +1,11 @@
 use crate::Contract;
+use near_contract_standards::storage_management::StorageManagement;
 use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
 use near_sdk::json_types::ValidAccountId;
 use near_sdk::serde_json;
 use near_sdk::{env, json_types::U128, near_bindgen, PromiseOrValue};
 
-use crate::action::Action;
+use crate::action::{Action, ActionResult};
 use crate::*;
 
 /// Message parameters to receive via token function call.
@@ -13,97 +14,273 @@ use crate::*;
 #[serde(untagged)]
 enum TokenReceiverMessage {
     /// Alternative to deposit + execute actions call.
-    Execute { actions: Vec<Action> },
+    Execute {
+        actions: Vec<Action>,
+        /// Returns the part of the transferred amount the actions did not use to the sender
+        #[serde(default)]
+        refund_unused: bool,
+        /// Withdraws the amount of a token the actions gained
+        withdraw_output: Option<OutputWithdrawal>,
+    },
 }
 
-#[derive(Clone, Serialize, BorshDeserialize, BorshSerialize, PartialEq)]
+#[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
-pub struct OpenPositionRequest {
-    pub owner_id: AccountId,
-    pub pool_id: usize,
-    pub token0_liquidity: Option<U128>,
-    pub token1_liquidity: Option<U128>,
-    pub lower_bound_price: f64,
-    pub upper_bound_price: f64,
+struct OutputWithdrawal {
+    token: AccountId,
+    /// The sender if not set
+    receiver_id: Option<AccountId>,
 }
 
+#[near_bindgen]
 impl Contract {
-    // may be needed to change predecessor_id -> signer_id in some method
-    fn internal_execute(&mut self, token_in: AccountId, actions: &[Action]) {
-        for action in actions {
-            match action {
-                Action::Swap(action) => {
-                    assert_eq!(token_in, action.token_in);
-                    self.swap(
-                        action.pool_id,
-                        &action.token_in,
-                        action.amount_in,
-                        &action.token_out,
-                    );
-                }
-                Action::Withdraw(action) => {
-                    self.withdraw(&action.token, action.amount);
-                }
-                Action::MultihopeSwap(action) => {
-                    self.swap_multihope(&action.token_in, action.amount_in, &action.token_out);
-                }
-                Action::OpenPosition(action) => {
-                    let account = env::signer_account_id();
-                    if let Some(request) = self.open_position_requests.get(&action.request_id) {
-                        let mut request = request.clone();
-                        assert_eq!(account, request.owner_id);
-                        assert_eq!(action.pool_id, request.pool_id);
-                        assert_eq!(action.lower_bound_price, request.lower_bound_price);
-                        assert_eq!(action.upper_bound_price, request.upper_bound_price);
-                        if request.token0_liquidity.is_none() {
-                            request.token0_liquidity = action.token0_liquidity;
-                        } else {
-                            request.token1_liquidity = action.token1_liquidity;
-                        }
-                        self.open_position(
-                            request.pool_id,
-                            request.token0_liquidity,
-                            request.token1_liquidity,
-                            request.lower_bound_price,
-                            request.upper_bound_price,
-                        );
-                        self.open_position_requests.remove(&action.request_id);
-                    } else {
-                        assert!(
-                            action.token0_liquidity.is_some() ^ action.token1_liquidity.is_some(),
-                            "{}",
-                            PST5
-                        );
-                        let request = OpenPositionRequest {
-                            owner_id: account,
-                            pool_id: action.pool_id,
-                            token0_liquidity: action.token0_liquidity,
-                            token1_liquidity: action.token1_liquidity,
-                            lower_bound_price: action.lower_bound_price,
-                            upper_bound_price: action.upper_bound_price,
-                        };
-                        self.open_position_requests
-                            .insert(action.request_id, request);
-                    }
-                }
-                Action::AddLiquidity(action) => {
-                    self.add_liquidity(
-                        action.pool_id,
-                        action.position_id,
-                        action.token0_liquidity,
-                        action.token1_liquidity,
-                    );
-                }
-                Action::CreateDeposit(action) => {
-                    self.create_deposit(&action.asset, action.amount);
-                }
-                Action::ReturnCollateralAndRepay(action) => {
-                    self.return_collateral_and_repay(action.borrow_id);
-                }
-                Action::Liquidate(action) => {
-                    self.liquidate(action.borrow_id);
+    /// Executes the actions with the internal balances of the caller, the attached deposit
+    /// is added to the storage deposit of the caller. Withdrawals and transfers require
+    /// at least 1 yoctoNEAR attached.
+    #[payable]
+    pub fn execute(&mut self, actions: Vec<Action>) -> Vec<ActionResult> {
+        Self::assert_deposit_for_transfers(&actions);
+        if env::attached_deposit() > 0 {
+            self.storage_deposit(None, None);
+        }
+        let account_id = env::predecessor_account_id();
+        self.internal_execute(&account_id, None, &actions)
+    }
+}
+
+impl Contract {
+    /// Batches that move tokens out of the balance need a deposit like `withdraw` does,
+    /// which a function call access key cannot attach
+    pub(crate) fn assert_deposit_for_transfers(actions: &[Action]) {
+        let transfers = actions
+            .iter()
+            .any(|action| matches!(action, Action::Withdraw(_) | Action::Transfer(_)));
+        assert!(!transfers || env::attached_deposit() >= 1, "{}", BAL4);
+    }
+
+    fn assert_min_amount_out(amount_out: U128, min_amount_out: Option<U128>) {
+        if let Some(min_amount_out) = min_amount_out {
+            assert!(amount_out.0 >= min_amount_out.0, "{}", SWP2);
+        }
+    }
+
+    /// Executes the actions for `account_id`, swaps must start with `token_in` if it is set
+    pub(crate) fn internal_execute(
+        &mut self,
+        account_id: &AccountId,
+        token_in: Option<&AccountId>,
+        actions: &[Action],
+    ) -> Vec<ActionResult> {
+        actions
+            .iter()
+            .map(|action| self.internal_execute_action(account_id, token_in, action))
+            .collect()
+    }
+
+    pub(crate) fn internal_execute_action(
+        &mut self,
+        account_id: &AccountId,
+        token_in: Option<&AccountId>,
+        action: &Action,
+    ) -> ActionResult {
+        match action {
+            Action::Swap(action) => {
+                if let Some(token_in) = token_in {
+                    assert_eq!(token_in, &action.token_in);
                 }
+                let amount_out = self.internal_swap(
+                    account_id,
+                    action.pool_id,
+                    &action.token_in,
+                    action.amount_in,
+                    &action.token_out,
+                );
+                Self::assert_min_amount_out(amount_out, action.min_amount_out);
+                ActionResult::Amount(amount_out)
+            }
+            Action::Withdraw(action) => {
+                let receiver_id = action.receiver_id.as_ref().unwrap_or(account_id);
+                self.balance_withdraw(account_id, &action.token, action.amount.0, receiver_id);
+                ActionResult::None
+            }
+            Action::Transfer(action) => {
+                self.balance_transfer(
+                    account_id,
+                    &action.receiver_id,
+                    &action.token,
+                    action.amount.0,
+                    action.memo.clone(),
+                );
+                ActionResult::None
+            }
+            Action::MultihopeSwap(action) => {
+                let amount_out = self.internal_swap_multihope(
+                    account_id,
+                    &action.token_in,
+                    action.amount_in,
+                    &action.token_out,
+                );
+                Self::assert_min_amount_out(amount_out, action.min_amount_out);
+                ActionResult::Amount(amount_out)
+            }
+            Action::OpenPosition(action) => {
+                ActionResult::PositionId(self.internal_open_position_leg(account_id, action))
+            }
+            Action::AddLiquidity(action) => {
+                self.internal_add_liquidity(
+                    account_id,
+                    action.pool_id,
+                    action.position_id,
+                    action.token0_liquidity,
+                    action.token1_liquidity,
+                );
+                ActionResult::None
+            }
+            Action::OpenPositionWithAmounts(action) => ActionResult::PositionId(Some(
+                self.internal_open_position_with_amounts(account_id, action),
+            )),
+            Action::ZapOpenPosition(action) => {
+                ActionResult::PositionId(Some(self.internal_zap_open_position(account_id, action)))
             }
+            Action::ZapClosePosition(action) => {
+                ActionResult::Amount(self.internal_zap_close_position(account_id, action))
+            }
+            Action::ZapRemoveLiquidityShare(action) => {
+                ActionResult::Amount(self.internal_zap_remove_liquidity_share(account_id, action))
+            }
+            Action::AddLiquidityWithAmounts(action) => {
+                let (amount0, amount1) =
+                    self.internal_add_liquidity_with_amounts(account_id, action);
+                ActionResult::Amounts(amount0, amount1)
+            }
+            Action::RemoveLiquidity(action) => {
+                let (amount0, amount1) = self.internal_remove_liquidity(
+                    account_id,
+                    action.pool_id,
+                    action.position_id,
+                    action.token0_liquidity,
+                    action.token1_liquidity,
+                );
+                ActionResult::Amounts(amount0, amount1)
+            }
+            Action::RemoveLiquidityShare(action) => {
+                let (amount0, amount1) = self.internal_remove_liquidity_share(account_id, action);
+                ActionResult::Amounts(amount0, amount1)
+            }
+            Action::ClosePosition(action) => {
+                let (amount0, amount1) =
+                    self.internal_close_position(account_id, action.pool_id, action.position_id);
+                ActionResult::Amounts(amount0, amount1)
+            }
+            Action::CreateDeposit(action) => {
+                self.internal_create_deposit(account_id, &action.asset, action.amount);
+                ActionResult::None
+            }
+            Action::CloseDeposit(action) => {
+                self.internal_close_deposit(account_id, &action.asset, action.amount);
+                ActionResult::None
+            }
+            Action::SupplyCollateralAndBorrow(action) => {
+                ActionResult::BorrowId(self.internal_supply_collateral_and_borrow(
+                    account_id,
+                    action.pool_id,
+                    action.position_id,
+                    action.leverage,
+                ))
+            }
+            Action::ReturnCollateralAndRepay(action) => {
+                self.internal_return_collateral_and_repay(account_id, action.borrow_id);
+                ActionResult::None
+            }
+            Action::RepayPartial(action) => {
+                self.internal_repay_partial(
+                    account_id,
+                    action.borrow_id,
+                    action.amount0,
+                    action.amount1,
+                );
+                ActionResult::None
+            }
+            Action::ReduceLeverage(action) => {
+                self.internal_reduce_leverage(account_id, action.borrow_id, action.target_leverage);
+                ActionResult::None
+            }
+            Action::AddCollateral(action) => {
+                self.internal_add_collateral(
+                    account_id,
+                    action.borrow_id,
+                    action.token0_liquidity,
+                    action.token1_liquidity,
+                );
+                ActionResult::None
+            }
+            Action::IncreaseLeverage(action) => {
+                self.internal_increase_leverage(account_id, action.borrow_id, action.new_leverage);
+                ActionResult::None
+            }
+            Action::Liquidate(action) => {
+                ActionResult::Liquidation(self.internal_liquidate(account_id, action.target))
+            }
+            Action::OpenFutures(action) => {
+                ActionResult::FuturesId(self.internal_open_futures(account_id, action))
+            }
+            Action::TakeFutures(action) => {
+                self.internal_take_futures(
+                    account_id,
+                    action.futures_id,
+                    action.collateral,
+                    action.limit_price,
+                );
+                ActionResult::None
+            }
+            Action::CancelFutures(action) => {
+                self.internal_cancel_futures(account_id, action.futures_id);
+                ActionResult::None
+            }
+            Action::SettleFutures(action) => {
+                self.settle_futures(action.futures_id);
+                ActionResult::None
+            }
+            Action::LiquidateFutures(action) => ActionResult::Amount(
+                self.internal_liquidate_futures(account_id, action.futures_id),
+            ),
+            Action::OpenPerpetual(action) => {
+                ActionResult::PerpetualId(self.internal_open_perpetual(
+                    account_id,
+                    action.pool_id,
+                    action.side,
+                    action.size,
+                    action.margin,
+                ))
+            }
+            Action::ClosePerpetual(action) => ActionResult::Amount(
+                self.internal_close_perpetual(account_id, action.perpetual_id),
+            ),
+            Action::WriteOptions(action) => {
+                self.internal_write_options(
+                    account_id,
+                    action.series_id,
+                    action.amount,
+                    action.premium,
+                );
+                ActionResult::None
+            }
+            Action::BuyOptions(action) => {
+                self.internal_buy_options(
+                    account_id,
+                    action.series_id,
+                    action.writer_id.clone(),
+                    action.amount,
+                    action.max_premium,
+                );
+                ActionResult::None
+            }
+            Action::ExerciseOptions(action) => ActionResult::Amount(
+                self.internal_exercise_options(account_id, action.series_id),
+            ),
+            Action::ClaimOptionCollateral(action) => ActionResult::Amount(
+                self.internal_claim_option_collateral(account_id, action.series_id),
+            ),
         }
     }
 }
@@ -119,16 +296,49 @@ impl FungibleTokenReceiver for Contract {
         msg: String,
     ) -> PromiseOrValue<U128> {
         let token_in = env::predecessor_account_id();
-        self.deposit_ft(&sender_id.into(), &token_in, amount.into());
+        let sender_id: AccountId = sender_id.into();
+        if !self.is_token_registered(&token_in) {
+            // the unused amount is refunded by the token contract
+            env::log(format!("{}: {}", TKN0, token_in).as_bytes());
+            return PromiseOrValue::Value(amount);
+        }
+        let balance_before = self.internal_get_balance(&sender_id, &token_in);
+        let initial_storage = self.internal_storage_usage();
+        self.deposit_ft(&sender_id, &token_in, amount.into());
+        self.internal_charge_storage(&sender_id, initial_storage);
         if msg == "".to_string() {
             return PromiseOrValue::Value(U128(0));
         }
         // instant swap
         let message = serde_json::from_str::<TokenReceiverMessage>(&msg).expect("Wrong msg format");
         match message {
-            TokenReceiverMessage::Execute { actions } => {
-                self.internal_execute(token_in, &actions);
-                return PromiseOrValue::Value(U128(0));
+            TokenReceiverMessage::Execute {
+                actions,
+                refund_unused,
+                withdraw_output,
+            } => {
+                let output_before = withdraw_output
+                    .as_ref()
+                    .map(|output| self.internal_get_balance(&sender_id, &output.token));
+                self.internal_execute(&sender_id, Some(&token_in), &actions);
+                let mut unused = 0;
+                if refund_unused {
+                    let balance = self.internal_get_balance(&sender_id, &token_in);
+                    unused = u128::min(amount.0, balance.saturating_sub(balance_before));
+                    if unused > 0 {
+                        self.decrease_balance(&sender_id, &token_in, unused);
+                    }
+                }
+                if let (Some(output), Some(output_before)) = (withdraw_output, output_before) {
+                    let balance = self.internal_get_balance(&sender_id, &output.token);
+                    let gained = balance.saturating_sub(output_before);
+                    if gained > 0 {
+                        let receiver_id = output.receiver_id.as_ref().unwrap_or(&sender_id);
+                        self.balance_withdraw(&sender_id, &output.token, gained, receiver_id);
+                    }
+                }
+                // the unused amount is refunded by the token contract
+                return PromiseOrValue::Value(U128(unused));
             }
         }
         PromiseOrValue::Value(U128(0))
@@ -148,17 +358,33 @@ mod test {
             token_in: "token_in.testnet".to_string(),
             amount_in: U128(1000),
             token_out: "token_out.testnet".to_string(),
+            min_amount_out: None,
         });
         let withdraw_action = Action::Withdraw(WithdrawAction {
             token: "token_in.testnet".to_string(),
             amount: U128(1000),
+            receiver_id: None,
         });
         let token_receiver_message = TokenReceiverMessage::Execute {
             actions: vec![swap_action, withdraw_action],
+            refund_unused: false,
+            withdraw_output: None,
         };
         let message: String = serde_json::to_value(&token_receiver_message)
             .unwrap()
             .to_string();
         println!("message is: {}", message);
     }
+    #[test]
+    fn message_without_options() {
+        let message: TokenReceiverMessage =
+            serde_json::from_str(r#"{"actions": []}"#).unwrap();
+        let TokenReceiverMessage::Execute {
+            refund_unused,
+            withdraw_output,
+            ..
+        } = message;
+        assert!(!refund_unused);
+        assert!(withdraw_output.is_none());
+    }
 }
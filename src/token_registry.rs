@@ -0,0 +1,144 @@
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::{ext_contract, serde_json, Gas, PromiseResult};
+
+use crate::*;
+
+pub const GAS_FOR_FT_METADATA: Gas = 10 * TGAS;
+pub const GAS_FOR_ON_FT_METADATA: Gas = 10 * TGAS;
+
+/// Token the exchange accepts deposits of, `metadata` is cached from the token contract
+/// and stays empty until `ft_metadata` returns
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RegisteredToken {
+    pub token_id: AccountId,
+    /// Account which paid the storage of the token, none if the owner registered it
+    pub registered_by: Option<AccountId>,
+    pub metadata: Option<FungibleTokenMetadata>,
+}
+
+#[ext_contract(ext_fungible_token)]
+pub trait FungibleTokenMetadataProvider {
+    fn ft_metadata(&self) -> FungibleTokenMetadata;
+}
+
+#[ext_contract(ext_self)]
+pub trait TokenRegistryCallbacks {
+    fn on_ft_metadata(&mut self, token_id: AccountId);
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Registers tokens at the expense of the contract, registered ones are skipped
+    #[private]
+    pub fn register_tokens(&mut self, token_ids: Vec<ValidAccountId>) {
+        for token_id in token_ids {
+            let token_id: AccountId = token_id.into();
+            if !self.is_token_registered(&token_id) {
+                self.internal_register_token(token_id, None);
+            }
+        }
+    }
+
+    /// Registers a token by anyone, the storage of the token and its metadata
+    /// is charged to the storage deposit of the caller
+    pub fn register_token(&mut self, token_id: ValidAccountId) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let token_id: AccountId = token_id.into();
+        assert!(!self.is_token_registered(&token_id), "{}", TKN1);
+        let initial_storage = self.internal_storage_usage();
+        let promise = self.internal_register_token(token_id, Some(account_id.clone()));
+        self.internal_charge_storage(&account_id, initial_storage);
+        promise
+    }
+
+    /// Stops accepting deposits of the tokens, balances can still be withdrawn
+    #[private]
+    pub fn unregister_tokens(&mut self, token_ids: Vec<AccountId>) {
+        for token_id in token_ids {
+            let initial_storage = self.internal_storage_usage();
+            if let Some(token) = self.registered_tokens.remove(&token_id) {
+                if let Some(account_id) = token.registered_by {
+                    if self.storage_accounts.get(&account_id).is_some() {
+                        self.internal_charge_storage(&account_id, initial_storage);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fetches the metadata of a registered token again, anyone can call it
+    pub fn refresh_token_metadata(&mut self, token_id: AccountId) -> Promise {
+        assert!(self.is_token_registered(&token_id), "{}", TKN0);
+        Self::internal_fetch_token_metadata(token_id)
+    }
+
+    #[private]
+    pub fn on_ft_metadata(&mut self, token_id: AccountId) {
+        let mut token = match self.registered_tokens.get(&token_id) {
+            Some(token) => token,
+            None => return,
+        };
+        let metadata = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                serde_json::from_slice::<FungibleTokenMetadata>(&value).ok()
+            }
+            _ => None,
+        };
+        if metadata.is_none() {
+            env::log(format!("Failed to fetch metadata of {}", token_id).as_bytes());
+            return;
+        }
+        let initial_storage = self.internal_storage_usage();
+        token.metadata = metadata;
+        self.registered_tokens.insert(&token_id, &token);
+        if let Some(account_id) = token.registered_by {
+            self.internal_charge_storage(&account_id, initial_storage);
+        }
+    }
+
+    pub fn get_tokens(&self, from_index: u64, limit: u64) -> Vec<RegisteredToken> {
+        self.registered_tokens
+            .iter()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .map(|(_, token)| token)
+            .collect()
+    }
+
+    pub fn get_token(&self, token_id: AccountId) -> Option<RegisteredToken> {
+        self.registered_tokens.get(&token_id)
+    }
+}
+
+impl Contract {
+    pub(crate) fn is_token_registered(&self, token_id: &AccountId) -> bool {
+        self.registered_tokens.get(token_id).is_some()
+    }
+
+    pub(crate) fn internal_register_token(
+        &mut self,
+        token_id: AccountId,
+        registered_by: Option<AccountId>,
+    ) -> Promise {
+        let token = RegisteredToken {
+            token_id: token_id.clone(),
+            registered_by,
+            metadata: None,
+        };
+        self.registered_tokens.insert(&token_id, &token);
+        Self::internal_fetch_token_metadata(token_id)
+    }
+
+    fn internal_fetch_token_metadata(token_id: AccountId) -> Promise {
+        ext_fungible_token::ft_metadata(&token_id, 0, GAS_FOR_FT_METADATA).then(
+            ext_self::on_ft_metadata(
+                token_id.clone(),
+                &env::current_account_id(),
+                0,
+                GAS_FOR_ON_FT_METADATA,
+            ),
+        )
+    }
+}
@@ -0,0 +1,265 @@
+use crate::action::{
+    OpenPositionWithAmountsAction, RemoveLiquidityShareAction, ZapClosePositionAction,
+    ZapOpenPositionAction, ZapRemoveLiquidityShareAction,
+};
+use crate::pool::SwapDirection;
+use crate::position::{calculate_x, calculate_y, sqrt_price_to_tick, tick_to_sqrt_price};
+use crate::*;
+
+/// Share of the value of a position between the sqrt prices `sa` and `sb` held in token0
+fn token0_value_share(sqrt_price: f64, sa: f64, sb: f64) -> f64 {
+    let value0 = calculate_x(1.0, sqrt_price, sa, sb) * sqrt_price * sqrt_price;
+    let value1 = calculate_y(1.0, sqrt_price, sa, sb);
+    value0 / (value0 + value1)
+}
+
+/// Sqrt price of the tick the position bound falls into
+fn sqrt_bound_price(bound_price: f64) -> f64 {
+    tick_to_sqrt_price(sqrt_price_to_tick(bound_price.sqrt()))
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Opens a position from `amount_in` of a single token. Swaps a part of it in the pool
+    /// to get both tokens in the ratio of the position, or first swaps the token along the
+    /// route of `swap_multihope` to one of the pool tokens. Every swap may give at most
+    /// `max_slippage` basis points less than the TWAP prices of its pools. The dust stays
+    /// in the balance. Returns position id.
+    pub fn zap_open_position(&mut self, action: ZapOpenPositionAction) -> u128 {
+        let account_id = env::predecessor_account_id();
+        self.internal_zap_open_position(&account_id, &action)
+    }
+
+    /// Closes the position and swaps both tokens into `token_out` along the routes
+    /// of `swap_multihope`. Returns the amount credited to the balance.
+    pub fn zap_close_position(&mut self, action: ZapClosePositionAction) -> U128 {
+        let account_id = env::predecessor_account_id();
+        self.internal_zap_close_position(&account_id, &action)
+    }
+
+    /// Removes `basis_points` of the position liquidity or `liquidity` units of it like
+    /// `remove_liquidity_share` and swaps both tokens into `token_out`.
+    /// Returns the amount credited to the balance.
+    pub fn zap_remove_liquidity_share(&mut self, action: ZapRemoveLiquidityShareAction) -> U128 {
+        let account_id = env::predecessor_account_id();
+        self.internal_zap_remove_liquidity_share(&account_id, &action)
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_zap_close_position(
+        &mut self,
+        account_id: &AccountId,
+        action: &ZapClosePositionAction,
+    ) -> U128 {
+        let ZapClosePositionAction {
+            pool_id,
+            position_id,
+            ref token_out,
+            min_amount_out,
+        } = *action;
+        self.internal_zap_remove_liquidity_share(
+            account_id,
+            &ZapRemoveLiquidityShareAction {
+                pool_id,
+                position_id,
+                basis_points: Some(BASIS_POINT_BASE),
+                liquidity: None,
+                token_out: token_out.clone(),
+                min_amount_out,
+            },
+        )
+    }
+
+    pub(crate) fn internal_zap_remove_liquidity_share(
+        &mut self,
+        account_id: &AccountId,
+        action: &ZapRemoveLiquidityShareAction,
+    ) -> U128 {
+        let ZapRemoveLiquidityShareAction {
+            pool_id,
+            position_id,
+            basis_points,
+            liquidity,
+            ref token_out,
+            min_amount_out,
+        } = *action;
+        let (amount0, amount1) = self.internal_remove_liquidity_share(
+            account_id,
+            &RemoveLiquidityShareAction {
+                pool_id,
+                position_id,
+                basis_points,
+                liquidity,
+                min_amount0: U128(0),
+                min_amount1: U128(0),
+            },
+        );
+        let pool = &self.pools[pool_id];
+        let token0 = pool.token0.clone();
+        let token1 = pool.token1.clone();
+        let mut amount_out = 0;
+        for (token, amount) in [(token0, amount0.0), (token1, amount1.0)] {
+            if &token == token_out {
+                amount_out += amount;
+            } else if amount > 0 {
+                let pair = (token.clone(), token_out.clone());
+                assert!(self.routes.contains_key(&pair), "{}", SWP0);
+                let (route, _) = self
+                    .internal_zap_route(&token, amount, token_out)
+                    .expect(SWP1);
+                amount_out += self.internal_swap_route(account_id, &route, U128(amount)).0;
+            }
+        }
+        assert!(amount_out >= min_amount_out.0, "{}", ZAP3);
+        U128(amount_out)
+    }
+
+    /// Route of `swap_multihope` from `token_in` to `token_out`, `None` if there is none
+    /// or its pools do not have the liquidity for `amount_in`
+    fn internal_zap_route(
+        &self,
+        token_in: &AccountId,
+        amount_in: u128,
+        token_out: &AccountId,
+    ) -> Option<(Vec<i32>, u128)> {
+        let route = self.routes.get(&(token_in.clone(), token_out.clone()))?;
+        let amount_out = self.internal_route_return(route, amount_in)?;
+        Some((route.clone(), amount_out))
+    }
+
+    pub(crate) fn internal_zap_open_position(
+        &mut self,
+        account_id: &AccountId,
+        action: &ZapOpenPositionAction,
+    ) -> u128 {
+        let ZapOpenPositionAction {
+            pool_id,
+            ref token_in,
+            amount_in,
+            lower_bound_price,
+            upper_bound_price,
+            max_slippage,
+        } = *action;
+        self.assert_pool_exists(pool_id);
+        assert!(max_slippage <= BASIS_POINT_BASE, "{}", ZAP2);
+        assert!(lower_bound_price < upper_bound_price, "{}", ZAP4);
+        let token0 = self.pools[pool_id].token0.clone();
+        let token1 = self.pools[pool_id].token1.clone();
+        let (token, amount) = if token_in == &token0 || token_in == &token1 {
+            (token_in.clone(), amount_in.0)
+        } else {
+            // the route to the pool token whose return is worth more at the TWAP price
+            let price = self
+                .internal_twap_price(pool_id)
+                .unwrap_or_else(|error| env::panic(error.as_bytes()));
+            let route0 = self.internal_zap_route(token_in, amount_in.0, &token0);
+            let route1 = self.internal_zap_route(token_in, amount_in.0, &token1);
+            let (token, route) = match (route0, route1) {
+                (Some((route0, amount0)), Some((_, amount1)))
+                    if amount0 as f64 * price >= amount1 as f64 =>
+                {
+                    (token0.clone(), route0)
+                }
+                (_, Some((route1, _))) => (token1.clone(), route1),
+                (Some((route0, _)), None) => (token0.clone(), route0),
+                (None, None) => env::panic(ZAP1.as_bytes()),
+            };
+            let amount = self.internal_zap_swap(account_id, &route, amount_in.0, max_slippage);
+            (token, amount)
+        };
+
+        let swap_amount = self.internal_zap_swap_amount(
+            pool_id,
+            &token,
+            amount,
+            sqrt_bound_price(lower_bound_price),
+            sqrt_bound_price(upper_bound_price),
+        );
+        let (mut amount0, mut amount1) = if token == token0 {
+            (amount, 0)
+        } else {
+            (0, amount)
+        };
+        if swap_amount > 0 {
+            let route_id = pool_id as i32 + 1;
+            let route = if token == token0 {
+                vec![route_id]
+            } else {
+                vec![-route_id]
+            };
+            let amount_out = self.internal_zap_swap(account_id, &route, swap_amount, max_slippage);
+            if token == token0 {
+                amount0 -= swap_amount;
+                amount1 += amount_out;
+            } else {
+                amount1 -= swap_amount;
+                amount0 += amount_out;
+            }
+        }
+        self.internal_open_position_with_amounts(
+            account_id,
+            &OpenPositionWithAmountsAction {
+                pool_id,
+                max_amount0: U128(amount0),
+                max_amount1: U128(amount1),
+                min_amount0: U128(0),
+                min_amount1: U128(0),
+                lower_bound_price,
+                upper_bound_price,
+            },
+        )
+    }
+
+    /// Amount of `token` to swap in the pool so that the rest and the swap result fit
+    /// a position between the sqrt prices `sa` and `sb`
+    fn internal_zap_swap_amount(
+        &self,
+        pool_id: usize,
+        token: &AccountId,
+        amount: u128,
+        sa: f64,
+        sb: f64,
+    ) -> u128 {
+        let pool = &self.pools[pool_id];
+        let is_token0 = token == &pool.token0;
+        let swap_amount = |sqrt_price: f64| {
+            let share0 = token0_value_share(sqrt_price, sa, sb);
+            let share = if is_token0 { 1.0 - share0 } else { share0 };
+            (amount as f64 * share) as u128
+        };
+        let estimate = swap_amount(pool.sqrt_price);
+        if estimate == 0 {
+            return 0;
+        }
+        // the swap moves the price, the position opens at the price after the swap
+        let swap_result = pool.get_swap_result(token, estimate, SwapDirection::Return);
+        swap_amount(swap_result.new_sqrt_price).min(amount)
+    }
+
+    /// Swaps all of `amount_in` along the route and checks the result against the TWAP
+    /// prices of its pools. Returns the amount received.
+    pub(crate) fn internal_zap_swap(
+        &mut self,
+        account_id: &AccountId,
+        route: &[i32],
+        amount_in: u128,
+        max_slippage: u16,
+    ) -> u128 {
+        let price = route
+            .iter()
+            .map(|&route_id| {
+                let price = self.internal_twap_price(route_id.unsigned_abs() as usize - 1)?;
+                Ok(if route_id > 0 { price } else { 1.0 / price })
+            })
+            .product::<Result<f64, &'static str>>()
+            .unwrap_or_else(|error| env::panic(error.as_bytes()));
+        let amount_out = self
+            .internal_swap_route(account_id, route, U128(amount_in))
+            .0;
+        let expected = amount_in as f64 * price;
+        let min_amount_out = expected * (1.0 - max_slippage as f64 / BASIS_POINT_TO_PERCENT);
+        assert!(amount_out as f64 >= min_amount_out.floor(), "{}", ZAP0);
+        amount_out
+    }
+}
@@ -1,5 +1,9 @@
-use crate::common::utils::{deposit_tokens, setup_contract, withdraw_tokens};
+use crate::common::utils::{deposit_tokens, set_caller_signed_by, setup_contract, withdraw_tokens};
+use near_sdk::test_utils::{get_logs, VMContextBuilder};
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::serde_json::{self, json};
 use near_sdk::MockedBlockchain;
+use near_sdk::{PromiseOrValue, PromiseResult};
 use near_sdk::{json_types::U128, test_utils::accounts, testing_env};
 
 mod common;
@@ -7,6 +11,7 @@ mod common;
 #[test]
 fn test_balance_after_deposit() {
     let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
     testing_env!(context.predecessor_account_id(accounts(1)).build());
     deposit_tokens(
         &mut context,
@@ -22,6 +27,7 @@ fn test_balance_after_deposit() {
 #[test]
 fn test_balance_after_two_deposits() {
     let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
     testing_env!(context.predecessor_account_id(accounts(1)).build());
     deposit_tokens(
         &mut context,
@@ -44,6 +50,7 @@ fn test_balance_after_two_deposits() {
 #[test]
 fn test_balance_after_withdraw() {
     let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
     testing_env!(context.predecessor_account_id(accounts(0)).build());
     testing_env!(context.signer_account_id(accounts(0)).build());
     deposit_tokens(
@@ -68,6 +75,7 @@ fn test_balance_after_withdraw() {
 #[should_panic(expected = "You want to withdraw 20000 of bob but only have 10000")]
 fn test_balance_withdraw_not_enough_token() {
     let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
     testing_env!(context.predecessor_account_id(accounts(0)).build());
     testing_env!(context.signer_account_id(accounts(0)).build());
     deposit_tokens(
@@ -86,10 +94,31 @@ fn test_balance_withdraw_not_enough_token() {
     );
 }
 
+#[test]
+#[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+fn test_balance_withdraw_without_yocto() {
+    let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
+    deposit_tokens(
+        &mut context,
+        &mut contract,
+        accounts(0),
+        accounts(1),
+        U128(10000),
+    );
+    testing_env!(context
+        .predecessor_account_id(accounts(0))
+        .signer_account_id(accounts(0))
+        .attached_deposit(0)
+        .build());
+    contract.withdraw(&accounts(1).to_string(), U128(10000), None);
+}
+
 #[test]
 #[should_panic]
 fn test_balance_withdraw_without_deposit() {
     let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
     testing_env!(context.predecessor_account_id(accounts(0)).build());
     withdraw_tokens(
         &mut context,
@@ -103,6 +132,7 @@ fn test_balance_withdraw_without_deposit() {
 #[test]
 fn test_balance_after_two_deposits_two_accounts() {
     let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1), accounts(2)]);
     testing_env!(context.predecessor_account_id(accounts(1)).build());
     deposit_tokens(
         &mut context,
@@ -144,3 +174,220 @@ fn test_balance_after_two_deposits_two_accounts() {
     assert_eq!(balance3, U128(30000));
     assert_eq!(balance4, U128(40000));
 }
+
+fn resolve_withdraw(
+    context: &mut VMContextBuilder,
+    contract: &mut mycelium_lab_near_amm::Contract,
+    receiver_id: String,
+    result: PromiseResult,
+) -> U128 {
+    testing_env!(
+        context.predecessor_account_id(accounts(0)).build(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        vec![result]
+    );
+    contract.on_withdraw_resolve(
+        accounts(0).to_string(),
+        receiver_id,
+        accounts(1).to_string(),
+        U128(10000),
+    )
+}
+
+#[test]
+fn test_balance_withdraw_resolved() {
+    let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
+    testing_env!(context.signer_account_id(accounts(0)).build());
+    deposit_tokens(
+        &mut context,
+        &mut contract,
+        accounts(0),
+        accounts(1),
+        U128(10000),
+    );
+    withdraw_tokens(
+        &mut context,
+        &mut contract,
+        accounts(0),
+        accounts(1),
+        U128(10000),
+    );
+    let withdrawn = resolve_withdraw(
+        &mut context,
+        &mut contract,
+        accounts(0).to_string(),
+        PromiseResult::Successful(vec![]),
+    );
+    assert_eq!(withdrawn, U128(10000));
+    let balance = contract.get_balance(&accounts(0).to_string(), &accounts(1).to_string());
+    assert_eq!(balance, U128(0));
+    assert!(get_logs()[0].contains(r#""success":true"#));
+}
+
+#[test]
+fn test_balance_withdraw_failed() {
+    let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
+    testing_env!(context.signer_account_id(accounts(0)).build());
+    deposit_tokens(
+        &mut context,
+        &mut contract,
+        accounts(0),
+        accounts(1),
+        U128(10000),
+    );
+    withdraw_tokens(
+        &mut context,
+        &mut contract,
+        accounts(0),
+        accounts(1),
+        U128(10000),
+    );
+    let withdrawn = resolve_withdraw(
+        &mut context,
+        &mut contract,
+        accounts(0).to_string(),
+        PromiseResult::Failed,
+    );
+    assert_eq!(withdrawn, U128(0));
+    let balance = contract.get_balance(&accounts(0).to_string(), &accounts(1).to_string());
+    assert_eq!(balance, U128(10000));
+    assert!(get_logs()[0].contains(r#""success":false"#));
+}
+
+#[test]
+fn test_balance_withdraw_to_receiver() {
+    let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
+    deposit_tokens(
+        &mut context,
+        &mut contract,
+        accounts(0),
+        accounts(1),
+        U128(10000),
+    );
+    testing_env!(context
+        .predecessor_account_id(accounts(0))
+        .signer_account_id(accounts(0))
+        .attached_deposit(1)
+        .build());
+    contract.withdraw(
+        &accounts(1).to_string(),
+        U128(10000),
+        Some(accounts(3).to_string()),
+    );
+    // the receiver failed to get the tokens, they are back with the account
+    resolve_withdraw(
+        &mut context,
+        &mut contract,
+        accounts(3).to_string(),
+        PromiseResult::Failed,
+    );
+    let balance = contract.get_balance(&accounts(0).to_string(), &accounts(1).to_string());
+    assert_eq!(balance, U128(10000));
+    assert!(get_logs()[0].contains(r#""receiver_id":"danny""#));
+}
+
+/// Pool of accounts(1) and accounts(2) at price 100 with liquidity of accounts(0),
+/// accounts(3) has 1000 of accounts(1)
+fn setup_swap() -> (VMContextBuilder, mycelium_lab_near_amm::Contract) {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    deposit_tokens(&mut context, &mut contract, accounts(0), accounts(1), U128(1000));
+    deposit_tokens(&mut context, &mut contract, accounts(0), accounts(2), U128(200000));
+    testing_env!(context.predecessor_account_id(accounts(0)).build());
+    contract.open_position(0, Some(U128(1000)), None, 90.0, 110.0);
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(1), U128(1000));
+    (context, contract)
+}
+
+fn swap_message(amount_in: u128, options: serde_json::Value) -> String {
+    let mut message = json!({"actions": [{"Swap": {
+        "pool_id": 0,
+        "token_in": accounts(1).to_string(),
+        "amount_in": U128(amount_in),
+        "token_out": accounts(2).to_string(),
+    }}]});
+    message
+        .as_object_mut()
+        .unwrap()
+        .extend(options.as_object().unwrap().clone());
+    message.to_string()
+}
+
+#[test]
+fn test_deposit_unused_refunded() {
+    let (mut context, mut contract) = setup_swap();
+    testing_env!(context.predecessor_account_id(accounts(1)).build());
+    let msg = swap_message(400, json!({"refund_unused": true}));
+    let unused = contract.ft_on_transfer(accounts(3), U128(1000), msg);
+    assert!(matches!(unused, PromiseOrValue::Value(U128(600))));
+    let balance = contract.get_balance(&accounts(3).to_string(), &accounts(1).to_string());
+    assert_eq!(balance, U128(1000));
+    // without the option the rest stays in the balance
+    let msg = swap_message(400, json!({}));
+    let unused = contract.ft_on_transfer(accounts(3), U128(1000), msg);
+    assert!(matches!(unused, PromiseOrValue::Value(U128(0))));
+    let balance = contract.get_balance(&accounts(3).to_string(), &accounts(1).to_string());
+    assert_eq!(balance, U128(1600));
+}
+
+#[test]
+fn test_deposit_output_withdrawn() {
+    let (mut context, mut contract) = setup_swap();
+    testing_env!(context.predecessor_account_id(accounts(1)).build());
+    let msg = swap_message(
+        1000,
+        json!({"withdraw_output": {"token": accounts(2).to_string()}}),
+    );
+    contract.ft_on_transfer(accounts(3), U128(1000), msg);
+    let balance = contract.get_balance(&accounts(3).to_string(), &accounts(2).to_string());
+    assert_eq!(balance, U128(0));
+    let balance = contract.get_balance(&accounts(3).to_string(), &accounts(1).to_string());
+    assert_eq!(balance, U128(1000));
+}
+
+#[test]
+fn test_deposit_output_withdrawn_to_receiver() {
+    let (mut context, mut contract) = setup_swap();
+    testing_env!(context.predecessor_account_id(accounts(1)).build());
+    let msg = swap_message(
+        100,
+        json!({
+            "refund_unused": true,
+            "withdraw_output": {
+                "token": accounts(2).to_string(),
+                "receiver_id": accounts(4).to_string(),
+            },
+        }),
+    );
+    let unused = contract.ft_on_transfer(accounts(3), U128(1000), msg);
+    assert!(matches!(unused, PromiseOrValue::Value(U128(900))));
+    let balance = contract.get_balance(&accounts(3).to_string(), &accounts(2).to_string());
+    assert_eq!(balance, U128(0));
+}
+
+#[test]
+fn test_balance_withdraw_by_predecessor() {
+    let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
+    deposit_tokens(&mut context, &mut contract, accounts(0), accounts(1), U128(10000));
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(1), U128(10000));
+    // a contract called by accounts(3) withdraws its own balance
+    set_caller_signed_by(&mut context, accounts(0), accounts(3));
+    testing_env!(context.attached_deposit(1).build());
+    contract.withdraw(&accounts(1).to_string(), U128(4000), None);
+    let balance = contract.get_balance(&accounts(0).to_string(), &accounts(1).to_string());
+    assert_eq!(balance, U128(6000));
+    let balance = contract.get_balance(&accounts(3).to_string(), &accounts(1).to_string());
+    assert_eq!(balance, U128(10000));
+}
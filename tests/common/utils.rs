@@ -1,12 +1,19 @@
 use mycelium_lab_near_amm::Contract;
 use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_contract_standards::storage_management::StorageManagement;
 use near_sdk::MockedBlockchain;
 use near_sdk::{
     json_types::{ValidAccountId, U128},
     test_utils::{accounts, VMContextBuilder},
     testing_env,
 };
-use near_sdk_sim::to_yocto;
+
+/// Amount of NEAR in yoctoNEAR, e.g. `to_yocto("1.5")`
+pub fn to_yocto(near: &str) -> u128 {
+    let (whole, fraction) = near.split_once('.').unwrap_or((near, ""));
+    let fraction = format!("{:0<24}", fraction);
+    whole.parse::<u128>().unwrap() * 10u128.pow(24) + fraction.parse::<u128>().unwrap()
+}
 
 pub fn setup_contract() -> (VMContextBuilder, Contract) {
     let mut context = VMContextBuilder::new();
@@ -22,6 +29,13 @@ pub fn deposit_tokens(
     token_id: ValidAccountId,
     amount: U128,
 ) {
+    if contract.storage_balance_of(account_id.clone()).is_none() {
+        testing_env!(context
+            .predecessor_account_id(account_id.clone())
+            .attached_deposit(to_yocto("1"))
+            .build());
+        contract.storage_deposit(None, None);
+    }
     testing_env!(context
         .predecessor_account_id(token_id)
         .attached_deposit(to_yocto("1"))
@@ -39,7 +53,132 @@ pub fn withdraw_tokens(
 ) {
     testing_env!(context
         .predecessor_account_id(account_id)
-        .attached_deposit(to_yocto("1"))
+        .attached_deposit(1)
+        .build());
+    contract.withdraw(&token_id.to_string(), amount.into(), None);
+}
+
+/// Opens a position of 50 token0 for accounts(0) in a pool of accounts(1) and accounts(2)
+/// at price 100 and borrows against it
+#[allow(dead_code)]
+pub fn setup_leveraged_position(
+    lower_bound_price: f64,
+    upper_bound_price: f64,
+    leverage: f64,
+) -> (VMContextBuilder, Contract) {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_reserve(&accounts(1).into(), None);
+    contract.create_reserve(&accounts(2).into(), None);
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    pass_twap_window(&mut context, &contract);
+    testing_env!(context.predecessor_account_id(accounts(1)).build());
+    deposit_tokens(
+        &mut context,
+        &mut contract,
+        accounts(0),
+        accounts(1),
+        U128(100100),
+    );
+    testing_env!(context.predecessor_account_id(accounts(2)).build());
+    deposit_tokens(
+        &mut context,
+        &mut contract,
+        accounts(0),
+        accounts(2),
+        U128(200000),
+    );
+    testing_env!(context
+        .predecessor_account_id(accounts(0))
+        .signer_account_id(accounts(0))
+        .build());
+    contract.open_position(
+        0,
+        Some(U128(50)),
+        None,
+        lower_bound_price,
+        upper_bound_price,
+    );
+    contract.create_deposit(&accounts(1).into(), U128::from(100000));
+    contract.create_deposit(&accounts(2).into(), U128::from(100000));
+    contract.supply_collateral_and_borrow(0, 0, leverage);
+    (context, contract)
+}
+
+/// accounts(3) provides wide liquidity and buys token0 with `amount_in` of token1,
+/// moving the price up and out of the leveraged position range
+#[allow(dead_code)]
+pub fn move_price_up(context: &mut VMContextBuilder, contract: &mut Contract, amount_in: u128) {
+    deposit_tokens(context, contract, accounts(3), accounts(1), U128(1000));
+    deposit_tokens(
+        context,
+        contract,
+        accounts(3),
+        accounts(2),
+        U128(100000 + amount_in),
+    );
+    testing_env!(context
+        .predecessor_account_id(accounts(3))
+        .signer_account_id(accounts(3))
+        .build());
+    contract.open_position(0, Some(U128(1000)), None, 1.0, 10000.0);
+    contract.swap(
+        0,
+        &accounts(2).to_string(),
+        U128(amount_in),
+        &accounts(1).to_string(),
+    );
+}
+
+/// Calls the contract from `account_id` in a transaction it signs, without a deposit
+#[allow(dead_code)]
+pub fn set_caller(context: &mut VMContextBuilder, account_id: ValidAccountId) {
+    set_caller_signed_by(context, account_id.clone(), account_id);
+}
+
+/// Calls the contract from `account_id` in a transaction it signs, attaching `deposit`
+#[allow(dead_code)]
+pub fn set_caller_with_deposit(
+    context: &mut VMContextBuilder,
+    account_id: ValidAccountId,
+    deposit: u128,
+) {
+    set_caller(context, account_id);
+    testing_env!(context.attached_deposit(deposit).build());
+}
+
+/// Calls the contract from `predecessor_id` in a transaction signed by `signer_id`,
+/// e.g. from a contract the signer called
+#[allow(dead_code)]
+pub fn set_caller_signed_by(
+    context: &mut VMContextBuilder,
+    predecessor_id: ValidAccountId,
+    signer_id: ValidAccountId,
+) {
+    testing_env!(context
+        .predecessor_account_id(predecessor_id)
+        .signer_account_id(signer_id)
+        .attached_deposit(0)
         .build());
-    contract.withdraw(&token_id.to_string(), amount.into());
+}
+
+#[allow(dead_code)]
+pub fn balance(contract: &Contract, account_id: ValidAccountId, token: ValidAccountId) -> u128 {
+    contract
+        .get_balance(&account_id.to_string(), &token.to_string())
+        .0
+}
+
+/// Moves the block time forward by the TWAP window, so that the pools created before
+/// have the price history the TWAP needs
+#[allow(dead_code)]
+pub fn pass_twap_window(context: &mut VMContextBuilder, contract: &Contract) {
+    let window = contract.get_price_config().twap_window_sec as u64 * 1_000_000_000;
+    let block_timestamp = context.context.block_timestamp + window;
+    testing_env!(context.block_timestamp(block_timestamp).build());
 }
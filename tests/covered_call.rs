@@ -0,0 +1,164 @@
+use mycelium_lab_near_amm::Contract;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+
+use crate::common::utils::balance;
+use crate::common::utils::deposit_tokens;
+use crate::common::utils::move_price_up;
+use crate::common::utils::pass_twap_window;
+use crate::common::utils::set_caller;
+use crate::common::utils::setup_contract;
+
+mod common;
+
+const SECOND: u64 = 1_000_000_000;
+
+/// Pool of accounts(1) and accounts(2) at price 100, accounts(4) locks 100 of token0
+/// from 110 to 121 until 2000 seconds
+fn setup_covered_call() -> (VMContextBuilder, Contract, u128) {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    pass_twap_window(&mut context, &contract);
+    deposit_tokens(&mut context, &mut contract, accounts(4), accounts(1), U128(100));
+    set_caller(&mut context, accounts(4));
+    let position_id =
+        contract.open_covered_call(0, U128(100), 110.0, 121.0, U64(2000 * SECOND));
+    (context, contract, position_id)
+}
+
+/// accounts(5) provides deep liquidity around the pool price, so the settlement swaps
+/// close to the TWAP
+fn add_liquidity(context: &mut VMContextBuilder, contract: &mut Contract) {
+    deposit_tokens(context, contract, accounts(5), accounts(1), U128(1000000));
+    deposit_tokens(context, contract, accounts(5), accounts(2), U128(100000000));
+    set_caller(context, accounts(5));
+    contract.open_position(0, Some(U128(100000)), None, 50.0, 200.0);
+}
+
+#[test]
+fn covered_call_not_exercised() {
+    let (mut context, mut contract, position_id) = setup_covered_call();
+    assert_eq!(balance(&contract, accounts(4), accounts(1)), 0);
+    assert_eq!(
+        contract.get_account_covered_calls(accounts(4).to_string()).len(),
+        1
+    );
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    let status = contract.settle_covered_call(position_id);
+    assert!(!status.exercised && !status.in_range);
+    assert_eq!(balance(&contract, accounts(4), accounts(1)), 100);
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 0);
+    assert!(contract
+        .get_account_covered_calls(accounts(4).to_string())
+        .is_empty());
+}
+
+#[test]
+fn covered_call_exercised() {
+    let (mut context, mut contract, position_id) = setup_covered_call();
+    move_price_up(&mut context, &mut contract, 40000);
+    assert!(contract.get_price(0) > 121.0);
+    let status = contract.get_covered_call(position_id);
+    assert!(status.exercised);
+    assert_eq!(status.amount0.0, 0);
+    // sold between 110 and 121
+    assert!(status.amount1.0 > 11000 && status.amount1.0 < 12100);
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    contract.settle_covered_call(position_id);
+    assert_eq!(balance(&contract, accounts(4), accounts(1)), 0);
+    assert_eq!(
+        balance(&contract, accounts(4), accounts(2)),
+        status.amount1.0
+    );
+}
+
+#[test]
+fn covered_call_settled_in_range() {
+    let (mut context, mut contract, position_id) = setup_covered_call();
+    move_price_up(&mut context, &mut contract, 8000);
+    let status = contract.get_covered_call(position_id);
+    assert!(status.in_range && status.amount0.0 > 0 && status.amount1.0 > 0);
+    add_liquidity(&mut context, &mut contract);
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    // the TWAP at expiration is above the strike, the rest of token0 is sold for token1
+    let status = contract.settle_covered_call(position_id);
+    assert!(status.exercised);
+    assert_eq!(status.amount0.0, 0);
+    assert_eq!(balance(&contract, accounts(4), accounts(1)), 0);
+    assert_eq!(
+        balance(&contract, accounts(4), accounts(2)),
+        status.amount1.0
+    );
+    assert!(status.amount1.0 > 10000);
+}
+
+#[test]
+fn covered_call_crossed_after_expiration() {
+    let (mut context, mut contract, position_id) = setup_covered_call();
+    testing_env!(context.block_timestamp(2050 * SECOND).build());
+    move_price_up(&mut context, &mut contract, 40000);
+    assert!(contract.get_covered_call(position_id).exercised);
+    add_liquidity(&mut context, &mut contract);
+    // settled once the TWAP follows the new price, at the price before expiration
+    testing_env!(context.block_timestamp(2700 * SECOND).build());
+    let status = contract.settle_covered_call(position_id);
+    assert!(!status.exercised);
+    assert_eq!(status.amount1.0, 0);
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 0);
+    assert_eq!(
+        balance(&contract, accounts(4), accounts(1)),
+        status.amount0.0
+    );
+    assert!(status.amount0.0 > 0);
+}
+
+#[test]
+#[should_panic(expected = "Pool price deviates from the TWAP more than allowed")]
+fn settle_covered_call_while_price_deviates_from_twap() {
+    let (mut context, mut contract, position_id) = setup_covered_call();
+    testing_env!(context.block_timestamp(2050 * SECOND).build());
+    move_price_up(&mut context, &mut contract, 40000);
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    contract.settle_covered_call(position_id);
+}
+
+#[test]
+#[should_panic(expected = "Swap result is beyond the max slippage")]
+fn settle_covered_call_swap_beyond_twap() {
+    let (mut context, mut contract, position_id) = setup_covered_call();
+    move_price_up(&mut context, &mut contract, 8000);
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    // the pool is too thin to swap the rest of token0 close to the TWAP
+    contract.settle_covered_call(position_id);
+}
+
+#[test]
+#[should_panic(expected = "Covered call has not expired yet")]
+fn settle_covered_call_before_expiration() {
+    let (_context, mut contract, position_id) = setup_covered_call();
+    contract.settle_covered_call(position_id);
+}
+
+#[test]
+#[should_panic(expected = "Range of a covered call must be above the pool price")]
+fn covered_call_in_range() {
+    let (mut context, mut contract, _) = setup_covered_call();
+    deposit_tokens(&mut context, &mut contract, accounts(4), accounts(1), U128(100));
+    set_caller(&mut context, accounts(4));
+    contract.open_covered_call(0, U128(100), 90.0, 121.0, U64(2000 * SECOND));
+}
+
+#[test]
+#[should_panic(expected = "NFT not found")]
+fn close_locked_covered_call() {
+    let (_context, mut contract, position_id) = setup_covered_call();
+    contract.close_position(0, position_id);
+}
@@ -0,0 +1,160 @@
+use mycelium_lab_near_amm::Contract;
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::{self, json};
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+
+use crate::common::utils::balance;
+use crate::common::utils::deposit_tokens;
+use crate::common::utils::pass_twap_window;
+use crate::common::utils::set_caller;
+use crate::common::utils::set_caller_with_deposit;
+use crate::common::utils::setup_contract;
+use crate::common::utils::to_yocto;
+
+mod common;
+
+/// Pool of accounts(1) and accounts(2) at price 100 with reserves of both tokens,
+/// accounts(3) has 100000 of token0 and 1000000 of token1 and opened position 0
+/// of 50 token0 from 25 to 121
+fn setup_execute() -> (VMContextBuilder, Contract) {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_reserve(&accounts(1).into(), None);
+    contract.create_reserve(&accounts(2).into(), None);
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    pass_twap_window(&mut context, &contract);
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(1), U128(100000));
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(2), U128(1000000));
+    set_caller(&mut context, accounts(3));
+    contract.open_position(0, Some(U128(50)), None, 25.0, 121.0);
+    (context, contract)
+}
+
+fn execute(contract: &mut Contract, actions: serde_json::Value) -> serde_json::Value {
+    let actions = serde_json::from_value(actions).unwrap();
+    serde_json::to_value(contract.execute(actions)).unwrap()
+}
+
+#[test]
+fn execute_liquidity_actions() {
+    let (_, mut contract) = setup_execute();
+    let balance0 = balance(&contract, accounts(3), accounts(1));
+    let results = execute(
+        &mut contract,
+        json!([
+            {"Swap": {
+                "pool_id": 0,
+                "token_in": accounts(2).to_string(),
+                "amount_in": U128(100),
+                "token_out": accounts(1).to_string(),
+            }},
+            {"RemoveLiquidity": {
+                "pool_id": 0,
+                "position_id": 0,
+                "token0_liquidity": U128(10),
+                "token1_liquidity": null,
+            }},
+            {"ClosePosition": {"pool_id": 0, "position_id": 0}},
+        ]),
+    );
+    let results = results.as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    let swapped: u128 = results[0]["Amount"].as_str().unwrap().parse().unwrap();
+    assert!(swapped > 0);
+    assert_eq!(results[1]["Amounts"][0], "10");
+    assert!(results[2]["Amounts"].is_array());
+    assert!(contract.get_pool(0).positions.is_empty());
+    assert!(balance(&contract, accounts(3), accounts(1)) > balance0 + swapped);
+}
+
+#[test]
+fn execute_lending_actions() {
+    let (_, mut contract) = setup_execute();
+    let results = execute(
+        &mut contract,
+        json!([
+            {"CreateDeposit": {"asset": accounts(1).to_string(), "amount": U128(50000)}},
+            {"CreateDeposit": {"asset": accounts(2).to_string(), "amount": U128(500000)}},
+            {"SupplyCollateralAndBorrow": {"pool_id": 0, "position_id": 0, "leverage": 2.0}},
+            {"RepayPartial": {"borrow_id": 0, "amount0": U128(10), "amount1": U128(1000)}},
+            {"CloseDeposit": {"asset": accounts(1).to_string(), "amount": U128(1000)}},
+        ]),
+    );
+    assert_eq!(
+        results,
+        json!(["None", "None", {"BorrowId": 0}, "None", "None"])
+    );
+    let borrow = contract.borrows.get(&0).unwrap();
+    assert_eq!(borrow.owner_id, accounts(3).to_string());
+    let deposits = contract.get_account_deposits(accounts(3).to_string());
+    assert_eq!(deposits[&accounts(1).to_string()].amount, 49000);
+}
+
+#[test]
+fn execute_open_position_request() {
+    let (_, mut contract) = setup_execute();
+    let results = execute(
+        &mut contract,
+        json!([{"OpenPosition": {
+            "request_id": 7,
+            "pool_id": 0,
+            "token0_liquidity": U128(10),
+            "token1_liquidity": null,
+            "lower_bound_price": 90.0,
+            "upper_bound_price": 110.0,
+        }}]),
+    );
+    // the position is opened when the request gets the other token
+    assert_eq!(results, json!([{"PositionId": null}]));
+}
+
+#[test]
+fn execute_deposits_storage() {
+    let (mut context, mut contract) = setup_execute();
+    let total = contract.storage_balance_of(accounts(3)).unwrap().total.0;
+    set_caller_with_deposit(&mut context, accounts(3), to_yocto("1"));
+    execute(&mut contract, json!([]));
+    assert_eq!(
+        contract.storage_balance_of(accounts(3)).unwrap().total.0,
+        total + to_yocto("1")
+    );
+}
+
+#[test]
+#[should_panic(expected = "Position not found")]
+fn execute_stops_at_failed_action() {
+    let (_, mut contract) = setup_execute();
+    execute(
+        &mut contract,
+        json!([{"ClosePosition": {"pool_id": 0, "position_id": 0}},
+               {"ClosePosition": {"pool_id": 0, "position_id": 0}}]),
+    );
+}
+
+#[test]
+#[should_panic(expected = "Withdrawals and transfers require at least 1 yoctoNEAR attached")]
+fn execute_withdraw_without_deposit() {
+    let (_, mut contract) = setup_execute();
+    execute(
+        &mut contract,
+        json!([{"Withdraw": {"token": accounts(1), "amount": "100"}}]),
+    );
+}
+
+#[test]
+fn execute_withdraw_with_deposit() {
+    let (mut context, mut contract) = setup_execute();
+    let balance0 = balance(&contract, accounts(3), accounts(1));
+    set_caller_with_deposit(&mut context, accounts(3), 1);
+    execute(
+        &mut contract,
+        json!([{"Withdraw": {"token": accounts(1), "amount": "100"}}]),
+    );
+    assert_eq!(balance(&contract, accounts(3), accounts(1)), balance0 - 100);
+}
@@ -90,6 +90,9 @@ fn fee_test() {
     let amount3 = result as f64 * 0.01;
     assert!((balance1_lp_after as f64 - amount3).abs() < 10.0);
     assert!(balance2_lp_after == 0);
+    // 10% of the protocol fee goes to the insurance fund
+    let insurance = contract.get_insurance_fund()[&accounts(1).to_string()].0;
+    assert!((insurance as f64 - result as f64 * 0.001).abs() < 2.0);
 }
 
 #[test]
@@ -0,0 +1,329 @@
+use mycelium_lab_near_amm::action::{Action, OpenFuturesAction, TakeFuturesAction};
+use mycelium_lab_near_amm::futures::{FuturesSide, FuturesType};
+use mycelium_lab_near_amm::Contract;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+
+use crate::common::utils::balance;
+use crate::common::utils::deposit_tokens;
+use crate::common::utils::move_price_up;
+use crate::common::utils::pass_twap_window;
+use crate::common::utils::set_caller;
+use crate::common::utils::setup_contract;
+
+mod common;
+
+const SECOND: u64 = 1_000_000_000;
+
+/// Pool of accounts(1) and accounts(2) at price 100, accounts(4) and accounts(5) trade futures
+fn setup_futures() -> (VMContextBuilder, Contract) {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    pass_twap_window(&mut context, &contract);
+    deposit_tokens(&mut context, &mut contract, accounts(4), accounts(2), U128(10000));
+    deposit_tokens(&mut context, &mut contract, accounts(5), accounts(2), U128(10000));
+    (context, contract)
+}
+
+/// accounts(4) goes long and accounts(5) goes short on 10 of token0 at price 100
+fn open_matched_futures(
+    context: &mut VMContextBuilder,
+    contract: &mut Contract,
+    long_collateral: u128,
+    short_collateral: u128,
+) {
+    set_caller(context, accounts(4));
+    contract.open_futures(OpenFuturesAction {
+        pool_id: 0,
+        futures_type: FuturesType::Settlement,
+        side: FuturesSide::Long,
+        price: 100.0,
+        size: U128(10),
+        collateral: U128(long_collateral),
+        expiration_ts: U64(2000 * SECOND),
+        limit_price: None,
+    });
+    set_caller(context, accounts(5));
+    contract.take_futures(0, U128(short_collateral));
+}
+
+/// accounts(4) goes long and accounts(5) goes short on delivery of 10 of token0 at price 100,
+/// the long side escrows token1 and the short side escrows token0
+fn open_deliverable_futures(
+    context: &mut VMContextBuilder,
+    contract: &mut Contract,
+    long_collateral: u128,
+    short_collateral: u128,
+) {
+    deposit_tokens(context, contract, accounts(5), accounts(1), U128(100));
+    set_caller(context, accounts(4));
+    contract.open_futures(OpenFuturesAction {
+        pool_id: 0,
+        futures_type: FuturesType::Deliverable,
+        side: FuturesSide::Long,
+        price: 100.0,
+        size: U128(10),
+        collateral: U128(long_collateral),
+        expiration_ts: U64(2000 * SECOND),
+        limit_price: None,
+    });
+    set_caller(context, accounts(5));
+    contract.take_futures(0, U128(short_collateral));
+}
+
+#[test]
+fn open_and_take_futures() {
+    let (mut context, mut contract) = setup_futures();
+    open_matched_futures(&mut context, &mut contract, 500, 300);
+    let futures = contract.get_futures(0);
+    assert_eq!(futures.long_id, Some(accounts(4).to_string()));
+    assert_eq!(futures.short_id, Some(accounts(5).to_string()));
+    assert_eq!(futures.token, accounts(2).to_string());
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 9500);
+    assert_eq!(balance(&contract, accounts(5), accounts(2)), 9700);
+    assert_eq!(contract.get_account_futures(accounts(4).to_string()).len(), 1);
+    assert_eq!(contract.get_account_futures(accounts(5).to_string()).len(), 1);
+    assert!(contract.get_account_futures(accounts(3).to_string()).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "Collateral is below the initial margin")]
+fn open_futures_below_initial_margin() {
+    let (mut context, mut contract) = setup_futures();
+    open_matched_futures(&mut context, &mut contract, 99, 300);
+}
+
+#[test]
+#[should_panic(expected = "Futures price is beyond the limit price of the side")]
+fn take_futures_beyond_limit_price() {
+    let (mut context, mut contract) = setup_futures();
+    set_caller(&mut context, accounts(4));
+    contract.open_futures(OpenFuturesAction {
+        pool_id: 0,
+        futures_type: FuturesType::Settlement,
+        side: FuturesSide::Long,
+        price: 100.0,
+        size: U128(10),
+        collateral: U128(500),
+        expiration_ts: U64(2000 * SECOND),
+        limit_price: Some(100.0),
+    });
+    set_caller(&mut context, accounts(5));
+    // the short side wants to sell at 101 at least
+    contract.execute(vec![Action::TakeFutures(TakeFuturesAction {
+        futures_id: 0,
+        collateral: U128(500),
+        limit_price: Some(101.0),
+    })]);
+}
+
+#[test]
+fn cancel_futures() {
+    let (mut context, mut contract) = setup_futures();
+    set_caller(&mut context, accounts(4));
+    contract.open_futures(OpenFuturesAction {
+        pool_id: 0,
+        futures_type: FuturesType::Settlement,
+        side: FuturesSide::Short,
+        price: 100.0,
+        size: U128(10),
+        collateral: U128(500),
+        expiration_ts: U64(2000 * SECOND),
+        limit_price: None,
+    });
+    contract.cancel_futures(0);
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 10000);
+    assert!(contract.get_account_futures(accounts(4).to_string()).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "Futures are already matched")]
+fn cancel_matched_futures() {
+    let (mut context, mut contract) = setup_futures();
+    open_matched_futures(&mut context, &mut contract, 500, 300);
+    contract.cancel_futures(0);
+}
+
+#[test]
+fn settle_futures() {
+    let (mut context, mut contract) = setup_futures();
+    open_matched_futures(&mut context, &mut contract, 500, 500);
+    // price goes to 121
+    move_price_up(&mut context, &mut contract, 11111);
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    let margin = contract.get_futures_margin(0);
+    assert!((margin.mark_price - 121.0).abs() < 0.1);
+    contract.settle_futures(0);
+    let long_profit = balance(&contract, accounts(4), accounts(2)) - 9500;
+    let short_rest = balance(&contract, accounts(5), accounts(2)) - 9500;
+    assert_eq!(long_profit + short_rest, 1000);
+    assert!((long_profit as f64 - 500.0 - margin.long_pnl).abs() <= 1.0);
+    assert!(contract.get_account_futures(accounts(4).to_string()).is_empty());
+}
+
+#[test]
+fn settle_futures_ignores_price_after_expiration() {
+    let (mut context, mut contract) = setup_futures();
+    open_matched_futures(&mut context, &mut contract, 500, 500);
+    testing_env!(context.block_timestamp(2050 * SECOND).build());
+    move_price_up(&mut context, &mut contract, 11111);
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    assert!(contract.get_futures_margin(0).mark_price > 101.0);
+    contract.settle_futures(0);
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 10000);
+    assert_eq!(balance(&contract, accounts(5), accounts(2)), 10000);
+}
+
+#[test]
+fn settle_futures_after_price_history_moved_on() {
+    let (mut context, mut contract) = setup_futures();
+    open_matched_futures(&mut context, &mut contract, 500, 500);
+    // price goes to 121
+    move_price_up(&mut context, &mut contract, 11111);
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(2), U128(1000));
+    // the pool refreshes far more often after expiration than it keeps observations
+    for i in 0..100 {
+        testing_env!(context.block_timestamp((2100 + 120 * i) * SECOND).build());
+        set_caller(&mut context, accounts(3));
+        contract.swap(
+            0,
+            &accounts(2).to_string(),
+            U128(10),
+            &accounts(1).to_string(),
+        );
+    }
+    contract.settle_futures(0);
+    // settled at 121 as of expiration
+    let long_profit = balance(&contract, accounts(4), accounts(2)) - 10000;
+    assert!((long_profit as f64 - 210.0).abs() <= 1.0);
+}
+
+#[test]
+#[should_panic(expected = "Futures have not expired yet")]
+fn settle_futures_before_expiration() {
+    let (mut context, mut contract) = setup_futures();
+    open_matched_futures(&mut context, &mut contract, 500, 500);
+    contract.settle_futures(0);
+}
+
+#[test]
+fn liquidate_futures() {
+    let (mut context, mut contract) = setup_futures();
+    open_matched_futures(&mut context, &mut contract, 500, 110);
+    // price goes to 110.25, the short side is below the maintenance margin
+    move_price_up(&mut context, &mut contract, 5556);
+    pass_twap_window(&mut context, &contract);
+    assert!(contract.get_futures_margin(0).short_margin < 0.05);
+    deposit_tokens(&mut context, &mut contract, accounts(0), accounts(2), U128(0));
+    set_caller(&mut context, accounts(0));
+    let fee = contract.liquidate_futures(0).0;
+    assert!(fee > 0);
+    assert_eq!(balance(&contract, accounts(0), accounts(2)), fee);
+    assert_eq!(balance(&contract, accounts(5), accounts(2)), 10000 - 110);
+    assert_eq!(balance(&contract, accounts(4), accounts(2)) + fee, 9500 + 610);
+}
+
+#[test]
+#[should_panic(expected = "Futures are above the maintenance margin")]
+fn liquidate_healthy_futures() {
+    let (mut context, mut contract) = setup_futures();
+    open_matched_futures(&mut context, &mut contract, 500, 500);
+    contract.liquidate_futures(0);
+}
+
+#[test]
+fn open_futures_is_listed_until_taken() {
+    let (mut context, mut contract) = setup_futures();
+    set_caller(&mut context, accounts(4));
+    contract.open_futures(OpenFuturesAction {
+        pool_id: 0,
+        futures_type: FuturesType::Deliverable,
+        side: FuturesSide::Long,
+        price: 100.0,
+        size: U128(10),
+        collateral: U128(100),
+        expiration_ts: U64(2000 * SECOND),
+        limit_price: None,
+    });
+    assert_eq!(contract.get_open_futures(0, 10).len(), 1);
+    deposit_tokens(&mut context, &mut contract, accounts(5), accounts(1), U128(100));
+    set_caller(&mut context, accounts(5));
+    contract.take_futures(0, U128(1));
+    assert!(contract.get_open_futures(0, 10).is_empty());
+}
+
+#[test]
+fn deliver_futures() {
+    let (mut context, mut contract) = setup_futures();
+    open_deliverable_futures(&mut context, &mut contract, 100, 1);
+    assert_eq!(contract.get_futures(0).token, accounts(1).to_string());
+    assert_eq!(balance(&contract, accounts(5), accounts(1)), 99);
+    set_caller(&mut context, accounts(4));
+    contract.add_futures_collateral(0, U128(900));
+    set_caller(&mut context, accounts(5));
+    contract.add_futures_collateral(0, U128(9));
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    contract.settle_futures(0);
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 9000);
+    assert_eq!(balance(&contract, accounts(4), accounts(1)), 10);
+    assert_eq!(balance(&contract, accounts(5), accounts(2)), 11000);
+    assert_eq!(balance(&contract, accounts(5), accounts(1)), 90);
+}
+
+#[test]
+fn deliver_futures_short_defaults() {
+    let (mut context, mut contract) = setup_futures();
+    open_deliverable_futures(&mut context, &mut contract, 1000, 5);
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    contract.settle_futures(0);
+    // the long side gets its payment back and the escrow of the short side
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 10000);
+    assert_eq!(balance(&contract, accounts(4), accounts(1)), 5);
+    assert_eq!(balance(&contract, accounts(5), accounts(2)), 10000);
+    assert_eq!(balance(&contract, accounts(5), accounts(1)), 95);
+}
+
+#[test]
+fn cancel_deliverable_futures() {
+    let (mut context, mut contract) = setup_futures();
+    deposit_tokens(&mut context, &mut contract, accounts(5), accounts(1), U128(100));
+    set_caller(&mut context, accounts(5));
+    contract.open_futures(OpenFuturesAction {
+        pool_id: 0,
+        futures_type: FuturesType::Deliverable,
+        side: FuturesSide::Short,
+        price: 100.0,
+        size: U128(10),
+        collateral: U128(10),
+        expiration_ts: U64(2000 * SECOND),
+        limit_price: None,
+    });
+    assert_eq!(balance(&contract, accounts(5), accounts(1)), 90);
+    contract.cancel_futures(0);
+    assert_eq!(balance(&contract, accounts(5), accounts(1)), 100);
+}
+
+#[test]
+#[should_panic(expected = "Collateral exceeds the delivery amount")]
+fn deliverable_futures_over_escrow() {
+    let (mut context, mut contract) = setup_futures();
+    open_deliverable_futures(&mut context, &mut contract, 1000, 10);
+    set_caller(&mut context, accounts(5));
+    contract.add_futures_collateral(0, U128(1));
+}
+
+#[test]
+#[should_panic(expected = "Only settlement futures are marked to market")]
+fn liquidate_deliverable_futures() {
+    let (mut context, mut contract) = setup_futures();
+    open_deliverable_futures(&mut context, &mut contract, 1000, 10);
+    contract.liquidate_futures(0);
+}
@@ -5,6 +5,8 @@ use near_sdk::testing_env;
 use near_sdk::MockedBlockchain;
 
 use crate::common::utils::deposit_tokens;
+use crate::common::utils::pass_twap_window;
+use crate::common::utils::set_caller;
 use crate::common::utils::setup_contract;
 
 mod common;
@@ -182,8 +184,8 @@ mod common;
 #[test]
 fn supply_collateral_and_borrow_leveraged() {
     let (mut context, mut contract) = setup_contract();
-    contract.create_reserve(&accounts(1).into());
-    contract.create_reserve(&accounts(2).into());
+    contract.create_reserve(&accounts(1).into(), None);
+    contract.create_reserve(&accounts(2).into(), None);
     contract.create_pool(
         accounts(1).to_string(),
         accounts(2).to_string(),
@@ -191,6 +193,7 @@ fn supply_collateral_and_borrow_leveraged() {
         0,
         0,
     );
+    pass_twap_window(&mut context, &contract);
     testing_env!(context.predecessor_account_id(accounts(1)).build());
     deposit_tokens(
         &mut context,
@@ -222,7 +225,7 @@ fn supply_collateral_and_borrow_leveraged() {
     contract.create_deposit(&accounts(1).into(), U128::from(100000));
     contract.create_deposit(&accounts(2).into(), U128::from(100000));
     let balance_before = contract.get_balance(&accounts(0).to_string(), &accounts(2).to_string());
-    let leverage = 2;
+    let leverage = 2.0;
     contract.supply_collateral_and_borrow(0, 0, leverage);
     let balance_after = contract.get_balance(&accounts(0).to_string(), &accounts(2).to_string());
     assert_eq!(balance_before.0, balance_after.0);
@@ -230,15 +233,55 @@ fn supply_collateral_and_borrow_leveraged() {
     assert_eq!(borrow.owner_id, accounts(0).to_string());
     // assert_eq!(borrow.asset, accounts(2).to_string());
     // assert_eq!(borrow.borrowed, (leverage - 1) * total_locked);
-    assert_eq!(borrow.collateral, leverage * total_locked);
+    let position = contract.pools[0].positions.get(&0).unwrap();
+    assert!((position.total_locked as u128).abs_diff(leverage as u128 * total_locked) <= 1);
     assert_eq!(borrow.position_id, 0);
     assert_eq!(borrow.pool_id, 0);
-    assert_eq!(borrow.last_update_timestamp, 0);
+    assert_eq!(
+        borrow.last_update_timestamp,
+        contract.get_price_config().twap_window_sec as u64 * 1_000_000_000
+    );
     assert_eq!(borrow.apr, 1000);
-    assert_eq!(borrow.leverage, Some(leverage));
+    assert_eq!(borrow.leverage, leverage);
     assert_eq!(borrow.fees, 0);
     let token = contract.tokens_by_id.get(&"0".to_string()).unwrap();
-    assert_eq!(token.owner_id, context.context.current_account_id);
+    assert_eq!(token.owner_id, accounts(0).to_string());
+}
+
+#[test]
+#[should_panic(expected = "assertion failed: account_id == nft_owner")]
+fn supply_collateral_and_borrow_on_position_of_other_account() {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_reserve(&accounts(1).into(), None);
+    contract.create_reserve(&accounts(2).into(), None);
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    deposit_tokens(
+        &mut context,
+        &mut contract,
+        accounts(0),
+        accounts(1),
+        U128(100050),
+    );
+    deposit_tokens(
+        &mut context,
+        &mut contract,
+        accounts(0),
+        accounts(2),
+        U128(127515),
+    );
+    set_caller(&mut context, accounts(0));
+    contract.open_position(0, Some(U128(50)), None, 25.0, 121.0);
+    contract.create_deposit(&accounts(1).into(), U128::from(100000));
+    contract.create_deposit(&accounts(2).into(), U128::from(100000));
+    // a borrow of another account would block the borrows and withdrawals of the owner
+    set_caller(&mut context, accounts(3));
+    contract.supply_collateral_and_borrow(0, 0, 2.0);
 }
 
 // #[test]
@@ -378,7 +421,7 @@ fn supply_collateral_and_borrow_leveraged() {
 #[test]
 fn get_account_deposits() {
     let (mut context, mut contract) = setup_contract();
-    contract.create_reserve(&accounts(1).into());
+    contract.create_reserve(&accounts(1).into(), None);
     deposit_tokens(
         &mut context,
         &mut contract,
@@ -423,7 +466,7 @@ fn get_account_deposits() {
 #[test]
 fn refresh_deposit_growth() {
     let (mut context, mut contract) = setup_contract();
-    contract.create_reserve(&accounts(1).into());
+    contract.create_reserve(&accounts(1).into(), None);
     deposit_tokens(
         &mut context,
         &mut contract,
@@ -451,9 +494,9 @@ fn refresh_deposit_growth() {
 #[test]
 fn create_deposit2() {
     let (mut context, mut contract) = setup_contract();
-    contract.create_reserve(&accounts(1).into());
-    contract.create_reserve(&accounts(2).into());
-    contract.create_reserve(&accounts(3).into());
+    contract.create_reserve(&accounts(1).into(), None);
+    contract.create_reserve(&accounts(2).into(), None);
+    contract.create_reserve(&accounts(3).into(), None);
     deposit_tokens(
         &mut context,
         &mut contract,
@@ -503,7 +546,7 @@ fn create_deposit2() {
 #[test]
 fn close_deposit() {
     let (mut context, mut contract) = setup_contract();
-    contract.create_reserve(&accounts(1).into());
+    contract.create_reserve(&accounts(1).into(), None);
     deposit_tokens(
         &mut context,
         &mut contract,
@@ -522,21 +565,45 @@ fn close_deposit() {
 fn create_reserve() {
     let (mut _context, mut contract) = setup_contract();
     assert!(contract.reserves.is_empty());
-    contract.create_reserve(&"usdt.testnet".to_string());
+    contract.create_reserve(&"usdt.testnet".to_string(), None);
     let reserve = contract.reserves.get(&"usdt.testnet".to_string()).unwrap();
     assert_eq!(reserve.deposited, 0);
     assert_eq!(reserve.borrowed, 0);
-    assert_eq!(reserve.liquidation_threshold, 0.0);
-    assert_eq!(reserve.loan_to_value, 0.0);
+    assert_eq!(reserve.config.liquidation_threshold, 0.8);
+    assert_eq!(reserve.config.loan_to_value, 0.8);
     assert_eq!(reserve.target_utilization_rate, 0.0);
-    assert_eq!(reserve.total_liquidity, 0);
     assert_eq!(reserve.utilization_rate, 0.0);
 }
 
+#[test]
+fn set_reserve_config() {
+    let (mut _context, mut contract) = setup_contract();
+    contract.create_reserve(&"usdt.testnet".to_string(), None);
+    let mut config = contract.get_reserve_config(&"usdt.testnet".to_string());
+    config.loan_to_value = 0.6;
+    config.liquidation_threshold = 0.7;
+    config.supply_cap = Some(U128(1000));
+    contract.set_reserve_config(&"usdt.testnet".to_string(), config);
+    let config = contract.get_reserve_config(&"usdt.testnet".to_string());
+    assert_eq!(config.loan_to_value, 0.6);
+    assert_eq!(config.liquidation_threshold, 0.7);
+    assert_eq!(config.supply_cap, Some(U128(1000)));
+}
+
+#[should_panic(expected = "Liquidation threshold must be below 1")]
+#[test]
+fn set_reserve_config_invalid() {
+    let (mut _context, mut contract) = setup_contract();
+    contract.create_reserve(&"usdt.testnet".to_string(), None);
+    let mut config = contract.get_reserve_config(&"usdt.testnet".to_string());
+    config.liquidation_threshold = 1.0;
+    contract.set_reserve_config(&"usdt.testnet".to_string(), config);
+}
+
 #[should_panic]
 #[test]
 fn create_deposit1() {
     let (mut _context, mut contract) = setup_contract();
-    contract.create_reserve(&"usdt.testnet".to_string());
+    contract.create_reserve(&"usdt.testnet".to_string(), None);
     contract.create_deposit(&"usn.testnet".to_string(), U128::from(100));
 }
@@ -0,0 +1,149 @@
+use near_sdk::json_types::U128;
+use near_sdk::test_utils::accounts;
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+
+use crate::common::utils::setup_leveraged_position;
+
+mod common;
+
+#[test]
+fn repay_partial() {
+    let (_context, mut contract) = setup_leveraged_position(25.0, 121.0, 2.0);
+    let borrow = contract.borrows.get(&0).unwrap();
+    let balance0_before = contract.get_balance(&accounts(0).to_string(), &accounts(1).to_string());
+    let balance1_before = contract.get_balance(&accounts(0).to_string(), &accounts(2).to_string());
+    contract.repay_partial(0, U128(10), U128(1000));
+    let repaid = contract.borrows.get(&0).unwrap();
+    assert_eq!(repaid.borrowed0, borrow.borrowed0 - 10);
+    assert_eq!(repaid.borrowed1, borrow.borrowed1 - 1000);
+    assert!(repaid.leverage < borrow.leverage);
+    let balance0_after = contract.get_balance(&accounts(0).to_string(), &accounts(1).to_string());
+    let balance1_after = contract.get_balance(&accounts(0).to_string(), &accounts(2).to_string());
+    assert_eq!(balance0_before.0 - 10, balance0_after.0);
+    assert_eq!(balance1_before.0 - 1000, balance1_after.0);
+    let reserve = contract.reserves.get(&accounts(1).to_string()).unwrap();
+    assert_eq!(reserve.borrowed, repaid.borrowed0);
+    let reserve = contract.reserves.get(&accounts(2).to_string()).unwrap();
+    assert_eq!(reserve.borrowed, repaid.borrowed1);
+}
+
+#[test]
+fn repay_partial_whole_loan() {
+    let (_context, mut contract) = setup_leveraged_position(25.0, 121.0, 2.0);
+    let borrow = contract.borrows.get(&0).unwrap();
+    contract.repay_partial(0, U128(borrow.borrowed0), U128(borrow.borrowed1 + 1000));
+    assert!(contract.borrows.get(&0).is_none());
+    let reserve = contract.reserves.get(&accounts(2).to_string()).unwrap();
+    assert_eq!(reserve.borrowed, 0);
+}
+
+#[test]
+#[should_panic(expected = "You do not own this borrow")]
+fn repay_partial_not_owner() {
+    let (mut context, mut contract) = setup_leveraged_position(25.0, 121.0, 2.0);
+    testing_env!(context.predecessor_account_id(accounts(3)).build());
+    contract.repay_partial(0, U128(10), U128(1000));
+}
+
+#[test]
+fn reduce_leverage() {
+    let (_context, mut contract) = setup_leveraged_position(25.0, 121.0, 2.0);
+    let borrow = contract.borrows.get(&0).unwrap();
+    contract.reduce_leverage(0, 1.5);
+    let reduced = contract.borrows.get(&0).unwrap();
+    assert!((reduced.leverage - 1.5).abs() < 0.01);
+    assert!(reduced.borrowed0 < borrow.borrowed0);
+    assert!(reduced.borrowed1 < borrow.borrowed1);
+    assert!(contract.get_borrow_health_factor(0) > 1.0);
+}
+
+#[test]
+fn reduce_leverage_to_one() {
+    let (_context, mut contract) = setup_leveraged_position(25.0, 121.0, 2.0);
+    contract.reduce_leverage(0, 1.0);
+    assert!(contract.borrows.get(&0).is_none());
+    assert!(contract.pools[0].positions.contains_key(&0));
+}
+
+#[test]
+#[should_panic(expected = "Target leverage must be at least 1 and below the current leverage")]
+fn reduce_leverage_above_current() {
+    let (_context, mut contract) = setup_leveraged_position(25.0, 121.0, 2.0);
+    contract.reduce_leverage(0, 3.0);
+}
+
+#[test]
+fn add_collateral() {
+    let (_context, mut contract) = setup_leveraged_position(25.0, 121.0, 2.0);
+    let borrow = contract.borrows.get(&0).unwrap();
+    let health_factor_before = contract.get_borrow_health_factor(0);
+    contract.add_collateral(0, Some(U128(50)), None);
+    let borrow_after = contract.borrows.get(&0).unwrap();
+    assert_eq!(borrow.borrowed0, borrow_after.borrowed0);
+    assert_eq!(borrow.borrowed1, borrow_after.borrowed1);
+    assert!(borrow_after.leverage < borrow.leverage);
+    assert!(contract.get_borrow_health_factor(0) > health_factor_before);
+}
+
+#[test]
+fn increase_leverage() {
+    let (_context, mut contract) = setup_leveraged_position(81.0, 121.0, 2.0);
+    let borrow = contract.borrows.get(&0).unwrap();
+    contract.increase_leverage(0, 3.0);
+    assert_eq!(contract.borrows.len(), 1);
+    let increased = contract.borrows.get(&0).unwrap();
+    assert!((increased.leverage - 3.0).abs() < 0.01);
+    assert!(increased.borrowed0 > borrow.borrowed0);
+    assert!(increased.borrowed1 > borrow.borrowed1);
+    let reserve = contract.reserves.get(&accounts(1).to_string()).unwrap();
+    assert_eq!(reserve.borrowed, increased.borrowed0);
+    let reserve = contract.reserves.get(&accounts(2).to_string()).unwrap();
+    assert_eq!(reserve.borrowed, increased.borrowed1);
+    assert!(contract.get_borrow_health_factor(0) >= 1.0);
+}
+
+#[test]
+#[should_panic(expected = "Leverage exceeds the maximum for this position")]
+fn increase_leverage_above_max() {
+    let (_context, mut contract) = setup_leveraged_position(81.0, 121.0, 2.0);
+    contract.increase_leverage(0, 5.0);
+}
+
+#[test]
+#[should_panic(expected = "Position is already used as collateral, use increase_leverage instead")]
+fn supply_collateral_and_borrow_twice() {
+    let (_context, mut contract) = setup_leveraged_position(81.0, 121.0, 2.0);
+    contract.supply_collateral_and_borrow(0, 0, 2.0);
+}
+
+#[test]
+#[should_panic(expected = "Borrowing is disabled for the reserve")]
+fn increase_leverage_borrowing_disabled() {
+    let (_context, mut contract) = setup_leveraged_position(81.0, 121.0, 2.0);
+    let mut config = contract.get_reserve_config(&accounts(2).to_string());
+    config.borrowing_enabled = false;
+    contract.set_reserve_config(&accounts(2).to_string(), config);
+    contract.increase_leverage(0, 3.0);
+}
+
+#[test]
+#[should_panic(expected = "Borrow cap of the reserve is exceeded")]
+fn increase_leverage_above_borrow_cap() {
+    let (_context, mut contract) = setup_leveraged_position(81.0, 121.0, 2.0);
+    let borrow = contract.borrows.get(&0).unwrap();
+    let mut config = contract.get_reserve_config(&accounts(2).to_string());
+    config.borrow_cap = Some(U128(borrow.borrowed1 + 10));
+    contract.set_reserve_config(&accounts(2).to_string(), config);
+    contract.increase_leverage(0, 3.0);
+}
+
+#[test]
+fn loan_to_value_limits_max_leverage() {
+    let (_context, mut contract) = setup_leveraged_position(81.0, 121.0, 2.0);
+    let max_leverage = contract.get_max_leverage(0, 81.0, 121.0);
+    let mut config = contract.get_reserve_config(&accounts(1).to_string());
+    config.loan_to_value = 0.5;
+    contract.set_reserve_config(&accounts(1).to_string(), config);
+    assert!(contract.get_max_leverage(0, 81.0, 121.0) < max_leverage);
+}
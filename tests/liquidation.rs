@@ -0,0 +1,200 @@
+use mycelium_lab_near_amm::oracle::{PriceConfig, PriceSource};
+use mycelium_lab_near_amm::{Contract, LiquidationTarget};
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::json_types::U128;
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+
+use crate::common::utils::deposit_tokens;
+use crate::common::utils::move_price_up;
+use crate::common::utils::setup_leveraged_position;
+
+mod common;
+
+fn fund_liquidator(context: &mut VMContextBuilder, contract: &mut Contract) {
+    deposit_tokens(context, contract, accounts(4), accounts(1), U128(1000));
+    deposit_tokens(context, contract, accounts(4), accounts(2), U128(100000));
+    testing_env!(context
+        .predecessor_account_id(accounts(4))
+        .signer_account_id(accounts(4))
+        .build());
+}
+
+/// Values the borrows at the pool price, so that a single swap makes them unhealthy
+fn use_spot_price(contract: &mut Contract) {
+    contract.set_price_config(PriceConfig {
+        source: PriceSource::Spot,
+        ..contract.get_price_config()
+    });
+}
+
+#[test]
+fn preview_liquidation_healthy() {
+    let (_context, contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    let preview = contract.preview_liquidation(0);
+    assert!(preview.health_factor > 1.0);
+    assert_eq!(preview.repay0, U128(0));
+    assert_eq!(preview.repay1, U128(0));
+    assert_eq!(preview.collateral0, U128(0));
+    assert_eq!(preview.collateral1, U128(0));
+    assert!(!preview.seize_position);
+}
+
+#[test]
+#[should_panic(expected = "Borrow is healthy and cannot be liquidated")]
+fn liquidate_healthy() {
+    let (mut context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    fund_liquidator(&mut context, &mut contract);
+    contract.liquidate(LiquidationTarget::Borrow(0));
+}
+
+#[test]
+fn liquidate_partially() {
+    let (mut context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    use_spot_price(&mut contract);
+    move_price_up(&mut context, &mut contract, 44000);
+    assert!(contract.get_borrow_health_factor(0) < 1.0);
+    assert_eq!(contract.get_liquidation_list(), vec![LiquidationTarget::Borrow(0)]);
+    let borrow = contract.borrows.get(&0).unwrap();
+    let preview = contract.preview_liquidation(0);
+    assert!(!preview.seize_position);
+    assert!(preview.repay0.0 > 0 && preview.repay0.0 <= borrow.borrowed0 / 2 + 1);
+    assert!(preview.repay1.0 > 0 && preview.repay1.0 <= borrow.borrowed1 / 2 + 1);
+    fund_liquidator(&mut context, &mut contract);
+    let balance0_before = contract.get_balance(&accounts(4).to_string(), &accounts(1).to_string());
+    let balance1_before = contract.get_balance(&accounts(4).to_string(), &accounts(2).to_string());
+    contract.liquidate(LiquidationTarget::Borrow(0));
+    let balance0_after = contract.get_balance(&accounts(4).to_string(), &accounts(1).to_string());
+    let balance1_after = contract.get_balance(&accounts(4).to_string(), &accounts(2).to_string());
+    assert_eq!(balance0_before.0 - preview.repay0.0 + preview.collateral0.0, balance0_after.0);
+    assert_eq!(balance1_before.0 - preview.repay1.0 + preview.collateral1.0, balance1_after.0);
+    let liquidated = contract.borrows.get(&0).unwrap();
+    assert_eq!(liquidated.borrowed0, borrow.borrowed0 - preview.repay0.0);
+    assert_eq!(liquidated.borrowed1, borrow.borrowed1 - preview.repay1.0);
+    assert!(contract.get_borrow_health_factor(0) > 0.999);
+    let token = contract.tokens_by_id.get(&"0".to_string()).unwrap();
+    assert_eq!(token.owner_id, accounts(0).to_string());
+}
+
+#[test]
+fn liquidate_seize_position() {
+    let (mut context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    use_spot_price(&mut contract);
+    move_price_up(&mut context, &mut contract, 58700);
+    let borrow = contract.borrows.get(&0).unwrap();
+    let preview = contract.preview_liquidation(0);
+    assert!(preview.seize_position);
+    assert_eq!(preview.repay0.0, borrow.borrowed0);
+    assert_eq!(preview.repay1.0, borrow.borrowed1);
+    assert_eq!(preview.deficit0, U128(0));
+    assert_eq!(preview.deficit1, U128(0));
+    fund_liquidator(&mut context, &mut contract);
+    contract.liquidate(LiquidationTarget::Borrow(0));
+    assert!(contract.borrows.get(&0).is_none());
+    let token = contract.tokens_by_id.get(&"0".to_string()).unwrap();
+    assert_eq!(token.owner_id, accounts(4).to_string());
+    let position = contract.pools[0].positions.get(&0).unwrap();
+    assert_eq!(position.owner_id, accounts(4).to_string());
+    let reserve = contract.reserves.get(&accounts(1).to_string()).unwrap();
+    assert_eq!(reserve.borrowed, 0);
+    let reserve = contract.reserves.get(&accounts(2).to_string()).unwrap();
+    assert_eq!(reserve.borrowed, 0);
+}
+
+#[test]
+fn liquidate_seize_position_moves_storage() {
+    let (mut context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    use_spot_price(&mut contract);
+    move_price_up(&mut context, &mut contract, 58700);
+    fund_liquidator(&mut context, &mut contract);
+    let owner_before = contract.storage_balance_of(accounts(0)).unwrap();
+    let liquidator_before = contract.storage_balance_of(accounts(4)).unwrap();
+    contract.liquidate(LiquidationTarget::Borrow(0));
+    // the owner no longer pays for the position, its NFT and the cleared borrow,
+    // the liquidator pays for the position and its NFT
+    let owner_after = contract.storage_balance_of(accounts(0)).unwrap();
+    let liquidator_after = contract.storage_balance_of(accounts(4)).unwrap();
+    let released = owner_after.available.0 - owner_before.available.0;
+    let charged = liquidator_before.available.0 - liquidator_after.available.0;
+    assert!(charged > 0 && charged < released);
+    assert!(charged >= released / 2, "{} {}", charged, released);
+}
+
+#[test]
+fn liquidate_bad_debt_socialized() {
+    let (mut context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    use_spot_price(&mut contract);
+    move_price_up(&mut context, &mut contract, 67000);
+    let borrow = contract.borrows.get(&0).unwrap();
+    let preview = contract.preview_liquidation(0);
+    assert!(preview.seize_position);
+    assert!(preview.repay0.0 < borrow.borrowed0);
+    assert_eq!(preview.deficit0.0, borrow.borrowed0 - preview.repay0.0);
+    assert!(preview.deficit1.0 > 0);
+    fund_liquidator(&mut context, &mut contract);
+    contract.liquidate(LiquidationTarget::Borrow(0));
+    assert!(contract.borrows.get(&0).is_none());
+    let reserve = contract.reserves.get(&accounts(1).to_string()).unwrap();
+    assert_eq!(reserve.borrowed, 0);
+    assert_eq!(reserve.deposited, 100000 - preview.deficit0.0);
+    assert!(reserve.supply_index < 1.0);
+    let reserve = contract.reserves.get(&accounts(2).to_string()).unwrap();
+    assert_eq!(reserve.borrowed, 0);
+    assert_eq!(reserve.deposited, 100000 - preview.deficit1.0);
+    let deposits = contract.get_account_deposits(accounts(0).to_string());
+    let deposit = &deposits[&accounts(2).to_string()];
+    assert!(deposit.amount <= reserve.deposited && deposit.amount + 1 >= reserve.deposited);
+    let history = contract.get_insurance_coverage_history(0, 10);
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[1].covered, U128(0));
+    assert_eq!(history[1].socialized, preview.deficit1);
+}
+
+#[test]
+fn liquidate_bad_debt_covered_by_insurance() {
+    let (mut context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    contract
+        .insurance_fund
+        .balances
+        .insert(accounts(2).to_string(), 100000);
+    use_spot_price(&mut contract);
+    move_price_up(&mut context, &mut contract, 67000);
+    let preview = contract.preview_liquidation(0);
+    fund_liquidator(&mut context, &mut contract);
+    contract.liquidate(LiquidationTarget::Borrow(0));
+    let reserve = contract.reserves.get(&accounts(2).to_string()).unwrap();
+    assert_eq!(reserve.borrowed, 0);
+    assert_eq!(reserve.deposited, 100000);
+    assert_eq!(reserve.supply_index, 1.0);
+    let fund = contract.get_insurance_fund();
+    assert_eq!(fund[&accounts(2).to_string()].0, 100000 - preview.deficit1.0);
+    let history = contract.get_insurance_coverage_history(0, 10);
+    assert_eq!(history[1].covered, preview.deficit1);
+    assert_eq!(history[1].socialized, U128(0));
+}
+
+#[test]
+#[should_panic(expected = "Liquidation bonus is too high for the health factor to be restored")]
+fn set_reserve_config_bonus_too_high() {
+    let (mut context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    testing_env!(context
+        .predecessor_account_id(accounts(0))
+        .current_account_id(accounts(0))
+        .build());
+    let mut config = contract.get_reserve_config(&accounts(1).to_string());
+    config.liquidation_bonus = 0.3;
+    contract.set_reserve_config(&accounts(1).to_string(), config);
+}
+
+#[test]
+fn liquidation_threshold_of_reserve() {
+    let (_context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    let health_factor = contract.get_borrow_health_factor(0);
+    let mut config = contract.get_reserve_config(&accounts(2).to_string());
+    config.loan_to_value = 0.7;
+    config.liquidation_threshold = 0.7;
+    contract.set_reserve_config(&accounts(2).to_string(), config);
+    let expected = health_factor * 0.7 / 0.8;
+    assert!((contract.get_borrow_health_factor(0) - expected).abs() < 1e-9);
+}
@@ -0,0 +1,371 @@
+use mycelium_lab_near_amm::operator::OperatorScope;
+use mycelium_lab_near_amm::Contract;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde_json::{self, json};
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+
+use crate::common::utils::balance;
+use crate::common::utils::deposit_tokens;
+use crate::common::utils::set_caller;
+use crate::common::utils::set_caller_with_deposit;
+use crate::common::utils::setup_contract;
+
+mod common;
+
+const SECOND: u64 = 1_000_000_000;
+
+/// Pool of accounts(1) and accounts(2) at price 100 with liquidity of accounts(0),
+/// accounts(3) has 1000 of both tokens and approved accounts(4) to swap and withdraw
+/// until 100 seconds
+fn setup_operator() -> (VMContextBuilder, Contract) {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    deposit_tokens(&mut context, &mut contract, accounts(0), accounts(1), U128(1000));
+    deposit_tokens(&mut context, &mut contract, accounts(0), accounts(2), U128(200000));
+    set_caller(&mut context, accounts(0));
+    contract.open_position(0, Some(U128(1000)), None, 90.0, 110.0);
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(1), U128(1000));
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(2), U128(1000));
+    set_caller_with_deposit(&mut context, accounts(3), 1);
+    contract.approve_operator(
+        accounts(4),
+        vec![OperatorScope::Swap, OperatorScope::Withdraw],
+        Some(U64(100 * SECOND)),
+        None,
+        None,
+        None,
+    );
+    (context, contract)
+}
+
+fn swap_action(amount_in: u128) -> serde_json::Value {
+    json!({"Swap": {
+        "pool_id": 0,
+        "token_in": accounts(2).to_string(),
+        "amount_in": U128(amount_in),
+        "token_out": accounts(1).to_string(),
+        "min_amount_out": U128(1),
+    }})
+}
+
+fn withdraw_action(receiver_id: Option<ValidAccountId>) -> serde_json::Value {
+    json!({"Withdraw": {
+        "token": accounts(1).to_string(),
+        "amount": U128(10),
+        "receiver_id": receiver_id.map(|receiver_id| receiver_id.to_string()),
+    }})
+}
+
+#[test]
+fn operator_swaps_for_account() {
+    let (mut context, mut contract) = setup_operator();
+    let balance1 = balance(&contract, accounts(3), accounts(1));
+    set_caller(&mut context, accounts(4));
+    let actions = serde_json::from_value(json!([swap_action(1000)])).unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+    assert_eq!(balance(&contract, accounts(3), accounts(2)), 0);
+    assert!(balance(&contract, accounts(3), accounts(1)) > balance1);
+}
+
+#[test]
+fn operator_withdraws_to_account() {
+    let (mut context, mut contract) = setup_operator();
+    let balance1 = balance(&contract, accounts(3), accounts(1));
+    set_caller_with_deposit(&mut context, accounts(4), 1);
+    let actions = serde_json::from_value(json!([
+        withdraw_action(None),
+        withdraw_action(Some(accounts(3)))
+    ]))
+    .unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+    assert_eq!(balance(&contract, accounts(3), accounts(1)), balance1 - 20);
+}
+
+#[test]
+#[should_panic(expected = "Operators can withdraw only to the account")]
+fn operator_withdraws_to_itself() {
+    let (mut context, mut contract) = setup_operator();
+    set_caller_with_deposit(&mut context, accounts(4), 1);
+    let actions = serde_json::from_value(json!([withdraw_action(Some(accounts(4)))])).unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Withdrawals and transfers require at least 1 yoctoNEAR attached")]
+fn operator_withdraws_without_deposit() {
+    let (mut context, mut contract) = setup_operator();
+    set_caller(&mut context, accounts(4));
+    let actions = serde_json::from_value(json!([withdraw_action(None)])).unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Operator is not approved for the action")]
+fn operator_out_of_scope() {
+    let (mut context, mut contract) = setup_operator();
+    set_caller(&mut context, accounts(4));
+    let actions = serde_json::from_value(json!([{"AddLiquidity": {
+        "pool_id": 0,
+        "position_id": 1,
+        "token0_liquidity": U128(10),
+        "token1_liquidity": null,
+    }}]))
+    .unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+fn approve_operator_without_deposit() {
+    let (mut context, mut contract) = setup_operator();
+    set_caller(&mut context, accounts(3));
+    contract.approve_operator(accounts(5), vec![OperatorScope::Swap], None, None, None, None);
+}
+
+#[test]
+#[should_panic(expected = "Operator is not approved")]
+fn operator_not_approved() {
+    let (mut context, mut contract) = setup_operator();
+    set_caller(&mut context, accounts(5));
+    let actions = serde_json::from_value(json!([swap_action(10)])).unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Operator approval has expired")]
+fn operator_expired() {
+    let (mut context, mut contract) = setup_operator();
+    testing_env!(context
+        .predecessor_account_id(accounts(4))
+        .block_timestamp(100 * SECOND)
+        .build());
+    let actions = serde_json::from_value(json!([swap_action(10)])).unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Operator is not approved")]
+fn operator_revoked() {
+    let (mut context, mut contract) = setup_operator();
+    assert_eq!(
+        contract.get_operators(accounts(3).to_string())[&accounts(4).to_string()].scopes,
+        vec![OperatorScope::Swap, OperatorScope::Withdraw]
+    );
+    contract.revoke_operator(accounts(4).to_string());
+    assert!(contract.get_operators(accounts(3).to_string()).is_empty());
+    set_caller(&mut context, accounts(4));
+    let actions = serde_json::from_value(json!([swap_action(10)])).unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "You want to withdraw 10 of charlie but only have 0")]
+fn caller_is_predecessor() {
+    let (mut context, mut contract) = setup_operator();
+    // a contract called by accounts(3) swaps with its own balance
+    deposit_tokens(&mut context, &mut contract, accounts(5), accounts(1), U128(1000));
+    testing_env!(context
+        .predecessor_account_id(accounts(5))
+        .signer_account_id(accounts(3))
+        .build());
+    contract.swap(0, &accounts(2).to_string(), U128(10), &accounts(1).to_string());
+}
+
+#[test]
+fn transfer_actions_for_sender() {
+    let (mut context, mut contract) = setup_operator();
+    let balance1 = balance(&contract, accounts(3), accounts(1));
+    // the token contract is the predecessor and a relayer signs the transaction
+    testing_env!(context
+        .predecessor_account_id(accounts(2))
+        .signer_account_id(accounts(5))
+        .build());
+    let msg = json!({ "actions": [swap_action(1000)] }).to_string();
+    contract.ft_on_transfer(accounts(3), U128(1000), msg);
+    assert_eq!(balance(&contract, accounts(3), accounts(2)), 1000);
+    assert!(balance(&contract, accounts(3), accounts(1)) > balance1);
+}
+
+#[test]
+#[should_panic(expected = "Operators must set slippage bounds, use the actions with min amounts")]
+fn operator_swaps_without_min_amount_out() {
+    let (mut context, mut contract) = setup_operator();
+    set_caller(&mut context, accounts(4));
+    let mut action = swap_action(10);
+    action["Swap"]["min_amount_out"] = serde_json::Value::Null;
+    let actions = serde_json::from_value(json!([action])).unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Swap gives less than the min amount out")]
+fn operator_swaps_below_min_amount_out() {
+    let (mut context, mut contract) = setup_operator();
+    set_caller(&mut context, accounts(4));
+    let mut action = swap_action(100);
+    action["Swap"]["min_amount_out"] = json!(U128(2));
+    let actions = serde_json::from_value(json!([action])).unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Operator is not approved for the token")]
+fn operator_token_not_approved() {
+    let (mut context, mut contract) = setup_operator();
+    contract.approve_operator(
+        accounts(4),
+        vec![OperatorScope::Swap],
+        None,
+        Some(vec![accounts(2)]),
+        None,
+        None,
+    );
+    set_caller(&mut context, accounts(4));
+    // the swap changes the balance of token0 as well
+    let actions = serde_json::from_value(json!([swap_action(100)])).unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Action spends more than the operator limit")]
+fn operator_spends_over_limit() {
+    let (mut context, mut contract) = setup_operator();
+    contract.approve_operator(
+        accounts(4),
+        vec![OperatorScope::Swap],
+        None,
+        Some(vec![accounts(1), accounts(2)]),
+        None,
+        Some([(accounts(2).to_string(), U128(150))].into_iter().collect()),
+    );
+    set_caller(&mut context, accounts(4));
+    let actions = serde_json::from_value(json!([swap_action(100)])).unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+    let operators = contract.get_operators(accounts(3).to_string());
+    assert_eq!(
+        operators[&accounts(4).to_string()].limits[&accounts(2).to_string()],
+        U128(50)
+    );
+    let actions = serde_json::from_value(json!([swap_action(100)])).unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Operator is not approved for the position")]
+fn operator_position_not_approved() {
+    let (mut context, mut contract) = setup_operator();
+    contract.open_position(0, Some(U128(5)), None, 90.0, 110.0);
+    contract.approve_operator(
+        accounts(4),
+        vec![OperatorScope::Liquidity],
+        None,
+        None,
+        Some(vec![U128(0)]),
+        None,
+    );
+    set_caller(&mut context, accounts(4));
+    let actions = serde_json::from_value(json!([{"RemoveLiquidityShare": {
+        "pool_id": 0,
+        "position_id": 1,
+        "basis_points": 5000,
+        "liquidity": null,
+        "min_amount0": U128(0),
+        "min_amount1": U128(0),
+    }}]))
+    .unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Operators must set slippage bounds, use the actions with min amounts")]
+fn operator_closes_position_without_min_amounts() {
+    let (mut context, mut contract) = setup_operator();
+    contract.open_position(0, Some(U128(5)), None, 90.0, 110.0);
+    contract.approve_operator(accounts(4), vec![OperatorScope::Liquidity], None, None, None, None);
+    set_caller(&mut context, accounts(4));
+    let actions =
+        serde_json::from_value(json!([{"ClosePosition": {"pool_id": 0, "position_id": 1}}]))
+            .unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Operators must set slippage bounds, use the actions with min amounts")]
+fn operator_zaps_above_max_slippage() {
+    let (mut context, mut contract) = setup_operator();
+    contract.approve_operator(
+        accounts(4),
+        vec![OperatorScope::Liquidity, OperatorScope::Swap],
+        None,
+        None,
+        None,
+        None,
+    );
+    set_caller(&mut context, accounts(4));
+    let actions = serde_json::from_value(json!([{"ZapOpenPosition": {
+        "pool_id": 0,
+        "token_in": accounts(2).to_string(),
+        "amount_in": U128(100),
+        "lower_bound_price": 90.0,
+        "upper_bound_price": 110.0,
+        "max_slippage": 500,
+    }}]))
+    .unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Operators must set slippage bounds, use the actions with min amounts")]
+fn operator_zap_closes_without_min_amount_out() {
+    let (mut context, mut contract) = setup_operator();
+    contract.open_position(0, Some(U128(5)), None, 90.0, 110.0);
+    contract.approve_operator(
+        accounts(4),
+        vec![OperatorScope::Liquidity, OperatorScope::Swap],
+        None,
+        None,
+        None,
+        None,
+    );
+    set_caller(&mut context, accounts(4));
+    let actions = serde_json::from_value(json!([{"ZapClosePosition": {
+        "pool_id": 0,
+        "position_id": 1,
+        "token_out": accounts(2).to_string(),
+        "min_amount_out": U128(0),
+    }}]))
+    .unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Operators must set slippage bounds, use the actions with min amounts")]
+fn operator_buys_options_without_max_premium() {
+    let (mut context, mut contract) = setup_operator();
+    contract.approve_operator(
+        accounts(4),
+        vec![OperatorScope::Derivatives],
+        None,
+        None,
+        None,
+        None,
+    );
+    set_caller(&mut context, accounts(4));
+    let actions = serde_json::from_value(json!([{"BuyOptions": {
+        "series_id": 0,
+        "writer_id": accounts(0).to_string(),
+        "amount": U128(1),
+        "max_premium": null,
+    }}]))
+    .unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
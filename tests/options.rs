@@ -0,0 +1,239 @@
+use mycelium_lab_near_amm::options::OptionType;
+use mycelium_lab_near_amm::Contract;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+
+use crate::common::utils::balance;
+use crate::common::utils::deposit_tokens;
+use crate::common::utils::move_price_up;
+use crate::common::utils::pass_twap_window;
+use crate::common::utils::set_caller;
+use crate::common::utils::setup_contract;
+
+mod common;
+
+const SECOND: u64 = 1_000_000_000;
+
+/// Pool of accounts(1) and accounts(2) at price 100, accounts(4) writes options
+/// and accounts(5) buys them
+fn setup_options() -> (VMContextBuilder, Contract) {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    pass_twap_window(&mut context, &contract);
+    deposit_tokens(&mut context, &mut contract, accounts(4), accounts(1), U128(1000));
+    deposit_tokens(&mut context, &mut contract, accounts(4), accounts(2), U128(10000));
+    deposit_tokens(&mut context, &mut contract, accounts(5), accounts(2), U128(10000));
+    (context, contract)
+}
+
+/// accounts(4) writes `amount` options of a new series for 1 of token1 each
+/// and accounts(5) buys them
+fn write_and_buy(
+    context: &mut VMContextBuilder,
+    contract: &mut Contract,
+    option_type: OptionType,
+    amount: u128,
+) -> u128 {
+    set_caller(context, accounts(4));
+    let series_id = contract.create_option_series(0, option_type, 100.0, U64(2000 * SECOND));
+    contract.write_options(series_id, U128(amount), U128(1));
+    set_caller(context, accounts(5));
+    contract.buy_options(series_id, accounts(4).to_string(), U128(amount));
+    series_id
+}
+
+#[test]
+fn buy_options() {
+    let (mut context, mut contract) = setup_options();
+    let series_id = write_and_buy(&mut context, &mut contract, OptionType::Call, 1000);
+    assert_eq!(balance(&contract, accounts(4), accounts(1)), 0);
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 11000);
+    assert_eq!(balance(&contract, accounts(5), accounts(2)), 9000);
+    assert_eq!(
+        contract
+            .get_option_balance(series_id, accounts(5).to_string())
+            .0,
+        1000
+    );
+    assert!(contract.get_option_series(series_id).offers.is_empty());
+    assert_eq!(contract.get_active_option_series(0, 10).len(), 1);
+}
+
+#[test]
+fn exercise_call_in_the_money() {
+    let (mut context, mut contract) = setup_options();
+    let series_id = write_and_buy(&mut context, &mut contract, OptionType::Call, 1000);
+    // price goes to 121
+    move_price_up(&mut context, &mut contract, 11111);
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    let price = contract.settle_option_series(series_id);
+    assert!((price - 121.0).abs() < 0.1);
+    set_caller(&mut context, accounts(5));
+    let payoff = contract.exercise_options(series_id).0;
+    assert_eq!(payoff, (1000.0 * (price - 100.0) / price) as u128);
+    assert_eq!(balance(&contract, accounts(5), accounts(1)), payoff);
+    set_caller(&mut context, accounts(4));
+    let collateral = contract.claim_option_collateral(series_id).0;
+    assert!(payoff + collateral <= 1000 && payoff + collateral >= 999);
+    assert_eq!(balance(&contract, accounts(4), accounts(1)), collateral);
+}
+
+#[test]
+fn settle_option_series_ignores_price_after_expiration() {
+    let (mut context, mut contract) = setup_options();
+    let series_id = write_and_buy(&mut context, &mut contract, OptionType::Call, 1000);
+    testing_env!(context.block_timestamp(2050 * SECOND).build());
+    move_price_up(&mut context, &mut contract, 11111);
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    assert_eq!(contract.settle_option_series(series_id), 100.0);
+    set_caller(&mut context, accounts(5));
+    assert_eq!(contract.exercise_options(series_id).0, 0);
+}
+
+#[test]
+#[should_panic(expected = "Account is not registered")]
+fn create_option_series_unregistered() {
+    let (mut context, mut contract) = setup_options();
+    set_caller(&mut context, accounts(3));
+    contract.create_option_series(0, OptionType::Call, 100.0, U64(2000 * SECOND));
+}
+
+#[test]
+fn put_out_of_the_money() {
+    let (mut context, mut contract) = setup_options();
+    let series_id = write_and_buy(&mut context, &mut contract, OptionType::Put, 10);
+    // the put locks the strike in token1
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 10000 - 1000 + 10);
+    move_price_up(&mut context, &mut contract, 11111);
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    set_caller(&mut context, accounts(5));
+    assert_eq!(contract.exercise_options(series_id).0, 0);
+    set_caller(&mut context, accounts(4));
+    assert_eq!(contract.claim_option_collateral(series_id).0, 1000);
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 10010);
+}
+
+#[test]
+fn put_in_the_money() {
+    let (mut context, mut contract) = setup_options();
+    set_caller(&mut context, accounts(4));
+    let series_id =
+        contract.create_option_series(0, OptionType::Put, 130.5, U64(2000 * SECOND));
+    contract.write_options(series_id, U128(10), U128(1));
+    set_caller(&mut context, accounts(5));
+    contract.buy_options(series_id, accounts(4).to_string(), U128(10));
+    // price goes to 121
+    move_price_up(&mut context, &mut contract, 11111);
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    let price = contract.settle_option_series(series_id);
+    set_caller(&mut context, accounts(5));
+    let payoff = contract.exercise_options(series_id).0;
+    // the difference to the strike, out of the 131 of token1 locked per option
+    assert_eq!(payoff, (10.0 * (130.5 - price)) as u128);
+    set_caller(&mut context, accounts(4));
+    let collateral = contract.claim_option_collateral(series_id).0;
+    assert!(payoff + collateral <= 1310 && payoff + collateral >= 1309);
+}
+
+#[test]
+fn cancel_option_offer() {
+    let (mut context, mut contract) = setup_options();
+    set_caller(&mut context, accounts(4));
+    let series_id =
+        contract.create_option_series(0, OptionType::Call, 100.0, U64(2000 * SECOND));
+    contract.write_options(series_id, U128(500), U128(1));
+    set_caller(&mut context, accounts(5));
+    contract.buy_options(series_id, accounts(4).to_string(), U128(200));
+    set_caller(&mut context, accounts(4));
+    contract.cancel_option_offer(series_id);
+    assert_eq!(balance(&contract, accounts(4), accounts(1)), 800);
+}
+
+#[test]
+fn transfer_options() {
+    let (mut context, mut contract) = setup_options();
+    let series_id = write_and_buy(&mut context, &mut contract, OptionType::Call, 1000);
+    contract.transfer_options(series_id, accounts(3).to_string(), U128(400));
+    assert_eq!(
+        contract
+            .get_option_balance(series_id, accounts(3).to_string())
+            .0,
+        400
+    );
+    assert_eq!(
+        contract
+            .get_option_balance(series_id, accounts(5).to_string())
+            .0,
+        600
+    );
+}
+
+#[test]
+#[should_panic(expected = "Not enough options offered")]
+fn buy_more_options_than_offered() {
+    let (mut context, mut contract) = setup_options();
+    set_caller(&mut context, accounts(4));
+    let series_id =
+        contract.create_option_series(0, OptionType::Call, 100.0, U64(2000 * SECOND));
+    contract.write_options(series_id, U128(500), U128(1));
+    set_caller(&mut context, accounts(5));
+    contract.buy_options(series_id, accounts(4).to_string(), U128(501));
+}
+
+#[test]
+#[should_panic(expected = "Option series has not expired yet")]
+fn exercise_options_before_expiration() {
+    let (mut context, mut contract) = setup_options();
+    let series_id = write_and_buy(&mut context, &mut contract, OptionType::Call, 1000);
+    contract.exercise_options(series_id);
+}
+
+#[test]
+#[should_panic(expected = "Option series has expired")]
+fn write_expired_options() {
+    let (mut context, mut contract) = setup_options();
+    set_caller(&mut context, accounts(4));
+    let series_id =
+        contract.create_option_series(0, OptionType::Call, 100.0, U64(2000 * SECOND));
+    testing_env!(context.block_timestamp(2100 * SECOND).build());
+    contract.write_options(series_id, U128(500), U128(1));
+}
+
+#[test]
+fn quote_option() {
+    let (mut context, mut contract) = setup_options();
+    move_price_up(&mut context, &mut contract, 1);
+    context.block_timestamp(3600 * SECOND);
+    set_caller(&mut context, accounts(5));
+    contract.swap(
+        0,
+        &accounts(2).to_string(),
+        U128(1000),
+        &accounts(1).to_string(),
+    );
+    testing_env!(context.block_timestamp(7200 * SECOND).build());
+    let expiration_ts = U64(30 * 24 * 3600 * SECOND);
+    let call = contract.quote_option(0, 100.0, expiration_ts, OptionType::Call);
+    let put = contract.quote_option(0, 100.0, expiration_ts, OptionType::Put);
+    assert!(call.volatility > 0.0);
+    assert!(call.premium > call.spot_price - 100.0);
+    assert!((call.premium - put.premium - (call.spot_price - 100.0)).abs() < 1e-4);
+    assert!(call.delta > 0.5 && call.delta < 1.0);
+    assert!((call.delta - put.delta - 1.0).abs() < 1e-9);
+    assert!(call.gamma > 0.0 && call.vega > 0.0 && call.theta < 0.0);
+}
+
+#[test]
+#[should_panic(expected = "Not enough price history to estimate volatility")]
+fn quote_option_without_history() {
+    let (_context, contract) = setup_options();
+    contract.quote_option(0, 100.0, U64(2000 * SECOND), OptionType::Call);
+}
@@ -0,0 +1,120 @@
+use mock_oracle::{MockOracle, Price as MockPrice};
+use mycelium_lab_near_amm::oracle::{PriceConfig, PriceData, PriceSource};
+use mycelium_lab_near_amm::{Contract, LiquidationTarget};
+use near_sdk::json_types::U128;
+use near_sdk::serde_json;
+use near_sdk::test_utils::accounts;
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+
+use crate::common::utils::move_price_up;
+use crate::common::utils::setup_leveraged_position;
+
+mod common;
+
+const SECOND: u64 = 1_000_000_000;
+
+/// Reports token0 at 1.0 and token1 at 0.01 so the oracle price of the pool is 100
+fn feed_oracle_prices(contract: &mut Contract) {
+    let mut oracle = MockOracle::new(accounts(0).to_string());
+    oracle.set_price(
+        accounts(1).to_string(),
+        MockPrice {
+            multiplier: U128(10000),
+            decimals: 4,
+        },
+    );
+    oracle.set_price(
+        accounts(2).to_string(),
+        MockPrice {
+            multiplier: U128(100),
+            decimals: 4,
+        },
+    );
+    // prices cross the contract boundary as JSON
+    let data = serde_json::to_value(oracle.get_price_data(None)).unwrap();
+    let data: PriceData = serde_json::from_value(data).unwrap();
+    contract.on_oracle_prices(data);
+}
+
+fn use_oracle(contract: &mut Contract, max_deviation: Option<f64>) {
+    contract.set_price_config(PriceConfig {
+        source: PriceSource::Oracle,
+        oracle_id: Some("oracle.near".to_string()),
+        max_deviation,
+        ..contract.get_price_config()
+    });
+}
+
+#[test]
+fn twap_ignores_single_swap() {
+    let (mut context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    context.block_timestamp(1000 * SECOND);
+    move_price_up(&mut context, &mut contract, 44000);
+    testing_env!(context.block_timestamp(1001 * SECOND).build());
+    assert!((contract.get_valuation_price(0) - 100.0).abs() < 1.0);
+    assert!(contract.get_borrow_health_factor(0) > 1.0);
+    assert!(contract.get_liquidation_list().is_empty());
+    contract.set_price_config(PriceConfig {
+        source: PriceSource::Spot,
+        ..contract.get_price_config()
+    });
+    assert!(contract.get_borrow_health_factor(0) < 1.0);
+    assert_eq!(contract.get_liquidation_list(), vec![LiquidationTarget::Borrow(0)]);
+}
+
+#[test]
+fn twap_follows_sustained_price() {
+    let (mut context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    context.block_timestamp(1000 * SECOND);
+    move_price_up(&mut context, &mut contract, 44000);
+    testing_env!(context.block_timestamp(2000 * SECOND).build());
+    assert!((contract.get_valuation_price(0) - contract.get_price(0)).abs() < 1e-9);
+    assert_eq!(contract.get_liquidation_list(), vec![LiquidationTarget::Borrow(0)]);
+}
+
+#[test]
+fn oracle_valuation() {
+    let (mut context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    feed_oracle_prices(&mut contract);
+    use_oracle(&mut contract, None);
+    let health_factor = contract.get_borrow_health_factor(0);
+    move_price_up(&mut context, &mut contract, 44000);
+    assert!((contract.get_valuation_price(0) - 100.0).abs() < 1e-9);
+    assert!(contract.get_price(0) > 121.0);
+    assert!(contract.get_liquidation_list().is_empty());
+    // the position is valued as if the pool was at the oracle price
+    assert!((contract.get_borrow_health_factor(0) - health_factor).abs() < 1e-9);
+}
+
+#[test]
+#[should_panic(expected = "Oracle price is missing or stale")]
+fn oracle_price_stale() {
+    let (mut context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    feed_oracle_prices(&mut contract);
+    use_oracle(&mut contract, None);
+    let block_timestamp = context.context.block_timestamp + 91 * SECOND;
+    testing_env!(context.block_timestamp(block_timestamp).build());
+    contract.get_borrow_health_factor(0);
+}
+
+#[test]
+#[should_panic(expected = "Pool price deviates from the reference price more than allowed")]
+fn liquidate_price_deviation() {
+    let (mut context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    feed_oracle_prices(&mut contract);
+    use_oracle(&mut contract, Some(0.05));
+    move_price_up(&mut context, &mut contract, 44000);
+    assert!(contract.get_liquidation_list().is_empty());
+    contract.liquidate(LiquidationTarget::Borrow(0));
+}
+
+#[test]
+#[should_panic(expected = "Oracle is not set")]
+fn set_price_config_oracle_not_set() {
+    let (_context, mut contract) = setup_leveraged_position(81.0, 121.0, 4.0);
+    contract.set_price_config(PriceConfig {
+        source: PriceSource::Oracle,
+        ..contract.get_price_config()
+    });
+}
@@ -0,0 +1,204 @@
+use mycelium_lab_near_amm::futures::FuturesSide;
+use mycelium_lab_near_amm::{Contract, LiquidationResult, LiquidationTarget};
+use near_sdk::json_types::U128;
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+
+use crate::common::utils::balance;
+use crate::common::utils::deposit_tokens;
+use crate::common::utils::move_price_up;
+use crate::common::utils::pass_twap_window;
+use crate::common::utils::set_caller;
+use crate::common::utils::setup_contract;
+
+mod common;
+
+const SECOND: u64 = 1_000_000_000;
+
+/// Pool of accounts(1) and accounts(2) at price 100, accounts(0) deposits `liquidity`
+/// of accounts(2) into the reserve, accounts(4) and accounts(5) trade perpetuals
+fn setup_perpetuals(liquidity: u128) -> (VMContextBuilder, Contract) {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_reserve(&accounts(2).into(), None);
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    pass_twap_window(&mut context, &contract);
+    deposit_tokens(
+        &mut context,
+        &mut contract,
+        accounts(0),
+        accounts(2),
+        U128(liquidity),
+    );
+    set_caller(&mut context, accounts(0));
+    contract.create_deposit(&accounts(2).into(), U128(liquidity));
+    deposit_tokens(&mut context, &mut contract, accounts(4), accounts(2), U128(10000));
+    deposit_tokens(&mut context, &mut contract, accounts(5), accounts(2), U128(10000));
+    (context, contract)
+}
+
+fn reserve_deposit(contract: &Contract) -> u128 {
+    contract.get_account_deposits(accounts(0).to_string())[&accounts(2).to_string()].amount
+}
+
+#[test]
+fn open_and_close_perpetual() {
+    let (mut context, mut contract) = setup_perpetuals(100000);
+    set_caller(&mut context, accounts(4));
+    let perpetual_id = contract.open_perpetual(0, FuturesSide::Long, U128(10), U128(200));
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 9800);
+    assert_eq!(contract.get_perpetual_market(0).open_interest_long, 10);
+    // price goes to 121
+    move_price_up(&mut context, &mut contract, 11111);
+    pass_twap_window(&mut context, &contract);
+    let status = contract.get_perpetual_status(perpetual_id);
+    assert!((status.pnl - 210.0).abs() < 1.0);
+    set_caller(&mut context, accounts(4));
+    let payout = contract.close_perpetual(perpetual_id).0;
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 9800 + payout);
+    // the profit is paid by the reserve
+    assert_eq!(reserve_deposit(&contract), 100000 - (payout - 200));
+    assert_eq!(contract.get_perpetual_market(0).open_interest_long, 0);
+    assert!(contract.get_account_perpetuals(accounts(4).to_string()).is_empty());
+}
+
+#[test]
+fn perpetual_funding() {
+    let (mut context, mut contract) = setup_perpetuals(100000);
+    set_caller(&mut context, accounts(4));
+    let long_id = contract.open_perpetual(0, FuturesSide::Long, U128(10), U128(200));
+    set_caller(&mut context, accounts(5));
+    let short_id = contract.open_perpetual(0, FuturesSide::Short, U128(10), U128(200));
+    // starts the price history of the pool at about 100
+    move_price_up(&mut context, &mut contract, 1);
+    context.block_timestamp(1000 * SECOND);
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(2), U128(1000));
+    set_caller(&mut context, accounts(3));
+    contract.swap(
+        0,
+        &accounts(2).to_string(),
+        U128(1000),
+        &accounts(1).to_string(),
+    );
+    testing_env!(context.block_timestamp(1100 * SECOND).build());
+    // the mark follows the new price faster than the index
+    assert!(contract.get_perpetual_funding_rate(0) > 0.0);
+    contract.refresh_perpetual_funding(0);
+    testing_env!(context.block_timestamp(1200 * SECOND).build());
+    let long = contract.get_perpetual_status(long_id);
+    let short = contract.get_perpetual_status(short_id);
+    assert!(long.funding > 0.0);
+    assert!((long.funding + short.funding).abs() < 1e-9);
+    assert!((long.equity + short.equity - 400.0).abs() < 1e-6);
+}
+
+#[test]
+fn liquidate_perpetual() {
+    let (mut context, mut contract) = setup_perpetuals(100000);
+    set_caller(&mut context, accounts(5));
+    contract.open_perpetual(0, FuturesSide::Short, U128(10), U128(100));
+    // price goes to 110.25, the loss exceeds the margin
+    move_price_up(&mut context, &mut contract, 5556);
+    pass_twap_window(&mut context, &contract);
+    assert_eq!(
+        contract.get_liquidation_list(),
+        vec![LiquidationTarget::Perpetual(0)]
+    );
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(2), U128(0));
+    set_caller(&mut context, accounts(3));
+    let balance_before = balance(&contract, accounts(3), accounts(2));
+    let fee = match contract.liquidate(LiquidationTarget::Perpetual(0)) {
+        LiquidationResult::Perpetual(fee) => fee.0,
+        _ => unreachable!(),
+    };
+    assert!(fee > 0);
+    assert_eq!(balance(&contract, accounts(3), accounts(2)), balance_before + fee);
+    assert_eq!(balance(&contract, accounts(5), accounts(2)), 9900);
+    // the reserve gets the rest of the margin
+    assert_eq!(reserve_deposit(&contract), 100000 + 100 - fee);
+    assert!(contract.get_liquidation_list().is_empty());
+}
+
+#[test]
+#[should_panic(expected = "Perpetual is above the maintenance margin")]
+fn liquidate_healthy_perpetual() {
+    let (mut context, mut contract) = setup_perpetuals(100000);
+    set_caller(&mut context, accounts(5));
+    contract.open_perpetual(0, FuturesSide::Short, U128(10), U128(100));
+    contract.liquidate(LiquidationTarget::Perpetual(0));
+}
+
+#[test]
+#[should_panic(expected = "Pool price deviates from the mark price more than allowed")]
+fn open_perpetual_while_price_deviates_from_mark() {
+    let (mut context, mut contract) = setup_perpetuals(100000);
+    context.block_timestamp(100 * SECOND);
+    // price goes to 121 while the mark price is still 100
+    move_price_up(&mut context, &mut contract, 11111);
+    set_caller(&mut context, accounts(4));
+    contract.open_perpetual(0, FuturesSide::Long, U128(10), U128(200));
+}
+
+#[test]
+#[should_panic(expected = "Margin is below the initial margin")]
+fn open_perpetual_below_initial_margin() {
+    let (mut context, mut contract) = setup_perpetuals(100000);
+    set_caller(&mut context, accounts(4));
+    contract.open_perpetual(0, FuturesSide::Long, U128(10), U128(99));
+}
+
+#[test]
+#[should_panic(expected = "Open interest cap of the pool is exceeded")]
+fn perpetual_open_interest_cap() {
+    let (mut context, mut contract) = setup_perpetuals(100000);
+    contract.set_perpetual_open_interest_cap(0, Some(U128(15)));
+    set_caller(&mut context, accounts(4));
+    contract.open_perpetual(0, FuturesSide::Long, U128(10), U128(200));
+    contract.open_perpetual(0, FuturesSide::Short, U128(10), U128(200));
+    contract.open_perpetual(0, FuturesSide::Long, U128(10), U128(200));
+}
+
+#[test]
+fn close_perpetual_above_reserve_liquidity() {
+    let (mut context, mut contract) = setup_perpetuals(100);
+    set_caller(&mut context, accounts(4));
+    contract.open_perpetual(0, FuturesSide::Long, U128(10), U128(200));
+    move_price_up(&mut context, &mut contract, 11111);
+    pass_twap_window(&mut context, &contract);
+    let equity = contract.get_perpetual_status(0).equity as u128;
+    set_caller(&mut context, accounts(4));
+    // the reserve pays only its liquidity, the rest of the profit is left as a claim
+    assert_eq!(contract.close_perpetual(0).0, 300);
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 10100);
+    assert_eq!(reserve_deposit(&contract), 0);
+    let claims = contract.get_perpetual_claims(accounts(4).to_string());
+    assert_eq!(claims[&accounts(2).to_string()].0, equity - 300);
+    // the claim is paid once the reserve has liquidity again
+    set_caller(&mut context, accounts(5));
+    contract.create_deposit(&accounts(2).into(), U128(1000));
+    set_caller(&mut context, accounts(4));
+    assert_eq!(contract.claim_perpetual_profit(accounts(2).to_string()).0, equity - 300);
+    assert_eq!(balance(&contract, accounts(4), accounts(2)), 9800 + equity);
+    assert!(contract
+        .get_perpetual_claims(accounts(4).to_string())
+        .is_empty());
+}
+
+#[test]
+#[should_panic(expected = "Reserve does not have enough liquidity to pay the profit")]
+fn claim_perpetual_profit_without_reserve_liquidity() {
+    let (mut context, mut contract) = setup_perpetuals(100);
+    set_caller(&mut context, accounts(4));
+    contract.open_perpetual(0, FuturesSide::Long, U128(10), U128(200));
+    move_price_up(&mut context, &mut contract, 11111);
+    pass_twap_window(&mut context, &contract);
+    set_caller(&mut context, accounts(4));
+    contract.close_perpetual(0);
+    contract.claim_perpetual_profit(accounts(2).to_string());
+}
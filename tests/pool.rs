@@ -1,14 +1,20 @@
 use std::collections::HashMap;
 
+use mycelium_lab_near_amm::action::{
+    AddLiquidityWithAmountsAction, OpenPositionWithAmountsAction, RemoveLiquidityShareAction,
+};
 use mycelium_lab_near_amm::position::Position;
+use mycelium_lab_near_amm::Contract;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
 use near_sdk::json_types::U128;
-use near_sdk::serde_json;
-use near_sdk::test_utils::accounts;
+use near_sdk::serde_json::{self, json};
+use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
 use near_sdk::testing_env;
-use near_sdk::MockedBlockchain;
+use near_sdk::{MockedBlockchain, PromiseOrValue};
 
 use crate::common::utils::deposit_tokens;
 use crate::common::utils::setup_contract;
+use crate::common::utils::setup_leveraged_position;
 
 mod common;
 
@@ -707,6 +713,8 @@ fn value_locked_more_open() {
     testing_env!(context.predecessor_account_id(accounts(0)).build());
     testing_env!(context.signer_account_id(accounts(0)).build());
     for _ in 0..100 {
+        // every position is opened in its own transaction
+        testing_env!(context.build());
         contract.open_position(0, Some(U128(1000)), None, 81.0, 121.0);
         let pool = &contract.pools[0];
         assert!(pool.token0_locked <= initial_balance1);
@@ -1002,3 +1010,221 @@ fn open_many_positions_with_swap2() {
     let pool = &contract.pools[0];
     assert!(pool.positions.len() == 150);
 }
+
+/// Pool of accounts(1) and accounts(2) at price 100,
+/// accounts(0) has 1000 of token0 and 200000 of token1
+fn setup_two_sided() -> (VMContextBuilder, Contract) {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    deposit_tokens(&mut context, &mut contract, accounts(0), accounts(1), U128(1000));
+    deposit_tokens(&mut context, &mut contract, accounts(0), accounts(2), U128(200000));
+    testing_env!(context
+        .predecessor_account_id(accounts(0))
+        .signer_account_id(accounts(0))
+        .build());
+    (context, contract)
+}
+
+fn balances(contract: &Contract) -> (u128, u128) {
+    let account_id = accounts(0).to_string();
+    (
+        contract.get_balance(&account_id, &accounts(1).to_string()).0,
+        contract.get_balance(&account_id, &accounts(2).to_string()).0,
+    )
+}
+
+#[test]
+fn open_position_with_amounts() {
+    let (_, mut contract) = setup_two_sided();
+    let position_id = contract.open_position_with_amounts(OpenPositionWithAmountsAction {
+        pool_id: 0,
+        max_amount0: U128(100),
+        max_amount1: U128(100000),
+        min_amount0: U128(99),
+        min_amount1: U128(0),
+        lower_bound_price: 90.0,
+        upper_bound_price: 110.0,
+    });
+    let position = contract.pools[0].positions[&position_id].clone();
+    // token0 limits the liquidity, the rest of token1 stays in the balance
+    assert_eq!(position.token0_locked.round(), 100.0);
+    assert!(position.token1_locked < 100000.0);
+    let (balance0, balance1) = balances(&contract);
+    assert_eq!(balance0, 900);
+    assert_eq!(balance1, 200000 - position.token1_locked.round() as u128);
+
+    let (amount0, amount1) = contract.add_liquidity_with_amounts(AddLiquidityWithAmountsAction {
+        pool_id: 0,
+        position_id,
+        max_amount0: U128(1000),
+        max_amount1: U128(5000),
+        min_amount0: U128(0),
+        min_amount1: U128(0),
+    });
+    let position = &contract.pools[0].positions[&position_id];
+    assert_eq!(amount1.0, 5000);
+    assert!(amount0.0 < 100);
+    assert_eq!(balances(&contract), (balance0 - amount0.0, balance1 - 5000));
+    assert_eq!(position.token0_locked.round() as u128, 100 + amount0.0);
+}
+
+#[test]
+#[should_panic(expected = "Provided liquidity is below the minimum amounts")]
+fn open_position_with_amounts_below_min() {
+    let (_, mut contract) = setup_two_sided();
+    contract.open_position_with_amounts(OpenPositionWithAmountsAction {
+        pool_id: 0,
+        max_amount0: U128(100),
+        max_amount1: U128(100000),
+        min_amount0: U128(0),
+        min_amount1: U128(100000),
+        lower_bound_price: 90.0,
+        upper_bound_price: 110.0,
+    });
+}
+
+#[test]
+#[should_panic(expected = "Provided liquidity is below the minimum amounts")]
+fn add_liquidity_with_amounts_below_min() {
+    let (_, mut contract) = setup_two_sided();
+    contract.open_position(0, Some(U128(100)), None, 90.0, 110.0);
+    contract.add_liquidity_with_amounts(AddLiquidityWithAmountsAction {
+        pool_id: 0,
+        position_id: 0,
+        max_amount0: U128(1000),
+        max_amount1: U128(5000),
+        min_amount0: U128(100),
+        min_amount1: U128(0),
+    });
+}
+
+#[test]
+fn open_position_with_amounts_on_deposit_refunds_unused() {
+    let (mut context, mut contract) = setup_two_sided();
+    testing_env!(context.predecessor_account_id(accounts(2)).build());
+    let msg = json!({
+        "actions": [{"OpenPositionWithAmounts": {
+            "pool_id": 0,
+            "max_amount0": U128(100),
+            "max_amount1": U128(100000),
+            "min_amount0": U128(0),
+            "min_amount1": U128(0),
+            "lower_bound_price": 90.0,
+            "upper_bound_price": 110.0,
+        }}],
+        "refund_unused": true,
+    });
+    let unused = contract.ft_on_transfer(accounts(0), U128(100000), msg.to_string());
+    let position = &contract.pools[0].positions[&0];
+    let used = position.token1_locked.round() as u128;
+    assert!(matches!(unused, PromiseOrValue::Value(U128(amount)) if amount == 100000 - used));
+    assert_eq!(balances(&contract), (900, 200000));
+}
+
+#[test]
+fn remove_liquidity_share_by_basis_points() {
+    let (_, mut contract) = setup_two_sided();
+    contract.open_position(0, Some(U128(100)), None, 90.0, 110.0);
+    let position = contract.pools[0].positions[&0].clone();
+    let (balance0, balance1) = balances(&contract);
+    let (amount0, amount1) = contract.remove_liquidity_share(RemoveLiquidityShareAction {
+        pool_id: 0,
+        position_id: 0,
+        basis_points: Some(2500),
+        liquidity: None,
+        min_amount0: U128(25),
+        min_amount1: U128(0),
+    });
+    // both tokens are given back
+    assert_eq!(amount0.0, 25);
+    assert_eq!(amount1.0 as f64, (position.token1_locked / 4.0).round());
+    assert_eq!(balances(&contract), (balance0 + 25, balance1 + amount1.0));
+    let liquidity = contract.pools[0].positions[&0].liquidity;
+    assert!((liquidity - position.liquidity * 0.75).abs() < 1e-6);
+}
+
+#[test]
+fn remove_liquidity_share_by_units() {
+    let (_, mut contract) = setup_two_sided();
+    contract.open_position(0, Some(U128(100)), None, 90.0, 110.0);
+    let liquidity = contract.pools[0].positions[&0].liquidity;
+    let (amount0, _) = contract.remove_liquidity_share(RemoveLiquidityShareAction {
+        pool_id: 0,
+        position_id: 0,
+        basis_points: None,
+        liquidity: Some(liquidity / 2.0),
+        min_amount0: U128(0),
+        min_amount1: U128(0),
+    });
+    assert_eq!(amount0.0, 50);
+    assert!((contract.pools[0].positions[&0].liquidity - liquidity / 2.0).abs() < 1e-6);
+}
+
+#[test]
+fn remove_all_liquidity_closes_position() {
+    let (_, mut contract) = setup_two_sided();
+    contract.open_position(0, Some(U128(100)), None, 90.0, 110.0);
+    assert_eq!(contract.nft_supply_for_owner(accounts(0).to_string()).0, 1);
+    contract.remove_liquidity_share(RemoveLiquidityShareAction {
+        pool_id: 0,
+        position_id: 0,
+        basis_points: Some(10000),
+        liquidity: None,
+        min_amount0: U128(100),
+        min_amount1: U128(0),
+    });
+    assert!(contract.pools[0].positions.is_empty());
+    assert_eq!(balances(&contract), (1000, 200000));
+    assert_eq!(contract.nft_supply_for_owner(accounts(0).to_string()).0, 0);
+    assert!(get_logs().last().unwrap().contains(r#""event":"nft_burn""#));
+}
+
+#[test]
+#[should_panic(expected = "Removed liquidity is below the minimum amounts")]
+fn remove_liquidity_share_below_min() {
+    let (_, mut contract) = setup_two_sided();
+    contract.open_position(0, Some(U128(100)), None, 90.0, 110.0);
+    contract.remove_liquidity_share(RemoveLiquidityShareAction {
+        pool_id: 0,
+        position_id: 0,
+        basis_points: Some(2500),
+        liquidity: None,
+        min_amount0: U128(26),
+        min_amount1: U128(0),
+    });
+}
+
+#[test]
+#[should_panic(expected = "Liquidity to remove must be above 0 and not above the position liquidity")]
+fn remove_liquidity_share_above_position() {
+    let (_, mut contract) = setup_two_sided();
+    contract.open_position(0, Some(U128(100)), None, 90.0, 110.0);
+    contract.remove_liquidity_share(RemoveLiquidityShareAction {
+        pool_id: 0,
+        position_id: 0,
+        basis_points: Some(10001),
+        liquidity: None,
+        min_amount0: U128(0),
+        min_amount1: U128(0),
+    });
+}
+
+#[test]
+#[should_panic(expected = "Position is used as collateral, repay the borrow first")]
+fn remove_liquidity_share_of_collateral() {
+    let (_, mut contract) = setup_leveraged_position(25.0, 121.0, 2.0);
+    contract.remove_liquidity_share(RemoveLiquidityShareAction {
+        pool_id: 0,
+        position_id: 0,
+        basis_points: Some(10000),
+        liquidity: None,
+        min_amount0: U128(0),
+        min_amount1: U128(0),
+    });
+}
@@ -0,0 +1,115 @@
+use near_sdk::json_types::U128;
+use near_sdk::test_utils::accounts;
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+
+use crate::common::utils::deposit_tokens;
+use crate::common::utils::setup_contract;
+use crate::common::utils::setup_leveraged_position;
+
+mod common;
+
+#[test]
+fn portfolio_of_unknown_account() {
+    let (_, mut contract) = setup_contract();
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    let portfolio =
+        contract.get_account_portfolio(accounts(3).to_string(), Some(accounts(2).to_string()));
+    assert!(portfolio.balances.is_empty());
+    assert!(portfolio.positions.is_empty());
+    assert!(portfolio.deposits.is_empty());
+    assert!(portfolio.borrows.is_empty());
+    assert_eq!(portfolio.valuation.unwrap().value, 0.0);
+}
+
+#[test]
+fn portfolio_balances_and_positions() {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(1), U128(1000));
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(2), U128(100000));
+    testing_env!(context
+        .predecessor_account_id(accounts(3))
+        .signer_account_id(accounts(3))
+        .build());
+    contract.open_position(0, Some(U128(100)), None, 90.0, 110.0);
+
+    let portfolio = contract.get_account_portfolio(accounts(3).to_string(), None);
+    assert!(portfolio.valuation.is_none());
+    let balance0 = contract.get_balance(&accounts(3).to_string(), &accounts(1).to_string());
+    let balance1 = contract.get_balance(&accounts(3).to_string(), &accounts(2).to_string());
+    assert_eq!(portfolio.balances[&accounts(1).to_string()], balance0);
+    assert_eq!(portfolio.balances[&accounts(2).to_string()], balance1);
+    assert_eq!(portfolio.positions.len(), 1);
+    let position = &portfolio.positions[0];
+    assert_eq!(position.pool_id, 0);
+    assert_eq!(position.position_id, 0);
+    assert_eq!(position.amount0.0, 100);
+    assert_eq!(position.amount0.0 + balance0.0, 1000);
+    assert_eq!(position.amount1.0 + balance1.0, 100000);
+    assert!(position.is_active);
+
+    // everything the account deposited, valued at the pool price
+    let portfolio =
+        contract.get_account_portfolio(accounts(3).to_string(), Some(accounts(2).to_string()));
+    let valuation = portfolio.valuation.unwrap();
+    assert_eq!(valuation.value.round(), 200000.0);
+    assert!(valuation.unpriced_tokens.is_empty());
+    let portfolio =
+        contract.get_account_portfolio(accounts(3).to_string(), Some(accounts(1).to_string()));
+    assert_eq!(portfolio.valuation.unwrap().value.round(), 2000.0);
+}
+
+#[test]
+fn portfolio_deposits_and_borrows() {
+    let (mut context, contract) = setup_leveraged_position(25.0, 121.0, 2.0);
+    let portfolio = contract.get_account_portfolio(accounts(0).to_string(), None);
+    assert_eq!(portfolio.deposits.len(), 2);
+    assert_eq!(portfolio.deposits[0].asset, accounts(1).to_string());
+    assert_eq!(portfolio.deposits[0].amount.0, 100000);
+    assert_eq!(portfolio.deposits[0].interest.0, 0);
+    assert_eq!(portfolio.borrows.len(), 1);
+    let borrow = &portfolio.borrows[0];
+    assert_eq!(borrow.borrow_id, 0);
+    assert_eq!(borrow.position_id, 0);
+    assert_eq!(
+        borrow.health_factor,
+        Some(contract.get_borrow_health_factor(0))
+    );
+
+    // interest and fees accrue without updating the account
+    testing_env!(context.block_timestamp(1_000_000_000_000).build());
+    let portfolio = contract.get_account_portfolio(accounts(0).to_string(), None);
+    assert!(portfolio.deposits[0].interest.0 > 0);
+    assert!(portfolio.borrows[0].fees.0 > 0);
+}
+
+#[test]
+fn portfolio_unpriced_tokens() {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(1), U128(1000));
+    let portfolio =
+        contract.get_account_portfolio(accounts(3).to_string(), Some(accounts(4).to_string()));
+    let valuation = portfolio.valuation.unwrap();
+    assert_eq!(valuation.value, 0.0);
+    assert_eq!(valuation.unpriced_tokens, vec![accounts(1).to_string()]);
+}
@@ -0,0 +1,157 @@
+use mycelium_lab_near_amm::Contract;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde_json::json;
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+use near_sdk::PromiseOrValue;
+
+use crate::common::utils::balance;
+use crate::common::utils::deposit_tokens;
+use crate::common::utils::setup_contract;
+
+mod common;
+
+const SECOND: u64 = 1_000_000_000;
+
+/// Pool of accounts(1) and accounts(2) at price 100, accounts(3) and accounts(4)
+/// have registered storage
+fn setup_request() -> (VMContextBuilder, Contract) {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(1), U128(0));
+    deposit_tokens(&mut context, &mut contract, accounts(4), accounts(1), U128(0));
+    (context, contract)
+}
+
+/// Deposits `amount` of `token` for `sender` with an open position leg of request 1
+fn open_position_leg(
+    context: &mut VMContextBuilder,
+    contract: &mut Contract,
+    sender: ValidAccountId,
+    token: ValidAccountId,
+    amount: u128,
+    expiration_ts: Option<U64>,
+) -> PromiseOrValue<U128> {
+    let (token0_liquidity, token1_liquidity) = if token == accounts(1) {
+        (Some(U128(amount)), None)
+    } else {
+        (None, Some(U128(amount)))
+    };
+    let msg = json!({
+        "actions": [{"OpenPosition": {
+            "request_id": 1,
+            "pool_id": 0,
+            "token0_liquidity": token0_liquidity,
+            "token1_liquidity": token1_liquidity,
+            "lower_bound_price": 90.0,
+            "upper_bound_price": 110.0,
+            "expiration_ts": expiration_ts,
+        }}],
+        "refund_unused": true,
+    });
+    testing_env!(context.predecessor_account_id(token).build());
+    contract.ft_on_transfer(sender, U128(amount), msg.to_string())
+}
+
+#[test]
+fn open_position_with_two_legs() {
+    let (mut context, mut contract) = setup_request();
+    let result =
+        open_position_leg(&mut context, &mut contract, accounts(3), accounts(1), 100, None);
+    assert!(matches!(result, PromiseOrValue::Value(U128(0))));
+    assert_eq!(balance(&contract, accounts(3), accounts(1)), 0);
+    let requests = contract.get_open_position_requests(accounts(3).to_string());
+    assert_eq!(requests[&1].token0_liquidity, Some(U128(100)));
+    assert_eq!(requests[&1].expiration_ts, 3600 * SECOND);
+
+    let result =
+        open_position_leg(&mut context, &mut contract, accounts(3), accounts(2), 20000, None);
+    let unused = match result {
+        PromiseOrValue::Value(unused) => unused.0,
+        _ => panic!(),
+    };
+    let position = &contract.get_pool(0).positions[&0];
+    assert_eq!(position.owner_id, accounts(3).to_string());
+    assert_eq!(position.token0_locked.round(), 100.0);
+    assert_eq!(unused + position.token1_locked.round() as u128, 20000);
+    assert!(contract
+        .get_open_position_requests(accounts(3).to_string())
+        .is_empty());
+}
+
+#[test]
+fn open_position_requests_per_account() {
+    let (mut context, mut contract) = setup_request();
+    open_position_leg(&mut context, &mut contract, accounts(3), accounts(1), 100, None);
+    // the same request id of another account is a new request
+    open_position_leg(&mut context, &mut contract, accounts(4), accounts(2), 10000, None);
+    let requests = contract.get_open_position_requests(accounts(4).to_string());
+    assert_eq!(requests[&1].token1_liquidity, Some(U128(10000)));
+    assert_eq!(
+        contract.get_open_position_requests(accounts(3).to_string())[&1].token0_liquidity,
+        Some(U128(100))
+    );
+}
+
+#[test]
+#[should_panic(expected = "Open position request has expired")]
+fn open_position_request_expired() {
+    let (mut context, mut contract) = setup_request();
+    let expiration_ts = Some(U64(10 * SECOND));
+    open_position_leg(&mut context, &mut contract, accounts(3), accounts(1), 100, expiration_ts);
+    testing_env!(context.block_timestamp(10 * SECOND).build());
+    open_position_leg(&mut context, &mut contract, accounts(3), accounts(2), 20000, None);
+}
+
+#[test]
+#[should_panic(expected = "Position needs more of the other token than the second leg allows")]
+fn open_position_second_leg_too_small() {
+    let (mut context, mut contract) = setup_request();
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(2), U128(20000));
+    open_position_leg(&mut context, &mut contract, accounts(3), accounts(1), 100, None);
+    open_position_leg(&mut context, &mut contract, accounts(3), accounts(2), 1000, None);
+}
+
+#[test]
+fn open_position_request_cancelled() {
+    let (mut context, mut contract) = setup_request();
+    open_position_leg(&mut context, &mut contract, accounts(3), accounts(1), 100, None);
+    testing_env!(context.predecessor_account_id(accounts(3)).build());
+    contract.cancel_open_position_request(accounts(3).to_string(), 1);
+    assert!(contract
+        .get_open_position_requests(accounts(3).to_string())
+        .is_empty());
+    // the held amount is withdrawn to the account
+    assert_eq!(balance(&contract, accounts(3), accounts(1)), 0);
+}
+
+#[test]
+#[should_panic(expected = "Only the account can cancel the request before it expires")]
+fn open_position_request_cancelled_by_other() {
+    let (mut context, mut contract) = setup_request();
+    open_position_leg(&mut context, &mut contract, accounts(3), accounts(1), 100, None);
+    testing_env!(context.predecessor_account_id(accounts(4)).build());
+    contract.cancel_open_position_request(accounts(3).to_string(), 1);
+}
+
+#[test]
+fn open_position_request_expired_cancelled_by_other() {
+    let (mut context, mut contract) = setup_request();
+    open_position_leg(&mut context, &mut contract, accounts(3), accounts(1), 100, None);
+    testing_env!(context
+        .predecessor_account_id(accounts(4))
+        .block_timestamp(3600 * SECOND)
+        .build());
+    contract.cancel_open_position_request(accounts(3).to_string(), 1);
+    assert!(contract
+        .get_open_position_requests(accounts(3).to_string())
+        .is_empty());
+}
@@ -0,0 +1,242 @@
+use mycelium_lab_near_amm::action::OpenFuturesAction;
+use mycelium_lab_near_amm::futures::{FuturesSide, FuturesType};
+use mycelium_lab_near_amm::Contract;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+
+use crate::common::utils::deposit_tokens;
+use crate::common::utils::set_caller;
+use crate::common::utils::set_caller_with_deposit;
+use crate::common::utils::setup_contract;
+use crate::common::utils::to_yocto;
+
+mod common;
+
+/// Pool of accounts(1) and accounts(2) at price 100,
+/// accounts(3) has 1000 of token0 and 100000 of token1
+fn setup_storage() -> (VMContextBuilder, Contract) {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(1), U128(1000));
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(2), U128(100000));
+    (context, contract)
+}
+
+fn storage_used(contract: &Contract, account_id: ValidAccountId) -> u128 {
+    let balance = contract.storage_balance_of(account_id).unwrap();
+    balance.total.0 - balance.available.0
+}
+
+#[test]
+fn storage_deposit_registers() {
+    let (mut context, mut contract) = setup_contract();
+    assert!(contract.storage_balance_of(accounts(3)).is_none());
+    let min = contract.storage_balance_bounds().min.0;
+    set_caller_with_deposit(&mut context, accounts(3), min);
+    let balance = contract.storage_deposit(None, None);
+    assert_eq!(balance.total.0, min);
+    assert!(balance.available.0 < min);
+    // more storage can be deposited for another account
+    set_caller_with_deposit(&mut context, accounts(4), to_yocto("1"));
+    contract.storage_deposit(Some(accounts(3)), None);
+    assert_eq!(
+        contract.storage_balance_of(accounts(3)).unwrap().total.0,
+        min + to_yocto("1")
+    );
+}
+
+#[test]
+fn storage_deposit_registration_only() {
+    let (mut context, mut contract) = setup_contract();
+    let min = contract.storage_balance_bounds().min.0;
+    set_caller_with_deposit(&mut context, accounts(3), to_yocto("1"));
+    let balance = contract.storage_deposit(None, Some(true));
+    assert_eq!(balance.total.0, min);
+}
+
+#[test]
+#[should_panic(expected = "Attached deposit is less than the minimum storage balance")]
+fn storage_deposit_below_minimum() {
+    let (mut context, mut contract) = setup_contract();
+    let min = contract.storage_balance_bounds().min.0;
+    set_caller_with_deposit(&mut context, accounts(3), min - 1);
+    contract.storage_deposit(None, None);
+}
+
+#[test]
+#[should_panic(expected = "Account is not registered, call storage_deposit first")]
+fn storage_deposit_tokens_not_registered() {
+    let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
+    set_caller(&mut context, accounts(1));
+    contract.ft_on_transfer(accounts(3), U128(1000), "".to_string());
+}
+
+#[test]
+fn storage_charged_for_position() {
+    let (mut context, mut contract) = setup_storage();
+    let used = storage_used(&contract, accounts(3));
+    set_caller(&mut context, accounts(3));
+    contract.open_position(0, Some(U128(100)), None, 90.0, 110.0);
+    let used_by_position = storage_used(&contract, accounts(3));
+    assert!(used_by_position > used);
+    contract.close_position(0, 0);
+    assert!(storage_used(&contract, accounts(3)) < used_by_position);
+}
+
+#[test]
+#[should_panic(expected = "Not enough storage deposit")]
+fn storage_not_enough_for_position() {
+    let (mut context, mut contract) = setup_storage();
+    let available = contract.storage_balance_of(accounts(3)).unwrap().available.0;
+    set_caller_with_deposit(&mut context, accounts(3), 1);
+    contract.storage_withdraw(Some(U128(available)));
+    set_caller(&mut context, accounts(3));
+    contract.open_position(0, Some(U128(100)), None, 90.0, 110.0);
+}
+
+#[test]
+fn storage_withdraw_available() {
+    let (mut context, mut contract) = setup_storage();
+    let used = storage_used(&contract, accounts(3));
+    set_caller_with_deposit(&mut context, accounts(3), 1);
+    let balance = contract.storage_withdraw(None);
+    assert_eq!(balance.available.0, 0);
+    assert_eq!(balance.total.0, used);
+}
+
+#[test]
+#[should_panic(expected = "Not enough storage deposit")]
+fn storage_withdraw_too_much() {
+    let (mut context, mut contract) = setup_storage();
+    let total = contract.storage_balance_of(accounts(3)).unwrap().total.0;
+    set_caller_with_deposit(&mut context, accounts(3), 1);
+    contract.storage_withdraw(Some(U128(total)));
+}
+
+#[test]
+#[should_panic(
+    expected = "Account has deposits, borrows, positions, derivatives or token balances"
+)]
+fn storage_unregister_with_balances() {
+    let (mut context, mut contract) = setup_storage();
+    set_caller_with_deposit(&mut context, accounts(3), 1);
+    contract.storage_unregister(None);
+}
+
+#[test]
+#[should_panic(
+    expected = "Account has deposits, borrows, positions, derivatives or token balances"
+)]
+fn storage_unregister_with_position() {
+    let (mut context, mut contract) = setup_storage();
+    set_caller(&mut context, accounts(3));
+    contract.open_position(0, Some(U128(100)), None, 90.0, 110.0);
+    set_caller_with_deposit(&mut context, accounts(3), 1);
+    contract.storage_unregister(Some(true));
+}
+
+#[test]
+fn storage_unregister_force() {
+    let (mut context, mut contract) = setup_storage();
+    set_caller_with_deposit(&mut context, accounts(3), 1);
+    assert!(contract.storage_unregister(Some(true)));
+    assert!(contract.storage_balance_of(accounts(3)).is_none());
+    assert!(contract
+        .get_balance_all_tokens(&accounts(3).to_string())
+        .is_empty());
+    assert!(!contract.storage_unregister(None));
+}
+
+#[test]
+fn storage_charged_for_swap() {
+    let (mut context, mut contract) = setup_storage();
+    set_caller(&mut context, accounts(3));
+    contract.open_position(0, Some(U128(100)), None, 90.0, 110.0);
+    deposit_tokens(&mut context, &mut contract, accounts(4), accounts(2), U128(1000));
+    let used = storage_used(&contract, accounts(4));
+    set_caller(&mut context, accounts(4));
+    contract.swap(0, &accounts(2).to_string(), U128(100), &accounts(1).to_string());
+    // the balance of token0 is a new entry
+    assert!(storage_used(&contract, accounts(4)) > used);
+}
+
+#[test]
+fn storage_charged_for_futures() {
+    let (mut context, mut contract) = setup_storage();
+    let used = storage_used(&contract, accounts(3));
+    set_caller(&mut context, accounts(3));
+    contract.open_futures(OpenFuturesAction {
+        pool_id: 0,
+        futures_type: FuturesType::Settlement,
+        side: FuturesSide::Long,
+        price: 100.0,
+        size: U128(10),
+        collateral: U128(200),
+        expiration_ts: U64(1_000_000_000_000),
+        limit_price: None,
+    });
+    let used_by_futures = storage_used(&contract, accounts(3));
+    assert!(used_by_futures > used);
+    contract.cancel_futures(0);
+    assert!(storage_used(&contract, accounts(3)) < used_by_futures);
+}
+
+#[test]
+fn storage_released_by_settled_futures() {
+    let (mut context, mut contract) = setup_storage();
+    deposit_tokens(&mut context, &mut contract, accounts(4), accounts(2), U128(1000));
+    let maker_used = storage_used(&contract, accounts(3));
+    let taker_used = storage_used(&contract, accounts(4));
+    set_caller(&mut context, accounts(3));
+    contract.open_futures(OpenFuturesAction {
+        pool_id: 0,
+        futures_type: FuturesType::Settlement,
+        side: FuturesSide::Long,
+        price: 100.0,
+        size: U128(10),
+        collateral: U128(200),
+        expiration_ts: U64(1_000_000_000_000),
+        limit_price: None,
+    });
+    set_caller(&mut context, accounts(4));
+    contract.take_futures(0, U128(200));
+    assert!(storage_used(&contract, accounts(3)) > maker_used);
+    assert!(storage_used(&contract, accounts(4)) > taker_used);
+    testing_env!(context.block_timestamp(1_100_000_000_000).build());
+    set_caller(&mut context, accounts(0));
+    contract.settle_futures(0);
+    assert_eq!(storage_used(&contract, accounts(3)), maker_used);
+    assert_eq!(storage_used(&contract, accounts(4)), taker_used);
+}
+
+#[test]
+#[should_panic(
+    expected = "Account has deposits, borrows, positions, derivatives or token balances"
+)]
+fn storage_unregister_with_futures() {
+    let (mut context, mut contract) = setup_storage();
+    set_caller(&mut context, accounts(3));
+    contract.open_futures(OpenFuturesAction {
+        pool_id: 0,
+        futures_type: FuturesType::Settlement,
+        side: FuturesSide::Long,
+        price: 100.0,
+        size: U128(10),
+        collateral: U128(200),
+        expiration_ts: U64(1_000_000_000_000),
+        limit_price: None,
+    });
+    set_caller_with_deposit(&mut context, accounts(3), 1);
+    contract.storage_unregister(Some(true));
+}
@@ -0,0 +1,182 @@
+use mycelium_lab_near_amm::Contract;
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+use near_sdk::{serde_json, PromiseOrValue, PromiseResult};
+
+use crate::common::utils::deposit_tokens;
+use crate::common::utils::setup_contract;
+use crate::common::utils::to_yocto;
+
+mod common;
+
+fn metadata(symbol: &str, decimals: u8) -> FungibleTokenMetadata {
+    FungibleTokenMetadata {
+        spec: "ft-1.0.0".to_string(),
+        name: symbol.to_string(),
+        symbol: symbol.to_string(),
+        icon: None,
+        reference: None,
+        reference_hash: None,
+        decimals,
+    }
+}
+
+fn set_promise_result(context: &mut VMContextBuilder, result: PromiseResult) {
+    testing_env!(
+        context.predecessor_account_id(accounts(0)).build(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        vec![result]
+    );
+}
+
+fn register_storage(
+    context: &mut VMContextBuilder,
+    contract: &mut Contract,
+    account_id: ValidAccountId,
+) {
+    testing_env!(context
+        .predecessor_account_id(account_id)
+        .attached_deposit(to_yocto("1"))
+        .build());
+    contract.storage_deposit(None, None);
+}
+
+#[test]
+fn token_registered_with_pool() {
+    let (_, mut contract) = setup_contract();
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    let tokens = contract.get_tokens(0, 10);
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(tokens[0].token_id, accounts(1).to_string());
+    assert!(tokens[0].registered_by.is_none());
+    assert!(tokens[0].metadata.is_none());
+    // a token of another pool is registered once
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(3).to_string(),
+        100.0,
+        0,
+        0,
+    );
+    assert_eq!(contract.get_tokens(0, 10).len(), 3);
+    assert_eq!(contract.get_tokens(1, 1)[0].token_id, accounts(2).to_string());
+}
+
+#[test]
+fn token_metadata_cached() {
+    let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
+    let value = serde_json::to_vec(&metadata("USN", 18)).unwrap();
+    set_promise_result(&mut context, PromiseResult::Successful(value));
+    contract.on_ft_metadata(accounts(1).to_string());
+    let metadata = contract
+        .get_token(accounts(1).to_string())
+        .unwrap()
+        .metadata
+        .unwrap();
+    assert_eq!(metadata.symbol, "USN");
+    assert_eq!(metadata.decimals, 18);
+}
+
+#[test]
+fn token_metadata_failed() {
+    let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
+    set_promise_result(&mut context, PromiseResult::Failed);
+    contract.on_ft_metadata(accounts(1).to_string());
+    let token = contract.get_token(accounts(1).to_string()).unwrap();
+    assert!(token.metadata.is_none());
+}
+
+#[test]
+fn token_not_registered_refunded() {
+    let (mut context, mut contract) = setup_contract();
+    register_storage(&mut context, &mut contract, accounts(3));
+    testing_env!(context.predecessor_account_id(accounts(1)).build());
+    let unused = contract.ft_on_transfer(accounts(3), U128(1000), "".to_string());
+    assert!(matches!(unused, PromiseOrValue::Value(U128(1000))));
+    assert!(contract
+        .get_balance_all_tokens(&accounts(3).to_string())
+        .is_empty());
+}
+
+#[test]
+fn token_unregistered_withdrawable() {
+    let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(1), U128(1000));
+    testing_env!(context.predecessor_account_id(accounts(0)).build());
+    contract.unregister_tokens(vec![accounts(1).to_string()]);
+    assert!(contract.get_token(accounts(1).to_string()).is_none());
+    testing_env!(context.predecessor_account_id(accounts(1)).build());
+    let unused = contract.ft_on_transfer(accounts(3), U128(1000), "".to_string());
+    assert!(matches!(unused, PromiseOrValue::Value(U128(1000))));
+    testing_env!(context
+        .predecessor_account_id(accounts(3))
+        .signer_account_id(accounts(3))
+        .attached_deposit(1)
+        .build());
+    contract.withdraw(&accounts(1).to_string(), U128(1000), None);
+    assert_eq!(
+        contract
+            .get_balance(&accounts(3).to_string(), &accounts(1).to_string())
+            .0,
+        0
+    );
+}
+
+#[test]
+fn token_registered_by_anyone() {
+    let (mut context, mut contract) = setup_contract();
+    register_storage(&mut context, &mut contract, accounts(3));
+    let initial_available = contract.storage_balance_of(accounts(3)).unwrap().available.0;
+    testing_env!(context.predecessor_account_id(accounts(3)).build());
+    contract.register_token(accounts(1));
+    let token = contract.get_token(accounts(1).to_string()).unwrap();
+    assert_eq!(token.registered_by, Some(accounts(3).to_string()));
+    let available = contract.storage_balance_of(accounts(3)).unwrap().available.0;
+    assert!(available < initial_available);
+    // the metadata is charged to the account too
+    let value = serde_json::to_vec(&metadata("USN", 18)).unwrap();
+    set_promise_result(&mut context, PromiseResult::Successful(value));
+    contract.on_ft_metadata(accounts(1).to_string());
+    assert!(contract.storage_balance_of(accounts(3)).unwrap().available.0 < available);
+    // and given back when the token is unregistered
+    testing_env!(context.predecessor_account_id(accounts(0)).build());
+    contract.unregister_tokens(vec![accounts(1).to_string()]);
+    assert_eq!(
+        contract.storage_balance_of(accounts(3)).unwrap().available.0,
+        initial_available
+    );
+}
+
+#[test]
+#[should_panic(expected = "Token is already registered")]
+fn token_registered_twice() {
+    let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
+    register_storage(&mut context, &mut contract, accounts(3));
+    testing_env!(context.predecessor_account_id(accounts(3)).build());
+    contract.register_token(accounts(1));
+}
+
+#[test]
+#[should_panic(expected = "Account is not registered, call storage_deposit first")]
+fn token_registered_without_storage() {
+    let (mut context, mut contract) = setup_contract();
+    testing_env!(context.predecessor_account_id(accounts(3)).build());
+    contract.register_token(accounts(1));
+}
@@ -0,0 +1,141 @@
+use mycelium_lab_near_amm::operator::OperatorScope;
+use mycelium_lab_near_amm::Contract;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde_json::{self, json};
+use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+
+use crate::common::utils::balance;
+use crate::common::utils::deposit_tokens;
+use crate::common::utils::set_caller;
+use crate::common::utils::set_caller_with_deposit;
+use crate::common::utils::setup_contract;
+use crate::common::utils::to_yocto;
+
+mod common;
+
+/// accounts(3) has 1000 of accounts(1) and accounts(4) has registered storage
+fn setup_transfer() -> (VMContextBuilder, Contract) {
+    let (mut context, mut contract) = setup_contract();
+    contract.register_tokens(vec![accounts(1)]);
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(1), U128(1000));
+    testing_env!(context
+        .predecessor_account_id(accounts(4))
+        .attached_deposit(to_yocto("1"))
+        .build());
+    contract.storage_deposit(None, None);
+    set_caller_with_deposit(&mut context, accounts(3), 1);
+    (context, contract)
+}
+
+fn transfer_action(receiver_id: ValidAccountId, amount: u128) -> serde_json::Value {
+    json!({"Transfer": {
+        "receiver_id": receiver_id.to_string(),
+        "token": accounts(1).to_string(),
+        "amount": U128(amount),
+        "memo": "salary",
+    }})
+}
+
+#[test]
+fn transfer_to_registered_account() {
+    let (_, mut contract) = setup_transfer();
+    let available = contract.storage_balance_of(accounts(4)).unwrap().available.0;
+    let sender_available = contract.storage_balance_of(accounts(3)).unwrap().available.0;
+    contract.transfer(
+        &accounts(4).to_string(),
+        &accounts(1).to_string(),
+        U128(400),
+        Some("salary".to_string()),
+    );
+    assert_eq!(balance(&contract, accounts(3), accounts(1)), 600);
+    assert_eq!(balance(&contract, accounts(4), accounts(1)), 400);
+    let logs = get_logs();
+    assert!(logs.last().unwrap().contains(r#""event":"transfer""#));
+    assert!(logs.last().unwrap().contains(r#""memo":"salary""#));
+    // the sender pays the storage of the new balance of the receiver
+    assert_eq!(contract.storage_balance_of(accounts(4)).unwrap().available.0, available);
+    assert!(contract.storage_balance_of(accounts(3)).unwrap().available.0 < sender_available);
+}
+
+#[test]
+#[should_panic(expected = "Account is not registered, call storage_deposit first")]
+fn transfer_to_unregistered_account() {
+    let (_, mut contract) = setup_transfer();
+    contract.transfer(
+        &accounts(5).to_string(),
+        &accounts(1).to_string(),
+        U128(400),
+        None,
+    );
+}
+
+#[test]
+#[should_panic(expected = "You want to withdraw 1001 of bob but only have 1000")]
+fn transfer_more_than_balance() {
+    let (_, mut contract) = setup_transfer();
+    contract.transfer(
+        &accounts(4).to_string(),
+        &accounts(1).to_string(),
+        U128(1001),
+        None,
+    );
+}
+
+#[test]
+#[should_panic(expected = "The sender and the receiver should be different")]
+fn transfer_to_itself() {
+    let (_, mut contract) = setup_transfer();
+    contract.transfer(
+        &accounts(3).to_string(),
+        &accounts(1).to_string(),
+        U128(400),
+        None,
+    );
+}
+
+#[test]
+fn transfer_action_on_deposit() {
+    let (mut context, mut contract) = setup_transfer();
+    testing_env!(context.predecessor_account_id(accounts(1)).build());
+    let msg = json!({ "actions": [transfer_action(accounts(4), 500)] }).to_string();
+    contract.ft_on_transfer(accounts(3), U128(500), msg);
+    assert_eq!(balance(&contract, accounts(3), accounts(1)), 1000);
+    assert_eq!(balance(&contract, accounts(4), accounts(1)), 500);
+}
+
+#[test]
+fn transfer_by_operator() {
+    let (mut context, mut contract) = setup_transfer();
+    contract.approve_operator(accounts(5), vec![OperatorScope::Transfer], None, None, None, None);
+    set_caller_with_deposit(&mut context, accounts(5), 1);
+    let actions = serde_json::from_value(json!([transfer_action(accounts(4), 100)])).unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+    assert_eq!(balance(&contract, accounts(4), accounts(1)), 100);
+}
+
+#[test]
+#[should_panic(expected = "Operator is not approved for the action")]
+fn transfer_by_operator_out_of_scope() {
+    let (mut context, mut contract) = setup_transfer();
+    contract.approve_operator(accounts(5), vec![OperatorScope::Withdraw], None, None, None, None);
+    set_caller_with_deposit(&mut context, accounts(5), 1);
+    let actions = serde_json::from_value(json!([transfer_action(accounts(4), 100)])).unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+fn transfer_without_yocto() {
+    let (mut context, mut contract) = setup_transfer();
+    set_caller(&mut context, accounts(3));
+    contract.transfer(
+        &accounts(4).to_string(),
+        &accounts(1).to_string(),
+        U128(400),
+        None,
+    );
+}
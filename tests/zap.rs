@@ -0,0 +1,390 @@
+use mycelium_lab_near_amm::action::{
+    ZapClosePositionAction, ZapOpenPositionAction, ZapRemoveLiquidityShareAction,
+};
+use mycelium_lab_near_amm::operator::OperatorScope;
+use mycelium_lab_near_amm::Contract;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::{self, json};
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::testing_env;
+use near_sdk::MockedBlockchain;
+
+use crate::common::utils::balance;
+use crate::common::utils::deposit_tokens;
+use crate::common::utils::pass_twap_window;
+use crate::common::utils::set_caller;
+use crate::common::utils::set_caller_with_deposit;
+use crate::common::utils::setup_contract;
+
+mod common;
+
+const SECOND: u64 = 1_000_000_000;
+
+/// Pool 0 of accounts(1) and accounts(2) at price 100 with 30 bps of rewards and
+/// pool 1 of accounts(4) and accounts(1) at price 2, both with liquidity of accounts(0).
+/// accounts(3) has 1000 of accounts(1), 100000 of accounts(2) and 1000 of accounts(4).
+fn setup_zap() -> (VMContextBuilder, Contract) {
+    let (mut context, mut contract) = setup_contract();
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(2).to_string(),
+        100.0,
+        0,
+        30,
+    );
+    contract.create_pool(
+        accounts(4).to_string(),
+        accounts(1).to_string(),
+        2.0,
+        0,
+        0,
+    );
+    pass_twap_window(&mut context, &contract);
+    deposit_tokens(&mut context, &mut contract, accounts(0), accounts(1), U128(10000000));
+    deposit_tokens(&mut context, &mut contract, accounts(0), accounts(2), U128(1000000000));
+    deposit_tokens(&mut context, &mut contract, accounts(0), accounts(4), U128(10000000));
+    set_caller(&mut context, accounts(0));
+    contract.open_position(0, Some(U128(1000000)), None, 50.0, 200.0);
+    contract.open_position(1, Some(U128(1000000)), None, 1.0, 4.0);
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(1), U128(1000));
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(2), U128(100000));
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(4), U128(1000));
+    set_caller(&mut context, accounts(3));
+    (context, contract)
+}
+
+#[test]
+fn swap_multihope_through_pools() {
+    let (_, mut contract) = setup_zap();
+    // accounts(2) is swapped into accounts(1) in pool 0 and then into accounts(4) in pool 1
+    let amount1 = contract.get_return(0, &accounts(2).to_string(), U128(10000));
+    let expected = contract.get_return(1, &accounts(1).to_string(), amount1);
+    let amount_out = contract.swap_multihope(
+        &accounts(2).to_string(),
+        U128(10000),
+        &accounts(4).to_string(),
+    );
+    assert_eq!(amount_out, expected);
+    assert_eq!(balance(&contract, accounts(3), accounts(2)), 90000);
+    assert_eq!(balance(&contract, accounts(3), accounts(1)), 1000);
+    assert_eq!(
+        balance(&contract, accounts(3), accounts(4)),
+        1000 + expected.0
+    );
+}
+
+#[test]
+fn zap_open_position_from_token0() {
+    let (_, mut contract) = setup_zap();
+    let position_id = contract.zap_open_position(ZapOpenPositionAction {
+        pool_id: 0,
+        token_in: accounts(1).to_string(),
+        amount_in: U128(1000),
+        lower_bound_price: 90.0,
+        upper_bound_price: 110.0,
+        max_slippage: 100,
+    });
+    let position = &contract.pools[0].positions[&position_id];
+    assert_eq!(position.owner_id, accounts(3).to_string());
+    // about half of the value is swapped, the dust stays in the balance
+    assert!(position.token0_locked > 450.0);
+    let dust0 = balance(&contract, accounts(3), accounts(1));
+    let dust1 = balance(&contract, accounts(3), accounts(2)) - 100000;
+    assert!(dust0 + dust1 / 100 <= 5, "dust {} {}", dust0, dust1);
+}
+
+#[test]
+fn zap_open_position_from_token1() {
+    let (_, mut contract) = setup_zap();
+    let position_id = contract.zap_open_position(ZapOpenPositionAction {
+        pool_id: 0,
+        token_in: accounts(2).to_string(),
+        amount_in: U128(100000),
+        lower_bound_price: 90.0,
+        upper_bound_price: 110.0,
+        max_slippage: 100,
+    });
+    let position = &contract.pools[0].positions[&position_id];
+    assert!(position.token1_locked > 45000.0);
+    let dust0 = balance(&contract, accounts(3), accounts(1)) - 1000;
+    let dust1 = balance(&contract, accounts(3), accounts(2));
+    assert!(dust0 + dust1 / 100 <= 5, "dust {} {}", dust0, dust1);
+}
+
+#[test]
+fn zap_open_position_out_of_range() {
+    let (_, mut contract) = setup_zap();
+    let price = contract.get_price(0);
+    // above the price the position holds only token0, nothing is swapped
+    let position_id = contract.zap_open_position(ZapOpenPositionAction {
+        pool_id: 0,
+        token_in: accounts(1).to_string(),
+        amount_in: U128(1000),
+        lower_bound_price: 110.0,
+        upper_bound_price: 121.0,
+        max_slippage: 0,
+    });
+    assert_eq!(contract.pools[0].positions[&position_id].token0_locked.round(), 1000.0);
+    assert_eq!(balance(&contract, accounts(3), accounts(1)), 0);
+    assert_eq!(contract.get_price(0), price);
+}
+
+#[test]
+fn zap_open_position_through_other_pool() {
+    let (_, mut contract) = setup_zap();
+    let position_id = contract.zap_open_position(ZapOpenPositionAction {
+        pool_id: 0,
+        token_in: accounts(4).to_string(),
+        amount_in: U128(1000),
+        lower_bound_price: 90.0,
+        upper_bound_price: 110.0,
+        max_slippage: 100,
+    });
+    let position = &contract.pools[0].positions[&position_id];
+    // 1000 of accounts(4) are worth about 2000 of token0
+    assert!(position.token0_locked > 900.0);
+    assert_eq!(balance(&contract, accounts(3), accounts(4)), 0);
+}
+
+#[test]
+#[should_panic(expected = "Swap result is beyond the max slippage")]
+fn zap_open_position_beyond_slippage() {
+    let (_, mut contract) = setup_zap();
+    // the rewards of the pool alone are 30 bps
+    contract.zap_open_position(ZapOpenPositionAction {
+        pool_id: 0,
+        token_in: accounts(1).to_string(),
+        amount_in: U128(1000),
+        lower_bound_price: 90.0,
+        upper_bound_price: 110.0,
+        max_slippage: 10,
+    });
+}
+
+#[test]
+#[should_panic(expected = "Lower bound price must be below the upper bound price")]
+fn zap_open_position_with_inverted_range() {
+    let (_, mut contract) = setup_zap();
+    contract.zap_open_position(ZapOpenPositionAction {
+        pool_id: 0,
+        token_in: accounts(1).to_string(),
+        amount_in: U128(1000),
+        lower_bound_price: 110.0,
+        upper_bound_price: 90.0,
+        max_slippage: 100,
+    });
+}
+
+#[test]
+#[should_panic(expected = "Swap result is beyond the max slippage")]
+fn zap_open_position_after_price_move() {
+    let (mut context, mut contract) = setup_zap();
+    context.block_timestamp(1000 * SECOND);
+    set_caller(&mut context, accounts(0));
+    // a swap right before the zap moves the spot price away from the TWAP
+    contract.swap(
+        0,
+        &accounts(2).to_string(),
+        U128(2000000),
+        &accounts(1).to_string(),
+    );
+    set_caller(&mut context, accounts(3));
+    contract.zap_open_position(ZapOpenPositionAction {
+        pool_id: 0,
+        token_in: accounts(2).to_string(),
+        amount_in: U128(100000),
+        lower_bound_price: 90.0,
+        upper_bound_price: 110.0,
+        max_slippage: 100,
+    });
+}
+
+#[test]
+#[should_panic(expected = "No route with the liquidity to swap the token into a token of the pool")]
+fn zap_open_position_without_pool() {
+    let (mut context, mut contract) = setup_zap();
+    deposit_tokens(&mut context, &mut contract, accounts(3), accounts(5), U128(1000));
+    set_caller(&mut context, accounts(3));
+    contract.zap_open_position(ZapOpenPositionAction {
+        pool_id: 0,
+        token_in: accounts(5).to_string(),
+        amount_in: U128(1000),
+        lower_bound_price: 90.0,
+        upper_bound_price: 110.0,
+        max_slippage: 100,
+    });
+}
+
+#[test]
+fn zap_open_position_on_deposit() {
+    let (mut context, mut contract) = setup_zap();
+    testing_env!(context.predecessor_account_id(accounts(1)).build());
+    let msg = json!({"actions": [{"ZapOpenPosition": {
+        "pool_id": 0,
+        "token_in": accounts(1).to_string(),
+        "amount_in": U128(500),
+        "lower_bound_price": 90.0,
+        "upper_bound_price": 110.0,
+        "max_slippage": 100,
+    }}]});
+    contract.ft_on_transfer(accounts(3), U128(500), msg.to_string());
+    assert_eq!(contract.nft_supply_for_owner(accounts(3).to_string()).0, 1);
+}
+
+/// accounts(3) opens position 2 of 100 token0 from 90 to 110.
+/// Returns the balances of accounts(1) and accounts(2) before.
+fn open_position(contract: &mut Contract) -> (u128, u128) {
+    let balances = (balance(contract, accounts(3), accounts(1)), balance(contract, accounts(3), accounts(2)));
+    contract.open_position(0, Some(U128(100)), None, 90.0, 110.0);
+    balances
+}
+
+#[test]
+fn zap_close_position_into_pool_token() {
+    let (_, mut contract) = setup_zap();
+    let (balance0, balance1) = open_position(&mut contract);
+    let position = contract.pools[0].positions[&2].clone();
+    let amount_out = contract
+        .zap_close_position(ZapClosePositionAction {
+            pool_id: 0,
+            position_id: 2,
+            token_out: accounts(1).to_string(),
+            min_amount_out: U128(0),
+        })
+        .0;
+    assert!(!contract.pools[0].positions.contains_key(&2));
+    assert_eq!(contract.nft_supply_for_owner(accounts(3).to_string()).0, 0);
+    // token1 of the position is swapped into token0
+    assert_eq!(
+        balance(&contract, accounts(3), accounts(2)),
+        balance1 - position.token1_locked.round() as u128
+    );
+    assert_eq!(balance(&contract, accounts(3), accounts(1)), balance0 - 100 + amount_out);
+    let value = position.token0_locked + position.token1_locked / 100.0;
+    assert!(amount_out as f64 > value * 0.99 && (amount_out as f64) < value);
+}
+
+#[test]
+fn zap_remove_liquidity_share_into_pool_token() {
+    let (_, mut contract) = setup_zap();
+    let (balance0, _) = open_position(&mut contract);
+    let liquidity = contract.pools[0].positions[&2].liquidity;
+    let amount_out = contract
+        .zap_remove_liquidity_share(ZapRemoveLiquidityShareAction {
+            pool_id: 0,
+            position_id: 2,
+            basis_points: Some(5000),
+            liquidity: None,
+            token_out: accounts(2).to_string(),
+            min_amount_out: U128(0),
+        })
+        .0;
+    assert!((contract.pools[0].positions[&2].liquidity - liquidity / 2.0).abs() < 1e-6);
+    assert_eq!(balance(&contract, accounts(3), accounts(1)), balance0 - 100);
+    assert!(amount_out > 0);
+}
+
+#[test]
+fn zap_close_position_through_route() {
+    let (_, mut contract) = setup_zap();
+    let (balance0, balance1) = open_position(&mut contract);
+    let position = contract.pools[0].positions[&2].clone();
+    let amount_out = contract
+        .zap_close_position(ZapClosePositionAction {
+            pool_id: 0,
+            position_id: 2,
+            token_out: accounts(4).to_string(),
+            min_amount_out: U128(0),
+        })
+        .0;
+    // token0 is swapped in pool 1, token1 in pool 0 and then in pool 1
+    assert_eq!(balance(&contract, accounts(3), accounts(1)), balance0 - 100);
+    assert_eq!(
+        balance(&contract, accounts(3), accounts(2)),
+        balance1 - position.token1_locked.round() as u128
+    );
+    assert_eq!(balance(&contract, accounts(3), accounts(4)), 1000 + amount_out);
+    let value = (position.token0_locked + position.token1_locked / 100.0) / 2.0;
+    assert!(amount_out as f64 > value * 0.98 && (amount_out as f64) < value);
+}
+
+#[test]
+#[should_panic(expected = "Zap gives less than the min amount out")]
+fn zap_close_position_below_min() {
+    let (_, mut contract) = setup_zap();
+    open_position(&mut contract);
+    contract.zap_close_position(ZapClosePositionAction {
+        pool_id: 0,
+        position_id: 2,
+        token_out: accounts(1).to_string(),
+        min_amount_out: U128(1000),
+    });
+}
+
+#[test]
+fn zap_close_position_by_operator() {
+    let (mut context, mut contract) = setup_zap();
+    let (balance0, _) = open_position(&mut contract);
+    set_caller_with_deposit(&mut context, accounts(3), 1);
+    contract.approve_operator(
+        accounts(5),
+        vec![OperatorScope::Liquidity, OperatorScope::Swap],
+        None,
+        None,
+        None,
+        None,
+    );
+    set_caller(&mut context, accounts(5));
+    let actions = serde_json::from_value(json!([{"ZapClosePosition": {
+        "pool_id": 0,
+        "position_id": 2,
+        "token_out": accounts(1).to_string(),
+        "min_amount_out": U128(150),
+    }}]))
+    .unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+    // the result is credited to the owner of the position
+    assert!(balance(&contract, accounts(3), accounts(1)) > balance0 + 50);
+}
+
+#[test]
+#[should_panic(expected = "Operator is not approved for the action")]
+fn zap_close_position_by_operator_without_swap() {
+    let (mut context, mut contract) = setup_zap();
+    open_position(&mut contract);
+    set_caller_with_deposit(&mut context, accounts(3), 1);
+    contract.approve_operator(accounts(5), vec![OperatorScope::Liquidity], None, None, None, None);
+    set_caller(&mut context, accounts(5));
+    let actions = serde_json::from_value(json!([{"ZapClosePosition": {
+        "pool_id": 0,
+        "position_id": 2,
+        "token_out": accounts(1).to_string(),
+        "min_amount_out": U128(150),
+    }}]))
+    .unwrap();
+    contract.execute_for(accounts(3).to_string(), actions);
+}
+
+#[test]
+#[should_panic(expected = "Not enough liquidity in pool to cover this swap")]
+fn zap_close_position_without_liquidity_on_route() {
+    let (mut context, mut contract) = setup_zap();
+    // pool 2 of accounts(1) and accounts(5) without liquidity
+    set_caller(&mut context, accounts(0));
+    contract.create_pool(
+        accounts(1).to_string(),
+        accounts(5).to_string(),
+        1.0,
+        0,
+        0,
+    );
+    set_caller(&mut context, accounts(3));
+    open_position(&mut contract);
+    contract.zap_close_position(ZapClosePositionAction {
+        pool_id: 0,
+        position_id: 2,
+        token_out: accounts(5).to_string(),
+        min_amount_out: U128(0),
+    });
+}